@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+
+use serde_derive::Deserialize;
+
+use crate::{utils::{Arr2d, parallel_map}, settings::{GenerationOptions, BiomeOptions, WaterOptions}, water::WaterMaps, rivers::{Grid, NEIGHBOURS}};
+
+
+// au dela de MOISTURE_DISTANCE_CUTOFF * moisture_distance, l'eau n'apporte plus d'humidite (moins de 2%)
+const MOISTURE_DISTANCE_CUTOFF: f32 = 4.0;
+
+
+// temperature, humidite, biome principal et couleur d'un point
+type ClassifiedPoint = (f32, f32, u8, [f32; 3]);
+
+
+// ligne du tableau des biomes: intervalles de temperature (degres) et d'humidite (0 a 1), couleur du sol (0 a 255)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Biome {
+    pub name: String,
+    pub temperature: [f32; 2],
+    pub moisture: [f32; 2],
+    pub color: [f32; 3]
+}
+
+impl Biome {
+    fn new(name: &str, temperature: [f32; 2], moisture: [f32; 2], color: [f32; 3]) -> Biome {
+        Biome { name: name.to_string(), temperature, moisture, color }
+    }
+
+    // distance au rectangle du biome, en largeurs de melange: 0 a l'interieur
+    fn distance(&self, temperature: f32, moisture: f32, biome_settings: &BiomeOptions) -> f32 {
+        let dt = f32::max(self.temperature[0] - temperature, temperature - self.temperature[1]).max(0.0);
+        let dm = f32::max(self.moisture[0] - moisture, moisture - self.moisture[1]).max(0.0);
+        f32::max(dt / biome_settings.temperature_blend, dm / biome_settings.moisture_blend)
+    }
+}
+
+
+// tableau de Whittaker simplifie
+pub fn default_biome_table() -> Vec<Biome> {
+    vec![
+        Biome::new("ice", [-100.0, -5.0], [0.0, 1.0], [240.0, 240.0, 245.0]),
+        Biome::new("tundra", [-5.0, 2.0], [0.0, 1.0], [150.0, 148.0, 125.0]),
+        Biome::new("cold_steppe", [2.0, 18.0], [0.0, 0.35], [165.0, 160.0, 110.0]),
+        Biome::new("taiga", [2.0, 8.0], [0.35, 1.0], [70.0, 95.0, 70.0]),
+        Biome::new("temperate_forest", [8.0, 18.0], [0.35, 1.0], [85.0, 120.0, 65.0]),
+        Biome::new("desert", [18.0, 100.0], [0.0, 0.25], [215.0, 190.0, 140.0]),
+        Biome::new("savanna", [18.0, 100.0], [0.25, 0.5], [175.0, 165.0, 90.0]),
+        Biome::new("tropical_forest", [18.0, 100.0], [0.5, 1.0], [50.0, 100.0, 45.0])
+    ]
+}
+
+
+// cartes de meme taille que la heightmap
+pub struct BiomeMaps {
+    pub temperature: Arr2d<f32>,
+    pub moisture: Arr2d<f32>,
+    // indice dans le tableau des biomes (names) du biome de plus grand poids
+    pub id: Arr2d<u8>,
+    pub names: Vec<String>,
+    // melange des couleurs des biomes voisins dans le tableau, entre 0 et 1
+    pub colors: Arr2d<[f32; 3]>
+}
+
+impl BiomeMaps {
+    pub fn named_maps(&self) -> Vec<(&'static str, &Arr2d<f32>)> {
+        vec![("temperaturemap.png", &self.temperature), ("moisturemap.png", &self.moisture)]
+    }
+
+    pub fn extract(&self, x: usize, y: usize, width: usize, height: usize) -> BiomeMaps {
+        BiomeMaps {
+            temperature: self.temperature.extract(x, y, width, height),
+            moisture: self.moisture.extract(x, y, width, height),
+            id: self.id.extract(x, y, width, height),
+            names: self.names.clone(),
+            colors: self.colors.extract(x, y, width, height)
+        }
+    }
+}
+
+
+// classe chaque point de heightmap, dont la premiere ligne est a la ligne origin_y du monde
+// temperature: equator_temperature, moins latitude_gradient par degre de latitude et lapse_rate par unite de hauteur au dessus de la mer
+// humidite: base_moisture, plus water_moisture pres de l'eau (mer, lacs, rivieres) et wetness_moisture la ou l'erosion a fait passer
+// beaucoup d'eau (wetness, a la resolution du mesh, rapportee a wetness_scale)
+#[allow(clippy::too_many_arguments)]
+pub fn generate_biomes(heightmap: &Arr2d<f32>, origin_y: i64, water: Option<&WaterMaps>, river_mask: Option<&Arr2d<f32>>, wetness: Option<&Arr2d<f32>>,
+    wetness_scale: f32, tileable: bool, settings: &GenerationOptions, biome_settings: &BiomeOptions, water_settings: &WaterOptions) -> BiomeMaps {
+
+    println!("classifying biomes...");
+
+    let (width, height) = (heightmap.get_width(), heightmap.get_height());
+    let (_, terrain_h) = settings.get_terrain_size();
+
+    // distance a l'eau la plus proche, en points
+    let grid = Grid::new(heightmap, tileable);
+    let max_distance = (biome_settings.moisture_distance * MOISTURE_DISTANCE_CUTOFF).ceil() as u32;
+    let mut distance = vec![u32::MAX; grid.len()];
+    let is_water = |x: usize, y: usize| water.is_some_and(|water| *water.mask.get(x, y).unwrap() > 0.0)
+        || river_mask.is_some_and(|mask| *mask.get(x, y).unwrap() > 0.0);
+
+    let mut queue: VecDeque<usize> = (0..grid.len()).filter(|index| is_water(index % grid.width, index / grid.width)).collect();
+    for index in queue.iter() {
+        distance[*index] = 0;
+    }
+    while let Some(index) = queue.pop_front() {
+        if distance[index] >= max_distance {
+            continue
+        }
+        for direction in NEIGHBOURS {
+            if let Some(neighbour) = grid.neighbour(index, direction) {
+                if distance[neighbour] == u32::MAX {
+                    distance[neighbour] = distance[index] + 1;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    let wetness = wetness.map(|wetness| wetness.resample(width, height));
+    let sea_level = water_settings.sea_level.unwrap_or(0.0);
+
+    // chaque colonne (x constant) est classee en parallele
+    let columns: Vec<Vec<ClassifiedPoint>> = parallel_map(width, settings.get_threads(), |x| {
+        (0..height).map(|y| {
+            // un terrain qui se repete va de latitude_top a latitude_bottom en son milieu puis revient a latitude_top
+            let mut t = (origin_y + y as i64) as f32 / (terrain_h - 1) as f32;
+            if tileable {
+                t = 1.0 - (2.0 * t - 1.0).abs();
+            }
+            let latitude = (biome_settings.latitude_top + t * (biome_settings.latitude_bottom - biome_settings.latitude_top)).clamp(-90.0, 90.0);
+            let altitude = (*heightmap.get(x, y).unwrap() - sea_level).max(0.0);
+            let temperature = biome_settings.equator_temperature - biome_settings.latitude_gradient * latitude.abs() - biome_settings.lapse_rate * altitude;
+
+            let (grid_x, grid_y) = if tileable { (x % grid.width, y % grid.height) } else { (x, y) };
+            let water_distance = distance[grid_y * grid.width + grid_x];
+            let mut moisture = biome_settings.base_moisture;
+            if water_distance != u32::MAX {
+                moisture += biome_settings.water_moisture * (-(water_distance as f32) / biome_settings.moisture_distance).exp();
+            }
+            if let Some(wetness) = &wetness {
+                moisture += biome_settings.wetness_moisture * (1.0 - (-*wetness.get(x, y).unwrap() / wetness_scale).exp());
+            }
+            let moisture = moisture.clamp(0.0, 1.0);
+
+            let (id, color) = classify(temperature, moisture, biome_settings);
+            (temperature, moisture, id, color)
+        }).collect()
+    });
+
+    let mut temperature: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut moisture: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut id: Arr2d<u8> = Arr2d::from_vec(vec![0; width * height], width, height);
+    let mut colors: Arr2d<[f32; 3]> = Arr2d::from_vec(vec![[0.0; 3]; width * height], width, height);
+
+    for (x, column) in columns.into_iter().enumerate() {
+        for (y, (t, m, i, c)) in column.into_iter().enumerate() {
+            *temperature.get_mut(x, y).unwrap() = t;
+            *moisture.get_mut(x, y).unwrap() = m;
+            *id.get_mut(x, y).unwrap() = i;
+            *colors.get_mut(x, y).unwrap() = c;
+        }
+    }
+
+    let names = biome_settings.table.iter().map(|biome| biome.name.clone()).collect();
+
+    BiomeMaps { temperature, moisture, id, names, colors }
+}
+
+
+// biome de plus grand poids et couleur melangee: chaque biome a un poids de 1 dans son rectangle, qui decroit jusqu'a 0
+// a une largeur de melange (temperature_blend, moisture_blend) de celui-ci; le biome le plus proche si aucun n'a de poids
+fn classify(temperature: f32, moisture: f32, biome_settings: &BiomeOptions) -> (u8, [f32; 3]) {
+    let table = &biome_settings.table;
+    let distances: Vec<f32> = table.iter().map(|biome| biome.distance(temperature, moisture, biome_settings)).collect();
+
+    let mut weights: Vec<f32> = distances.iter().map(|distance| (1.0 - distance).max(0.0)).collect();
+    let total: f32 = weights.iter().sum();
+
+    let nearest = (0..table.len()).min_by(|a, b| distances[*a].total_cmp(&distances[*b])).unwrap();
+    if total <= 0.0 {
+        weights = vec![0.0; table.len()];
+        weights[nearest] = 1.0;
+    }
+    let total: f32 = weights.iter().sum();
+
+    let main = (0..table.len()).max_by(|a, b| weights[*a].total_cmp(&weights[*b]).then(b.cmp(a))).unwrap();
+
+    let mut color = [0.0; 3];
+    for (biome, weight) in table.iter().zip(weights.iter()) {
+        for (channel, biome_channel) in color.iter_mut().zip(biome.color) {
+            *channel += biome_channel / 255.0 * weight / total;
+        }
+    }
+
+    (main as u8, color)
+}
+
+
+// wetness pour laquelle l'humidite apportee par l'erosion atteint ~63% de wetness_moisture: sa moyenne sur la carte
+pub fn wetness_scale(wetness: Option<&Arr2d<f32>>) -> f32 {
+    let mean = wetness.map(|wetness| wetness.get_vec().iter().sum::<f32>() / wetness.get_length() as f32).unwrap_or(0.0);
+    if mean > 0.0 { mean } else { 1.0 }
+}
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+
+pub const USAGE: &str = "\
+usage: mapgeneration [options] <command>
+
+commands:
+    generate    run every stage enabled in [launch_options]
+    sky         generate the sky heightmap and texture
+    terrain     generate the terrain heightmap
+    texture     generate the terrain heightmap and texture
+    chunk <cx> <cy>
+                generate the terrain chunk at (cx, cy) of an infinite world, its borders match the
+                neighbouring chunks whatever the order in which they are generated
+    display     launch the displayer on the output directory
+    repl        interactive mode (generate, generate -c, display, quit)
+    help        print this message
+
+options:
+    --settings <path>       settings file (default: Settings)
+    --out-dir <path>        directory where the images are written (default: .)
+    --<field> <value>       override a field of the settings file, e.g. --seed 42, --sun-angle 30,
+                            --generate-sky-texture false, --erosion.radius 4";
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Generate,
+    Sky,
+    Terrain,
+    Texture,
+    Chunk(i32, i32),
+    Display,
+    Repl,
+    Help
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "generate" => Some(Command::Generate),
+            "sky" => Some(Command::Sky),
+            "terrain" => Some(Command::Terrain),
+            "texture" => Some(Command::Texture),
+            "chunk" => Some(Command::Chunk(0, 0)),
+            "display" => Some(Command::Display),
+            "repl" => Some(Command::Repl),
+            "help" => Some(Command::Help),
+            _ => None
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Cli {
+    pub command: Command,
+    pub settings_path: PathBuf,
+    pub out_dir: PathBuf,
+
+    // champs du fichier de parametres remplaces en ligne de commande
+    pub overrides: Vec<(String, String)>
+}
+
+
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Cli, String> {
+    let mut command: Option<Command> = None;
+    let mut settings_path = PathBuf::from("Settings");
+    let mut out_dir = PathBuf::from(".");
+    let mut overrides: Vec<(String, String)> = vec![];
+
+    let mut chunk_coordinates: Vec<i32> = vec![];
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            command = Some(Command::Help);
+            continue
+        }
+
+        if let Some(flag) = arg.strip_prefix("--") {
+            // accepte "--name value" et "--name=value"
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => {
+                    let value = args.next().ok_or_else(|| format!("missing value for \"--{}\"", flag))?;
+                    (flag.to_string(), value)
+                }
+            };
+
+            match name.as_str() {
+                "settings" => settings_path = PathBuf::from(value),
+                "out-dir" => out_dir = PathBuf::from(value),
+                _ => overrides.push((name.replace('-', "_"), value))
+            }
+
+        } else if command.is_none() {
+            command = Some(Command::from_name(&arg).ok_or_else(|| format!("unknown command: \"{}\"", arg))?);
+        } else if matches!(command, Some(Command::Chunk(..))) && chunk_coordinates.len() < 2 {
+            chunk_coordinates.push(arg.parse().map_err(|_| format!("invalid chunk coordinate: \"{}\"", arg))?);
+        } else {
+            return Err(format!("unexpected argument: \"{}\"", arg))
+        }
+    }
+
+    let command = match command {
+        Some(Command::Chunk(..)) => match chunk_coordinates[..] {
+            [cx, cy] => Command::Chunk(cx, cy),
+            _ => return Err("\"chunk\" expects two coordinates".to_string())
+        },
+        command => command.unwrap_or(Command::Help)
+    };
+
+    Ok(Cli { command, settings_path, out_dir, overrides })
+}
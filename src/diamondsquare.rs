@@ -2,7 +2,9 @@
 use rand_pcg::Mcg128Xsl64;
 use rand_core::RngCore;
 
-use crate::{utils::{Arr2d, TWO_POW_15_F32, Vec2, ReducedArrayWrapper, ColorMapArray}, erosion::erode, settings::{GenerationOptions}};
+use crate::{utils::{Arr2d, TWO_POW_15_F32, Vec2, ColorMapArray, derive_rng, rand, line_rng, parallel_map,
+    CHUNK_CORNER_STREAM, CHUNK_HORIZONTAL_EDGE_STREAM, CHUNK_VERTICAL_EDGE_STREAM}, erosion::{erosion_levels, erode_level, ErosionMaps}, hardness::Hardness,
+    settings::{GenerationOptions, ErosionOptions}};
 
 
 
@@ -11,7 +13,7 @@ pub fn generate_noise(w: usize, id: usize, h: f32, max_height: f32, rng: &mut Mc
 
     let n = (rng.next_u32() >> 16) as f32 - TWO_POW_15_F32;
 
-    let noise = (n as f32) / TWO_POW_15_F32;
+    let noise = n / TWO_POW_15_F32;
     
     noise * get_multiplier(w, id, h, max_height)    
 
@@ -24,16 +26,18 @@ pub fn get_multiplier(w: usize, id: usize, h: f32, max_height: f32) -> f32 {
 }
 
 
-pub fn diamond_square_2(arr: &Arr2d<f32>, output: &mut Arr2d<f32>, power_of_two: usize, reduced_output: &mut Arr2d<f32>, scaling: usize, mut h: f32,
-    n_iteration_difference: u32, rng: &mut Mcg128Xsl64, color_map: &mut ColorMapArray, settings: &GenerationOptions) {
+// output doit etre carre, de taille 2^power_of_two + 1
+#[allow(clippy::too_many_arguments)]
+pub fn diamond_square_2(arr: &Arr2d<f32>, output: &mut Arr2d<f32>, power_of_two: usize, scaling: usize, h: f32,
+    n_iteration_difference: u32, rng: &mut Mcg128Xsl64, color_map: &mut ColorMapArray, erosion_maps: &mut ErosionMaps, hardness: &Hardness,
+    settings: &GenerationOptions, erosion_settings: &ErosionOptions) {
 
     assert_eq!(arr.get_height(), arr.get_width());
     assert_eq!(output.get_height(), output.get_width());
+    assert_eq!(output.get_width(), 2_usize.pow(power_of_two as u32) + 1);
 
     let input_w = arr.get_height();  // taille du tableau d'entree (template)
 
-    let w = output.get_height();  // taille du tableau de sortie
-    
     // rempli le tableau de sortie avec les valeurs de la template
     for x in 0..input_w {
         for y in 0..input_w {
@@ -46,93 +50,292 @@ pub fn diamond_square_2(arr: &Arr2d<f32>, output: &mut Arr2d<f32>, power_of_two:
         }
     }
 
+    if settings.tileable {
+        // les bords opposes representent les memes points
+        output.wrap_edges();
+    }
+
     println!("{}", output.get_width());
 
-    let mut number_of_step_to_skip = settings.template_power_of_two; // nombre d'etape a sauter pour ne pas perdre les donnees de la template
+    // nombre d'etape a sauter pour ne pas perdre les donnees de la template
+    diamond_square_steps(output, settings.template_power_of_two, h, n_iteration_difference, rng, color_map, erosion_maps, hardness, settings, erosion_settings);
+}
 
 
-    let mut i = w - 1;  // 2^n
+// etapes diamond et square sur output (carre de 2^n + 1) dont les points de depart sont deja places
+// hardness: durete de la roche aux points de output, utilisee par l'erosion
+#[allow(clippy::too_many_arguments)]
+pub fn diamond_square_steps(output: &mut Arr2d<f32>, mut number_of_step_to_skip: u32, mut h: f32, n_iteration_difference: u32,
+    rng: &mut Mcg128Xsl64, color_map: &mut ColorMapArray, erosion_maps: &mut ErosionMaps, hardness: &Hardness,
+    settings: &GenerationOptions, erosion_settings: &ErosionOptions) {
 
-    let reduced_output_step = 2_usize.pow(n_iteration_difference);  // distance entre les valeurs du tableau reduit
+    let w = output.get_height();  // taille du tableau de sortie
 
-    let mut id: usize;  // 2^(n-1) 
-    let mut offset: usize;  // variable utilitaire pour l'etape square
+    let mut i = w - 1;  // 2^n
 
-    let mut sum: f32;
-    let mut n: usize;
+    // distance entre les valeurs erodees a chaque niveau, la premiere erosion se fait au niveau du mesh
+    let levels = erosion_levels(n_iteration_difference, erosion_settings);
 
 
     while i > 1 {
 
-        id = i / 2;
-
         if number_of_step_to_skip == 0 {
-            // diamond step
-            for x in (id..w).step_by(i) {
-                for y in (id..w).step_by(i) {
-
-                    let center_value = (
-                        output.get(x - id, y - id).unwrap()   // équivaut à output[x - id, y - id] en python
-                        + output.get(x - id, y + id).unwrap()
-                        + output.get(x + id, y + id).unwrap()
-                        + output.get(x + id, y - id).unwrap()
-                    ) / 4.0;
-
-                    // équivaut à "output[x, y] = center_value + generate_noise(w, id, h, settings.max_terrain_height, rng)" en python
-                    *(output.get_mut(x, y).unwrap()) = center_value + generate_noise(w, id, h, settings.max_terrain_height, rng);
-                }
-            }
+            diamond_square_step(output, i, h, false, rng, settings);
+        }
+
+        else {
+            number_of_step_to_skip -= 1;
+        }
 
-            // square step
-            offset = 0;
-            for x in (0..w).step_by(id) {
-                offset = if offset == 0 {id} else {0};
+        i /= 2;
+        
+        // les niveaux suivants erodent les points interpoles entre ceux deja erodes
+        if let Some(level) = levels.iter().position(|(step, _)| *step == i) {
+            erode_level(output, i, level, rng, color_map, erosion_maps, hardness, settings, &levels[level].1);
+            h = 0.0;
+        }
 
-                for y in (offset..w).step_by(i) {
-                    sum = 0.0;
-                    n = 0;
 
+    }
+
+
+    println!("generation done.");
+
+
+
+
+}
+
+
+// etape diamond puis etape square sur output (carre de 2^n + 1), les points espaces de i etant deja calcules
+// fixed_border: la premiere et la derniere ligne / colonne sont deja calculees et ne sont pas modifiees
+// les lignes (x constant) sont calculees en parallele a partir de l'etat precedent du tableau, chacune avec
+// son propre generateur: le resultat ne depend pas du nombre de threads
+fn diamond_square_step(output: &mut Arr2d<f32>, i: usize, h: f32, fixed_border: bool, rng: &mut Mcg128Xsl64, settings: &GenerationOptions) {
+    let w = output.get_height();
+    let id = i / 2;  // 2^(n-1)
+
+    let threads = settings.get_threads();
+
+    // diamond step
+    let base = rng.next_u64();  // graine des generateurs des lignes de l'etape en cours
+    let lines: Vec<usize> = (id..w).step_by(i).collect();
+    let values = {
+        let output: &Arr2d<f32> = output;
+        parallel_map(lines.len(), threads, |k| {
+            let x = lines[k];
+            let mut rng = line_rng(base, x);
+
+            (id..w).step_by(i).map(|y| {
+                let center_value = (
+                    output.get(x - id, y - id).unwrap()   // équivaut à output[x - id, y - id] en python
+                    + output.get(x - id, y + id).unwrap()
+                    + output.get(x + id, y + id).unwrap()
+                    + output.get(x + id, y - id).unwrap()
+                ) / 4.0;
+
+                (y, center_value + generate_noise(w, id, h, settings.max_terrain_height, &mut rng))
+            }).collect::<Vec<(usize, f32)>>()
+        })
+    };
+    write_lines(output, &lines, &values);
+
+    // square step
+    let base = rng.next_u64();
+    let lines: Vec<usize> = (0..w).step_by(id).collect();
+    let values = {
+        let output: &Arr2d<f32> = output;
+        parallel_map(lines.len(), threads, |k| {
+            let x = lines[k];
+            let mut rng = line_rng(base, x);
+
+            // une ligne sur deux commence au milieu d'un cote
+            let offset = match k % 2 { 0 => id, _ => 0 };
+
+            let mut line: Vec<(usize, f32)> = vec![];
+            let mut sum: f32;
+            let mut n: usize;
+
+            for y in (offset..w).step_by(i) {
+                sum = 0.0;
+                n = 0;
+
+                if fixed_border && (x == 0 || y == 0 || x == w - 1 || y == w - 1) { continue }
+
+                if settings.tileable {
+                    // les voisins sont pris de l'autre cote, la derniere ligne / colonne est recopiee apres
+                    if x == w - 1 || y == w - 1 { continue }
+
+                    sum += output.get_wrapped(x as i32 - id as i32, y as i32)
+                        + output.get_wrapped((x + id) as i32, y as i32)
+                        + output.get_wrapped(x as i32, y as i32 - id as i32)
+                        + output.get_wrapped(x as i32, (y + id) as i32);
+                    n = 4;
+                } else {
                     if x >= id {sum += output.get(x - id, y).unwrap(); n += 1}
                     if x + id < w {sum += output.get(x + id, y).unwrap(); n += 1}
                     if y >= id {sum += output.get(x, y - id).unwrap(); n += 1}
                     if y + id < w {sum += output.get(x, y + id).unwrap(); n += 1}
+                }
 
-                    *(output.get_mut(x, y).unwrap()) = sum / (n as f32) + generate_noise(w, id, h, settings.max_terrain_height, rng);
-                };
+                line.push((y, sum / (n as f32) + generate_noise(w, id, h, settings.max_terrain_height, &mut rng)));
             }
-        }
 
-        else {
-            number_of_step_to_skip -= 1;
-        }
+            line
+        })
+    };
+    write_lines(output, &lines, &values);
 
-        i = id;
-        
-        if i == reduced_output_step {
-            erode(&mut ReducedArrayWrapper::new(output, power_of_two as u32, power_of_two as u32 - n_iteration_difference), rng, color_map, settings);
-            h = 0.0;
+    if settings.tileable {
+        output.wrap_edges();
+    }
+}
+
+
+// ecrit les valeurs (y, valeur) calculees pour chaque ligne x
+fn write_lines(output: &mut Arr2d<f32>, lines: &[usize], values: &[Vec<(usize, f32)>]) {
+    for (x, line) in lines.iter().zip(values) {
+        for (y, value) in line {
+            *output.get_mut(*x, *y).unwrap() = *value;
         }
+    }
+}
+
 
+// diamond-square d'un chunk de (2^n + 1)^2, le chunk (cx, cy) commence au point (cx * 2^n, cy * 2^n) du monde
+// les coins et les bords ne dependent que de la seed et de leur position: deux chunks voisins calculent
+// exactement le meme bord commun, quel que soit l'ordre de generation
+// seuls les points espaces du pas du mesh sont calcules: ils sont erodes avec ceux des chunks voisins
+// (voir TerrainPipeline::generate_chunk), puis les points intermediaires sont interpoles par interpolate_chunk
+pub fn diamond_square_chunk(output: &mut Arr2d<f32>, cx: i32, cy: i32, n_iteration_difference: u32, rng: &mut Mcg128Xsl64,
+    settings: &GenerationOptions) {
 
+    assert_eq!(output.get_height(), output.get_width());
+
+    let w = output.get_width();
+    let h = settings.irregularity;
+    let reduced_output_step = 2_usize.pow(n_iteration_difference);
+
+    let corner = |x: i32, y: i32| {
+        let mut corner_rng = derive_rng(settings.seed, &[CHUNK_CORNER_STREAM, x as i64, y as i64]);
+        rand(&mut corner_rng) * settings.max_terrain_height
+    };
+
+    let edge = |stream: i64, x: i32, y: i32, first: f32, last: f32| {
+        let mut edge_rng = derive_rng(settings.seed, &[stream, x as i64, y as i64]);
+        generate_chunk_edge(first, last, w, h, reduced_output_step, &mut edge_rng, settings)
+    };
+
+    let (top_left, top_right) = (corner(cx, cy), corner(cx + 1, cy));
+    let (bottom_left, bottom_right) = (corner(cx, cy + 1), corner(cx + 1, cy + 1));
+
+    let top = edge(CHUNK_HORIZONTAL_EDGE_STREAM, cx, cy, top_left, top_right);
+    let bottom = edge(CHUNK_HORIZONTAL_EDGE_STREAM, cx, cy + 1, bottom_left, bottom_right);
+    let left = edge(CHUNK_VERTICAL_EDGE_STREAM, cx, cy, top_left, bottom_left);
+    let right = edge(CHUNK_VERTICAL_EDGE_STREAM, cx + 1, cy, top_right, bottom_right);
+
+    for k in 0..w {
+        *output.get_mut(k, 0).unwrap() = top[k];
+        *output.get_mut(k, w - 1).unwrap() = bottom[k];
+        *output.get_mut(0, k).unwrap() = left[k];
+        *output.get_mut(w - 1, k).unwrap() = right[k];
     }
 
-    for (rx, x) in (0..w).step_by(reduced_output_step).enumerate() {
-        for (ry, y) in (0..w).step_by(reduced_output_step).enumerate() {
-            *reduced_output.get_mut(rx, ry).unwrap() = *output.get(x, y).unwrap();
+    let mut i = w - 1;
+    while i > reduced_output_step {
+        diamond_square_step(output, i, h, true, rng, settings);
+        i /= 2;
+    }
+}
+
+
+// deplacement du point milieu sur un segment de w points, avec le meme bruit que le diamond-square,
+// jusqu'aux points espaces de reduced_output_step
+fn generate_chunk_edge(first: f32, last: f32, w: usize, h: f32, reduced_output_step: usize, rng: &mut Mcg128Xsl64,
+    settings: &GenerationOptions) -> Vec<f32> {
+
+    let mut edge = vec![0.0; w];
+    edge[0] = first;
+    edge[w - 1] = last;
+
+    let mut i = w - 1;
+    let mut id: usize;
+
+    while i > reduced_output_step {
+        id = i / 2;
+
+        for x in (id..w).step_by(i) {
+            edge[x] = (edge[x - id] + edge[x + id]) / 2.0 + generate_noise(w, id, h, settings.max_terrain_height, rng);
         }
+
+        i = id;
     }
 
+    edge
+}
 
-    println!("generation done.");
 
+// etapes diamond et square sans bruit d'un chunk dont les points espaces de from_step sont calcules, jusqu'aux points
+// espaces de to_step: comme le diamond-square apres l'erosion, mais les bords sont interpoles le long du bord, sans
+// les points interieurs, pour que deux chunks voisins calculent le meme bord commun
+pub fn interpolate_chunk(output: &mut Arr2d<f32>, from_step: usize, to_step: usize) {
+    let w = output.get_width();
+    let h = output.get_height();
 
+    let mut i = from_step;
+    let mut id: usize;
 
+    while i > to_step {
+        id = i / 2;
+
+        for k in (id..w).step_by(i) {
+            for y in [0, h - 1] {
+                *output.get_mut(k, y).unwrap() = (output.get(k - id, y).unwrap() + output.get(k + id, y).unwrap()) / 2.0;
+            }
+        }
+        for k in (id..h).step_by(i) {
+            for x in [0, w - 1] {
+                *output.get_mut(x, k).unwrap() = (output.get(x, k - id).unwrap() + output.get(x, k + id).unwrap()) / 2.0;
+            }
+        }
+
+        // diamond step
+        for x in (id..w).step_by(i) {
+            for y in (id..h).step_by(i) {
+                *output.get_mut(x, y).unwrap() = (output.get(x - id, y - id).unwrap() + output.get(x - id, y + id).unwrap()
+                    + output.get(x + id, y + id).unwrap() + output.get(x + id, y - id).unwrap()) / 4.0;
+            }
+        }
+
+        // square step, sur les points interieurs
+        for x in (id..w - 1).step_by(id) {
+            let offset = if x % i == 0 { id } else { i };
+            for y in (offset..h - 1).step_by(i) {
+                *output.get_mut(x, y).unwrap() = (output.get(x - id, y).unwrap() + output.get(x + id, y).unwrap()
+                    + output.get(x, y - id).unwrap() + output.get(x, y + id).unwrap()) / 4.0;
+            }
+        }
 
+        i = id;
+    }
 }
 
 
 
 
+// plus petite puissance de 2 telle que le diamond-square couvre width x height, tout en restant compatible
+// avec la template et la resolution de l'erosion
+pub fn get_enclosing_power_of_two(width: usize, height: usize, settings: &GenerationOptions) -> usize {
+    let mut n: u32 = u32::max(settings.template_power_of_two, settings.terrain_power_of_two - settings.mesh_power_of_two);
+
+    while 2_usize.pow(n) + 1 < usize::max(width, height) {
+        n += 1;
+    }
+
+    n as usize
+}
+
+
 pub fn generate_demisphere_heightmap(arr: &mut Arr2d<f32>, w: usize) {
     let radius = w / 2 - 1;
     let radius_squared = radius.pow(2) as f32;
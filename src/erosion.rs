@@ -1,13 +1,14 @@
-use std::convert::TryInto;
-
+use rand_core::RngCore;
 use rand_pcg::Mcg128Xsl64;
+use serde_derive::Deserialize;
 
-use crate::{utils::{ReducedArrayWrapper, ColorMapArray, next_random_number, Vec2}, settings::{GenerationOptions}};
+use crate::{pipe_erosion::pipe_erode, hardness::Hardness, utils::{ReducedArrayWrapper, ColorMapArray, Arr2d, next_random_number, Vec2, derive_rng, parallel_map, EROSION_BATCH_STREAM}, settings::{GenerationOptions, ErosionOptions}};
 
 
 
 
 // calcul l'altitude de la goutte d'eau et la pente sur l'axe des x et des y
+#[allow(clippy::too_many_arguments)]
 pub fn compute_height_and_slopes(heightmap: &ReducedArrayWrapper<f32>, offset_x: f64, offset_y: f64, arr_pos_x: usize, arr_pos_y: usize, height: &mut f64, hslope: &mut f64, vslope: &mut f64) {
 
     let height_00 = *heightmap.get(arr_pos_x, arr_pos_y).unwrap() as f64;
@@ -39,16 +40,18 @@ pub struct Point {
 }
 
 pub struct PointsInRangeIterator<'a> {
-    relative_points_table: &'a Vec<RelativePoint>,
+    relative_points_table: &'a [RelativePoint],
     height_map_width: usize,
+    height_map_height: usize,
+    tileable: bool,  // les points hors du tableau reviennent de l'autre cote au lieu d'etre ignores
     x: i32,
     y: i32,
     index: usize
 }
 
 impl<'a> PointsInRangeIterator<'a> {
-    pub fn new(relative_points_table: &'a Vec<RelativePoint>, height_map_width: usize, x: i32, y: i32) -> PointsInRangeIterator {
-        PointsInRangeIterator { relative_points_table, height_map_width, x, y, index: 0 }
+    pub fn new(relative_points_table: &'a [RelativePoint], height_map_width: usize, height_map_height: usize, tileable: bool, x: i32, y: i32) -> PointsInRangeIterator<'a> {
+        PointsInRangeIterator { relative_points_table, height_map_width, height_map_height, tileable, x, y, index: 0 }
     }
 }
 
@@ -62,14 +65,19 @@ impl<'a> Iterator for PointsInRangeIterator<'a> {
         let mut y: i32;
 
         let width = self.height_map_width as i32;
+        let height = self.height_map_height as i32;
 
         let mut relative_point: &RelativePoint = self.relative_points_table.get(self.index)?;
 
         x = self.x + relative_point.dx;
         y = self.y + relative_point.dy;
 
+        if self.tileable {
+            self.index += 1;
+            return Some(Point {x: x.rem_euclid(width - 1) as usize, y: y.rem_euclid(height - 1) as usize, weight: relative_point.weight})
+        }
 
-        while !(0 <= x && x < width && 0 <= y && y < width) {
+        while !(0 <= x && x < width && 0 <= y && y < height) {
             self.index += 1;
 
             relative_point = self.relative_points_table.get(self.index)?;
@@ -113,14 +121,285 @@ pub fn compute_points_in_range(relative_points_table: &mut Vec<RelativePoint>, r
 
 
 
-pub fn erode(heightmap: &mut ReducedArrayWrapper<f32>, rng: &mut Mcg128Xsl64, _color_map: &mut ColorMapArray, settings: &GenerationOptions) {
+// moment ou l'erosion thermique est appliquee par rapport a l'erosion par les gouttes d'eau
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThermalErosionOrder {
+    Before,
+    #[default]
+    After
+}
 
-    println!("starting erosion, number of iterations: {}", settings.number_of_erosion_iterations);
 
-    let initial_lifetime: usize = settings.initial_lifetime as usize;
-    
-    let mut pos_x: f64;
-    let mut pos_y: f64;
+// modele utilise pour l'erosion par l'eau
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HydraulicErosionModel {
+    #[default]
+    Droplets,  // gouttes d'eau simulees une par une: ravines
+    Pipes  // ecoulement sur toute la grille, voir pipe_erode: reseaux de rivieres et eau stagnante
+}
+
+
+// cartes produites par l'erosion, a la resolution a laquelle elle travaille (points du tableau reduit)
+#[derive(Default, Clone)]
+pub struct ErosionMaps {
+    // materiau depose par l'erosion thermique, seulement si ErosionOptions::export_debris_map
+    pub debris: Option<Arr2d<f32>>,
+
+    // hauteur d'eau, vitesse de l'eau et sediment transporte a la fin de l'erosion par tuyaux
+    pub water: Option<Arr2d<f32>>,
+    pub velocity: Option<Arr2d<f32>>,
+    pub sediment: Option<Arr2d<f32>>,
+
+    // passage de l'eau (nombre de gouttes ou volume ecoule), quantites totales erodees et deposees,
+    // et presence d'eau (eau transportee par les gouttes ou hauteur d'eau finale)
+    pub flow: Option<Arr2d<f32>>,
+    pub eroded: Option<Arr2d<f32>>,
+    pub deposited: Option<Arr2d<f32>>,
+    pub wetness: Option<Arr2d<f32>>,
+
+    // durete de la roche a la fin de l'erosion, si elle n'est pas uniforme (ErosionOptions::hardness_source)
+    pub hardness: Option<Arr2d<f32>>
+}
+
+impl ErosionMaps {
+    // cartes calculees, avec le nom de l'image exportee
+    pub fn named_maps(&self) -> Vec<(&'static str, &Arr2d<f32>)> {
+        vec![("debrismap.png", &self.debris), ("watermap.png", &self.water), ("velocitymap.png", &self.velocity), ("sedimentmap.png", &self.sediment),
+            ("flowmap.png", &self.flow), ("erodedmap.png", &self.eroded), ("depositedmap.png", &self.deposited), ("wetnessmap.png", &self.wetness),
+            ("hardnessmap.png", &self.hardness)]
+            .into_iter()
+            .filter_map(|(name, map)| map.as_ref().map(|map| (name, map)))
+            .collect()
+    }
+
+    pub fn maps_mut(&mut self) -> Vec<&mut Arr2d<f32>> {
+        vec![&mut self.debris, &mut self.water, &mut self.velocity, &mut self.sediment,
+            &mut self.flow, &mut self.eroded, &mut self.deposited, &mut self.wetness, &mut self.hardness].into_iter().filter_map(|map| map.as_mut()).collect()
+    }
+
+    // applique f a chaque carte calculee (recadrage, changement de resolution)
+    pub fn map<F: Fn(&Arr2d<f32>) -> Arr2d<f32>>(&self, f: F) -> ErosionMaps {
+        ErosionMaps {
+            debris: self.debris.as_ref().map(&f),
+            water: self.water.as_ref().map(&f),
+            velocity: self.velocity.as_ref().map(&f),
+            sediment: self.sediment.as_ref().map(&f),
+            flow: self.flow.as_ref().map(&f),
+            eroded: self.eroded.as_ref().map(&f),
+            deposited: self.deposited.as_ref().map(&f),
+            wetness: self.wetness.as_ref().map(&f),
+            hardness: self.hardness.as_ref().map(&f)
+        }
+    }
+
+    // ajoute les cartes d'un niveau plus fin, ramenees a la resolution de ces cartes: les quantites (passage, materiau
+    // erode, depose, deplace) s'additionnent, l'etat de l'eau reste celui du premier niveau
+    pub fn add_level(&mut self, level_maps: &ErosionMaps) {
+        for (map, level_map) in [(&mut self.debris, &level_maps.debris), (&mut self.flow, &level_maps.flow),
+            (&mut self.eroded, &level_maps.eroded), (&mut self.deposited, &level_maps.deposited)] {
+
+            if let (Some(map), Some(level_map)) = (map.as_mut(), level_map) {
+                let level_map = level_map.resample(map.get_width(), map.get_height());
+                for y in 0..map.get_height() {
+                    for x in 0..map.get_width() {
+                        *map.get_mut(x, y).unwrap() += *level_map.get(x, y).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+// hardness: durete aux points du tableau complet, ramenee aux points du tableau reduit
+pub fn erode(heightmap: &mut ReducedArrayWrapper<f32>, rng: &mut Mcg128Xsl64, _color_map: &mut ColorMapArray, erosion_maps: &mut ErosionMaps, hardness: &Hardness,
+    settings: &GenerationOptions, erosion_settings: &ErosionOptions) {
+
+    erode_with(heightmap, DropletStarts::Random(rng), erosion_maps, hardness, settings, erosion_settings);
+}
+
+
+// erosion d'une zone dont les points de depart des gouttes sont donnes, en points du tableau reduit (gouttes simulees
+// dans l'ordre, sans erosion parallele): utilise pour les chunks, voir TerrainPipeline::generate_chunk
+pub fn erode_region(heightmap: &mut ReducedArrayWrapper<f32>, droplets: &[(f64, f64)], erosion_maps: &mut ErosionMaps, hardness: &Hardness,
+    settings: &GenerationOptions, erosion_settings: &ErosionOptions) {
+
+    erode_with(heightmap, DropletStarts::Given(droplets), erosion_maps, hardness, settings, erosion_settings);
+}
+
+
+// points de depart des gouttes d'eau
+enum DropletStarts<'a> {
+    Random(&'a mut Mcg128Xsl64),  // tires sur le generateur
+    Given(&'a [(f64, f64)])
+}
+
+
+fn erode_with(heightmap: &mut ReducedArrayWrapper<f32>, droplets: DropletStarts, erosion_maps: &mut ErosionMaps, hardness: &Hardness,
+    settings: &GenerationOptions, erosion_settings: &ErosionOptions) {
+
+    if erosion_settings.thermal_erosion_order == ThermalErosionOrder::Before {
+        thermal_erode(heightmap, erosion_maps, settings.get_cell_size(), settings.get_threads(), erosion_settings);
+    }
+
+    let hardness = hardness.reduced(heightmap.get_scaling());
+
+    match erosion_settings.hydraulic_erosion_model {
+        HydraulicErosionModel::Droplets => erode_droplets(heightmap, droplets, erosion_maps, &hardness, settings.get_threads(), erosion_settings),
+        HydraulicErosionModel::Pipes => {
+            // le modele des tuyaux travaille sur une copie des points du tableau reduit, espaces de scaling points de la heightmap
+            let (width, height) = (heightmap.get_reduced_width(), heightmap.get_reduced_height());
+            let mut grid: Arr2d<f32> = Arr2d::zeros(width, height);
+            for y in 0..height {
+                for x in 0..width {
+                    *grid.get_mut(x, y).unwrap() = *heightmap.get(x, y).unwrap();
+                }
+            }
+
+            pipe_erode(&mut grid, heightmap.get_scaling() as f32 * settings.get_cell_size(), heightmap.is_tileable(), erosion_maps, &hardness, settings.get_threads(), erosion_settings);
+
+            for y in 0..height {
+                for x in 0..width {
+                    *heightmap.get_mut(x, y).unwrap() = *grid.get(x, y).unwrap();
+                }
+            }
+        }
+    }
+
+    if heightmap.is_tileable() {
+        heightmap.wrap_edges();
+    }
+
+    if erosion_settings.thermal_erosion_order == ThermalErosionOrder::After {
+        thermal_erode(heightmap, erosion_maps, settings.get_cell_size(), settings.get_threads(), erosion_settings);
+    }
+}
+
+
+// erosion par les gouttes d'eau, une par une ou par lots en parallele
+fn erode_droplets(heightmap: &mut ReducedArrayWrapper<f32>, droplets: DropletStarts, erosion_maps: &mut ErosionMaps, hardness: &Hardness, threads: usize,
+    erosion_settings: &ErosionOptions) {
+
+    let number_of_droplets = match &droplets {
+        DropletStarts::Random(_) => erosion_settings.number_of_erosion_iterations as usize,
+        DropletStarts::Given(droplets) => droplets.len()
+    };
+    println!("starting erosion, number of iterations: {}", number_of_droplets);
+
+    let mut relative_points_table: Vec<RelativePoint> = Vec::with_capacity((erosion_settings.radius as usize).pow(2) * 4);
+    compute_points_in_range(&mut relative_points_table, erosion_settings.radius as i32);
+
+    let mut droplet_maps = DropletMaps::new(heightmap.get_reduced_width(), heightmap.get_reduced_height());
+
+    match droplets {
+        DropletStarts::Given(droplets) => {
+            for (iteration, (pos_x, pos_y)) in droplets.iter().enumerate() {
+                if (iteration + 1) % 50000 == 0 {
+                    println!("{} iterations done.", iteration + 1)
+                }
+
+                erode_droplet(heightmap, &mut droplet_maps, hardness, *pos_x, *pos_y, &relative_points_table, erosion_settings);
+            }
+        }
+        DropletStarts::Random(rng) if erosion_settings.parallel_erosion => {
+            erode_parallel(heightmap, rng, &mut droplet_maps, hardness, &relative_points_table, threads, erosion_settings);
+        }
+        DropletStarts::Random(rng) => {
+            let array_max_index_x = heightmap.get_reduced_width() as u32 - 1;
+            let array_max_index_y = heightmap.get_reduced_height() as u32 - 1;
+
+            let mut pos_x: f64;
+            let mut pos_y: f64;
+
+            for iteration in 0..erosion_settings.number_of_erosion_iterations {
+
+                if (iteration + 1) % 50000 == 0 {
+                    println!("{} iterations done.", iteration + 1)
+                }
+
+
+                pos_x = next_random_number(array_max_index_x as u64, rng).into();
+                pos_y = next_random_number(array_max_index_y as u64, rng).into();
+
+                erode_droplet(heightmap, &mut droplet_maps, hardness, pos_x, pos_y, &relative_points_table, erosion_settings);
+            }
+        }
+    }
+
+    if heightmap.is_tileable() {
+        droplet_maps.fold_edges();
+    }
+
+    erosion_maps.flow = Some(droplet_maps.flow);
+    erosion_maps.eroded = Some(droplet_maps.eroded);
+    erosion_maps.deposited = Some(droplet_maps.deposited);
+    erosion_maps.wetness = Some(droplet_maps.wetness);
+}
+
+
+// cartes accumulees par les gouttes, aux coordonnees du tableau reduit
+struct DropletMaps {
+    flow: Arr2d<f32>,
+    eroded: Arr2d<f32>,
+    deposited: Arr2d<f32>,
+    wetness: Arr2d<f32>
+}
+
+impl DropletMaps {
+    fn new(width: usize, height: usize) -> DropletMaps {
+        DropletMaps { flow: Arr2d::zeros(width, height), eroded: Arr2d::zeros(width, height), deposited: Arr2d::zeros(width, height), wetness: Arr2d::zeros(width, height) }
+    }
+
+    fn maps(&self) -> Vec<&Arr2d<f32>> {
+        vec![&self.flow, &self.eroded, &self.deposited, &self.wetness]
+    }
+
+    fn maps_mut(&mut self) -> Vec<&mut Arr2d<f32>> {
+        vec![&mut self.flow, &mut self.eroded, &mut self.deposited, &mut self.wetness]
+    }
+
+    // ajoute other, dont le point (0, 0) est en (x0, y0), en ramenant les positions sur la periode si elle est donnee
+    fn add(&mut self, other: &DropletMaps, x0: i32, y0: i32, period: Option<(usize, usize)>) {
+        for (map, other_map) in self.maps_mut().into_iter().zip(other.maps()) {
+            for ox in 0..other_map.get_width() {
+                for oy in 0..other_map.get_height() {
+                    let (mut x, mut y) = (ox as i32 + x0, oy as i32 + y0);
+                    if let Some((period_x, period_y)) = period {
+                        (x, y) = (x.rem_euclid(period_x as i32), y.rem_euclid(period_y as i32));
+                    }
+                    *map.get_mut(x as usize, y as usize).unwrap() += *other_map.get(ox, oy).unwrap();
+                }
+            }
+        }
+    }
+
+    // en mode tileable, la derniere ligne / colonne est confondue avec la premiere: leurs valeurs sont additionnees
+    fn fold_edges(&mut self) {
+        for map in self.maps_mut() {
+            let (last_x, last_y) = (map.get_width() - 1, map.get_height() - 1);
+            for y in 0..=last_y {
+                let v = *map.get(last_x, y).unwrap();
+                *map.get_mut(0, y).unwrap() += v;
+            }
+            for x in 0..last_x {
+                let v = *map.get(x, last_y).unwrap();
+                *map.get_mut(x, 0).unwrap() += v;
+            }
+            map.wrap_edges();
+        }
+    }
+}
+
+
+// distance maximale parcourue par une goutte a chaque etape, en points du tableau reduit
+const MAX_DROPLET_STEP: f64 = 1.0;
+
+// simule une goutte d'eau partant de (pos_x, pos_y)
+fn erode_droplet(heightmap: &mut ReducedArrayWrapper<f32>, droplet_maps: &mut DropletMaps, hardness: &Hardness, mut pos_x: f64, mut pos_y: f64, relative_points_table: &[RelativePoint], erosion_settings: &ErosionOptions) {
+
+    let initial_lifetime: usize = erosion_settings.initial_lifetime as usize;
 
     let mut arr_pos_x: usize;
     let mut arr_pos_y: usize;
@@ -133,11 +412,7 @@ pub fn erode(heightmap: &mut ReducedArrayWrapper<f32>, rng: &mut Mcg128Xsl64, _c
     let mut horizontal_slope: f64 = 0.0;
     let mut vertical_slope: f64 = 0.0;
 
-    let mut velocity: Vec2<f64>;
-
-    let mut quantity_of_water: f64;
     let mut capacity: f64;
-    let min_capacity: f64 = 0.01;
 
     let mut new_arr_pos_x: usize;
     let mut new_arr_pos_y: usize;
@@ -147,7 +422,8 @@ pub fn erode(heightmap: &mut ReducedArrayWrapper<f32>, rng: &mut Mcg128Xsl64, _c
     let mut new_height: f64 = 42.0;
     let mut height_difference: f64;
 
-    let array_max_index = heightmap.get_reduced_width() as u32 - 1;
+    let array_max_index_x = heightmap.get_reduced_width() as u32 - 1;
+    let array_max_index_y = heightmap.get_reduced_height() as u32 - 1;
 
     let mut to_depose: f64;
     let mut to_erode: f64;
@@ -155,122 +431,453 @@ pub fn erode(heightmap: &mut ReducedArrayWrapper<f32>, rng: &mut Mcg128Xsl64, _c
 
     // // let mut pixel;
 
-    let mut sediment_stocked: f64;
-
     let mut mut_height_ref: &mut f32;
 
     let mut speed = 1.0;
 
-    let mut relative_points_table: Vec<RelativePoint> = Vec::with_capacity((settings.radius.pow(2) * 4) as usize);
-    compute_points_in_range(&mut relative_points_table, settings.radius as i32);
+    let mut direction = Vec2 {x: 0.0, y: 0.0};  // direction de la goutte, normalisee a 1
+    let mut quantity_of_water = 1.0;
 
-    for iteration in 0..settings.number_of_erosion_iterations {
+    let mut sediment_stocked = 0.0;
 
-        if (iteration + 1) % 50000 == 0 {
-            println!("{} iterations done.", iteration + 1)
+    for _current_lifetime in (0..initial_lifetime).rev() {
+
+        arr_pos_x = pos_x as usize;
+        arr_pos_y = pos_y as usize;
+
+        offset_x = pos_x - arr_pos_x as f64;
+        offset_y = pos_y - arr_pos_y as f64;
+
+        compute_height_and_slopes(heightmap, offset_x, offset_y, arr_pos_x, arr_pos_y, &mut height, &mut horizontal_slope, &mut vertical_slope);
+
+        *droplet_maps.flow.get_mut(arr_pos_x, arr_pos_y).unwrap() += 1.0;
+        *droplet_maps.wetness.get_mut(arr_pos_x, arr_pos_y).unwrap() += quantity_of_water as f32;
+
+        direction.x = direction.x * erosion_settings.inertia + horizontal_slope * (1.0 - erosion_settings.inertia);
+        direction.y = direction.y * erosion_settings.inertia + vertical_slope * (1.0 - erosion_settings.inertia);
+
+        match direction.normalize_ip() {
+            Ok(()) => (),
+            Err(()) => break
         }
 
+        // la goutte avance de sa vitesse, limitee pour ne pas sauter par-dessus les creux
+        let distance = f64::min(speed, MAX_DROPLET_STEP);
+        pos_x -= direction.x * distance;
+        pos_y -= direction.y * distance;
+
+        if heightmap.is_tileable() {
+            // la goutte fait le tour du tore
+            pos_x = pos_x.rem_euclid(array_max_index_x as f64);
+            pos_y = pos_y.rem_euclid(array_max_index_y as f64);
+        } else if pos_x < 0.0 || pos_y < 0.0 || pos_x > (array_max_index_x - 1) as f64 || pos_y > (array_max_index_y - 1) as f64 { break }
+
+        new_arr_pos_x = pos_x as usize;
+        new_arr_pos_y = pos_y as usize;
 
-        pos_x = next_random_number(array_max_index as u64, rng).try_into().expect("Error while converting");
-        pos_y = next_random_number(array_max_index as u64, rng).try_into().expect("Error while converting");
+        new_offset_x = pos_x - new_arr_pos_x as f64;
+        new_offset_y = pos_y - new_arr_pos_y as f64;
+        
+        compute_height_and_slopes(heightmap, new_offset_x, new_offset_y, new_arr_pos_x, new_arr_pos_y, &mut new_height, &mut horizontal_slope, &mut vertical_slope);
 
-        velocity = Vec2 {x: 0.0, y: 0.0};
-        quantity_of_water = 1.0;
 
-        sediment_stocked = 0.0;
+        height_difference = new_height - height;
 
-        for _current_lifetime in (0..initial_lifetime).rev() {
+        to_erode = 0.0;
+        to_depose = 0.0;
 
-            arr_pos_x = pos_x as usize;
-            arr_pos_y = pos_y as usize;
+        // la goutte accelere en descendant et ralentit en montant
+        speed = f64::sqrt(f64::max(0.0, speed * speed - height_difference * erosion_settings.gravity));
 
-            offset_x = pos_x - arr_pos_x as f64;
-            offset_y = pos_y - arr_pos_y as f64;
+        if height_difference > 0.0 {
+              // si la goutte remonte une pente, dépose une partie du sédiment pour essayer de faire une zone plate
+            to_depose = f64::min(height_difference, sediment_stocked);
 
-            compute_height_and_slopes(heightmap, offset_x, offset_y, arr_pos_x, arr_pos_y, &mut height, &mut horizontal_slope, &mut vertical_slope);
+        } else {
+            capacity = quantity_of_water * f64::max(-height_difference, erosion_settings.min_slope) * erosion_settings.capacity_factor;
+            if erosion_settings.speed_based_capacity {
+                capacity *= speed;
+            }
+            capacity = f64::max(erosion_settings.min_capacity, capacity);
+
+            if capacity < sediment_stocked {
+                // dépose une fraction de la différence entre capacité et stockage
+                to_depose = (sediment_stocked - capacity) * erosion_settings.deposition_speed
 
-            velocity.x = velocity.x * settings.inertia + horizontal_slope * (1.0 - settings.inertia);
-            velocity.y = velocity.y * settings.inertia + vertical_slope * (1.0 - settings.inertia);
+            } else {
+                // érode en forme de sphère autour de la position
+                to_erode = f64::min(-height_difference, (capacity - sediment_stocked) * erosion_settings.erosion_speed);
+
+                for point in PointsInRangeIterator::new(relative_points_table, array_max_index_x as usize + 1, array_max_index_y as usize + 1, heightmap.is_tileable(), arr_pos_x as i32, arr_pos_y as i32) {
+                    mut_height_ref = heightmap.get_mut(point.x, point.y).unwrap();
+                    *mut_height_ref = f32::max(*mut_height_ref, 0.0);
+                    // la roche dure resiste a l'erosion
+                    sediment_eroded = f64::min(to_erode * point.weight * (1.0 - hardness.get(point.x, point.y, *mut_height_ref)) as f64, *mut_height_ref as f64);
+
+                    sediment_stocked += sediment_eroded;
+                    *mut_height_ref -= sediment_eroded as f32;
+                    *droplet_maps.eroded.get_mut(point.x, point.y).unwrap() += sediment_eroded as f32;
+                }
 
-            match velocity.normalize_ip() {  // le vecteur vitesse est toujours normalisé à 1 pour éviter des bonds
-                Ok(()) => (),
-                Err(()) => break
             }
 
-            pos_x -= velocity.x;
-            pos_y -= velocity.y;
+        }
+        assert!(to_depose >= 0.0);
+        assert!(to_erode >= 0.0);
+        sediment_stocked -= to_depose;
+        assert!(sediment_stocked >= 0.0);
+
+        *heightmap.get_mut(arr_pos_x, arr_pos_y).unwrap() += (to_depose * (1.0 - offset_x) * (1.0 - offset_y)) as f32;
+        *heightmap.get_mut(arr_pos_x + 1, arr_pos_y).unwrap() += (to_depose * offset_x * (1.0 - offset_y)) as f32;
+        *heightmap.get_mut(arr_pos_x, arr_pos_y + 1).unwrap() += (to_depose * (1.0 - offset_x) * offset_y) as f32;
+        *heightmap.get_mut(arr_pos_x + 1, arr_pos_y + 1).unwrap() += (to_depose * offset_x * offset_y) as f32;
+
+        *droplet_maps.deposited.get_mut(arr_pos_x, arr_pos_y).unwrap() += (to_depose * (1.0 - offset_x) * (1.0 - offset_y)) as f32;
+        *droplet_maps.deposited.get_mut(arr_pos_x + 1, arr_pos_y).unwrap() += (to_depose * offset_x * (1.0 - offset_y)) as f32;
+        *droplet_maps.deposited.get_mut(arr_pos_x, arr_pos_y + 1).unwrap() += (to_depose * (1.0 - offset_x) * offset_y) as f32;
+        *droplet_maps.deposited.get_mut(arr_pos_x + 1, arr_pos_y + 1).unwrap() += (to_depose * offset_x * offset_y) as f32;
+
+        quantity_of_water *= 1.0 - erosion_settings.evaporation_rate;
+
+        // la goutte n'a plus assez d'elan pour continuer a monter
+        if speed == 0.0 {
+            break
+        }
+
+        
+        // // pixel = color_map.get_mut_pixel(heightmap.convert(arr_pos_y), heightmap.convert(arr_pos_x)).unwrap();
+        // // *pixel.0 += 1.0 / settings.number_of_erosion_iterations as f32 * 1000.0;
+        // // *pixel.1 += (current_lifetime as f32) / 200.0 / settings.number_of_erosion_iterations as f32 * 1000.0;
+        
+             
+    }
+}
+
+
+// limites des tuiles sur une periode: chaque tuile fait au moins 2 * halo points, pour que les marges de deux tuiles
+// separees par une troisieme ne se recouvrent pas; sur un terrain qui se repete, le nombre de tuiles est pair pour que
+// la premiere et la derniere, voisines, ne soient pas traitees en meme temps
+fn get_tile_boundaries(period: usize, halo: usize, tileable: bool) -> Vec<usize> {
+    let mut number_of_tiles = usize::max(period / (2 * halo), 1);
+    if tileable && number_of_tiles > 1 {
+        number_of_tiles -= number_of_tiles % 2;
+    }
+    (0..=number_of_tiles).map(|k| k * period / number_of_tiles).collect()
+}
 
-            if pos_x < 0.0 || pos_y < 0.0 || pos_x > (array_max_index - 1) as f64 || pos_y > (array_max_index - 1) as f64 { break }
 
-            new_arr_pos_x = pos_x as usize;
-            new_arr_pos_y = pos_y as usize;
+// marge, en points du tableau reduit, contenant tout le trajet d'une goutte partie d'une tuile: une goutte se deplace
+// d'au plus un point par etape, et erode ou depose jusqu'a radius + 1 points de sa position
+pub fn droplet_halo(erosion_settings: &ErosionOptions) -> usize {
+    erosion_settings.initial_lifetime as usize + erosion_settings.radius as usize + 2
+}
 
-            new_offset_x = pos_x - new_arr_pos_x as f64;
-            new_offset_y = pos_y - new_arr_pos_y as f64;
-            
-            compute_height_and_slopes(heightmap, new_offset_x, new_offset_y, new_arr_pos_x, new_arr_pos_y, &mut new_height, &mut horizontal_slope, &mut vertical_slope);
 
+// nombre de gouttes tirees a la fois par l'erosion parallele
+const EROSION_BATCH_SIZE: u32 = 16384;
 
-            height_difference = new_height - height;
+// erosion parallele: le tableau est decoupe en tuiles, chaque goutte etant simulee dans la tuile de son point de depart
+// sur une copie de la tuile entouree d'une marge assez large pour contenir tout son trajet
+// les tuiles sont traitees en 4 passes selon la parite de leurs indices: deux tuiles d'une meme passe sont separees par
+// une tuile entiere, leurs marges ne se recouvrent pas et elles peuvent etre erodees en meme temps
+// les points de depart sont tires par lots sur des generateurs derives de rng, et l'ordre des gouttes d'une tuile est
+// celui du tirage: le resultat ne depend pas du nombre de threads
+fn erode_parallel(heightmap: &mut ReducedArrayWrapper<f32>, rng: &mut Mcg128Xsl64, droplet_maps: &mut DropletMaps, hardness: &Hardness, relative_points_table: &[RelativePoint], threads: usize, erosion_settings: &ErosionOptions) {
+    let tileable = heightmap.is_tileable();
 
-            to_erode = 0.0;
-            to_depose = 0.0;
+    let array_max_index_x = heightmap.get_reduced_width() as u32 - 1;
+    let array_max_index_y = heightmap.get_reduced_height() as u32 - 1;
 
-            if height_difference > 0.0 {
-                  // si la goutte remonte une pente, dépose une partie du sédiment pour essayer de faire une zone plate
-                to_depose = f64::min(height_difference, sediment_stocked);
+    let halo = droplet_halo(erosion_settings);
 
-            } else {
-                capacity = f64::max(min_capacity, quantity_of_water * -height_difference * settings.capacity_factor);
+    // sur un terrain qui se repete, la derniere ligne / colonne est confondue avec la premiere
+    let period_x = if tileable { array_max_index_x as usize } else { array_max_index_x as usize + 1 };
+    let period_y = if tileable { array_max_index_y as usize } else { array_max_index_y as usize + 1 };
 
-                if capacity < sediment_stocked {
-                    // dépose 30% de la différence entre capacité et stockage
-                    to_depose = (sediment_stocked - capacity) * 0.3
+    let tile_boundaries_x = get_tile_boundaries(period_x, halo, tileable);
+    let tile_boundaries_y = get_tile_boundaries(period_y, halo, tileable);
+    let number_of_tiles_x = tile_boundaries_x.len() - 1;
+    let number_of_tiles_y = tile_boundaries_y.len() - 1;
 
-                } else {
-                    // érode en forme de sphère autour de la position
-                    to_erode = f64::min(-height_difference, (capacity - sediment_stocked) * 0.3);
+    let base = rng.next_u64();
 
-                    for point in PointsInRangeIterator::new(&relative_points_table, array_max_index as usize + 1, arr_pos_x as i32, arr_pos_y as i32) {
-                        mut_height_ref = heightmap.get_mut(point.x, point.y).unwrap();
-                        *mut_height_ref = f32::max(*mut_height_ref, 0.0);
-                        sediment_eroded = f64::min(to_erode * point.weight, *mut_height_ref as f64);
+    // terrain trop petit pour etre decoupe: les lots sont simules a la suite sur tout le tableau
+    if number_of_tiles_x < 2 || number_of_tiles_y < 2 {
+        for (batch, batch_start) in (0..erosion_settings.number_of_erosion_iterations).step_by(EROSION_BATCH_SIZE as usize).enumerate() {
+            let batch_size = u32::min(EROSION_BATCH_SIZE, erosion_settings.number_of_erosion_iterations - batch_start);
+            let mut batch_rng = derive_rng(base, &[EROSION_BATCH_STREAM, batch as i64]);
+
+            for _ in 0..batch_size {
+                let pos_x: f64 = next_random_number(array_max_index_x as u64, &mut batch_rng).into();
+                let pos_y: f64 = next_random_number(array_max_index_y as u64, &mut batch_rng).into();
+                erode_droplet(heightmap, droplet_maps, hardness, pos_x, pos_y, relative_points_table, erosion_settings);
+            }
+
+            println!("{} iterations done.", batch_start + batch_size);
+        }
+        return
+    }
+
+    let mut droplets_per_tile: Vec<Vec<(f64, f64)>> = vec![vec![]; number_of_tiles_x * number_of_tiles_y];
+
+    for (batch, batch_start) in (0..erosion_settings.number_of_erosion_iterations).step_by(EROSION_BATCH_SIZE as usize).enumerate() {
+        let batch_size = u32::min(EROSION_BATCH_SIZE, erosion_settings.number_of_erosion_iterations - batch_start);
+        let mut batch_rng = derive_rng(base, &[EROSION_BATCH_STREAM, batch as i64]);
+
+        for droplets in droplets_per_tile.iter_mut() {
+            droplets.clear();
+        }
+
+        for _ in 0..batch_size {
+            let pos_x: f64 = next_random_number(array_max_index_x as u64, &mut batch_rng).into();
+            let pos_y: f64 = next_random_number(array_max_index_y as u64, &mut batch_rng).into();
+
+            let tile_x = tile_boundaries_x.partition_point(|b| *b as f64 <= pos_x.rem_euclid(period_x as f64)) - 1;
+            let tile_y = tile_boundaries_y.partition_point(|b| *b as f64 <= pos_y.rem_euclid(period_y as f64)) - 1;
+            droplets_per_tile[tile_y * number_of_tiles_x + tile_x].push((pos_x, pos_y));
+        }
 
-                        sediment_stocked += sediment_eroded;
-                        *mut_height_ref -= sediment_eroded as f32;
+        for parity in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let tiles: Vec<(usize, usize)> = (0..number_of_tiles_y)
+                .flat_map(|tile_y| (0..number_of_tiles_x).map(move |tile_x| (tile_x, tile_y)))
+                .filter(|(tile_x, tile_y)| (tile_x % 2, tile_y % 2) == parity)
+                .collect();
+
+            let eroded_tiles = {
+                let heightmap: &ReducedArrayWrapper<f32> = heightmap;
+                let droplets_per_tile = &droplets_per_tile;
+                let tile_boundaries_x = &tile_boundaries_x;
+                let tile_boundaries_y = &tile_boundaries_y;
+
+                parallel_map(tiles.len(), threads, move |k| {
+                    let (tile_x, tile_y) = tiles[k];
+
+                    // copie de la tuile et de sa marge, limitee aux bords du tableau s'il ne se repete pas
+                    let (mut x0, mut x1) = (tile_boundaries_x[tile_x] as i32 - halo as i32, (tile_boundaries_x[tile_x + 1] + halo) as i32);
+                    let (mut y0, mut y1) = (tile_boundaries_y[tile_y] as i32 - halo as i32, (tile_boundaries_y[tile_y + 1] + halo) as i32);
+                    if !tileable {
+                        (x0, x1) = (i32::max(x0, 0), i32::min(x1, array_max_index_x as i32 + 1));
+                        (y0, y1) = (i32::max(y0, 0), i32::min(y1, array_max_index_y as i32 + 1));
                     }
 
+                    let mut tile: Arr2d<f32> = Arr2d::zeros((x1 - x0) as usize, (y1 - y0) as usize);
+                    for x in x0..x1 {
+                        for y in y0..y1 {
+                            *tile.get_mut((x - x0) as usize, (y - y0) as usize).unwrap() = *heightmap.geti(x, y).unwrap();
+                        }
+                    }
+
+                    let tile_hardness = hardness.tile(x0, y0, tile.get_width(), tile.get_height(), tileable);
+                    let mut tile_maps = DropletMaps::new(tile.get_width(), tile.get_height());
+                    {
+                        let mut tile_wrapper = ReducedArrayWrapper::new(&mut tile, 1);
+                        for (pos_x, pos_y) in droplets_per_tile[tile_y * number_of_tiles_x + tile_x].iter() {
+                            // position dans la copie, le point de depart etant ramene dans la tuile sur un terrain qui se repete
+                            let (mut pos_x, mut pos_y) = (*pos_x, *pos_y);
+                            if tileable {
+                                pos_x = pos_x.rem_euclid(period_x as f64);
+                                pos_y = pos_y.rem_euclid(period_y as f64);
+                            }
+                            erode_droplet(&mut tile_wrapper, &mut tile_maps, &tile_hardness, pos_x - x0 as f64, pos_y - y0 as f64, relative_points_table, erosion_settings);
+                        }
+                    }
+
+                    (x0, y0, tile, tile_maps)
+                })
+            };
+
+            for (x0, y0, tile, tile_maps) in eroded_tiles {
+                droplet_maps.add(&tile_maps, x0, y0, if tileable { Some((period_x, period_y)) } else { None });
+
+                for tx in 0..tile.get_width() {
+                    for ty in 0..tile.get_height() {
+                        let (mut x, mut y) = (tx as i32 + x0, ty as i32 + y0);
+                        if tileable {
+                            (x, y) = (x.rem_euclid(period_x as i32), y.rem_euclid(period_y as i32));
+                        }
+                        *heightmap.get_mut(x as usize, y as usize).unwrap() = *tile.get(tx, ty).unwrap();
+                    }
                 }
+            }
+        }
+
+        println!("{} iterations done.", batch_start + batch_size);
+    }
+}
+
+
+// voisins d'un point et distance a ce point, en points du tableau reduit
+const THERMAL_NEIGHBOURS: [(i32, i32, f32); 8] = [
+    (1, 0, 1.0), (-1, 0, 1.0), (0, 1, 1.0), (0, -1, 1.0),
+    (1, 1, std::f32::consts::SQRT_2), (-1, -1, std::f32::consts::SQRT_2), (1, -1, std::f32::consts::SQRT_2), (-1, 1, std::f32::consts::SQRT_2)];
+
+// erosion thermique: la ou la pente vers un voisin depasse l'angle de talus, une partie du materiau glisse vers ce voisin
+// (eboulis au pied des falaises); cell_size: largeur d'un point de la heightmap complete en unites de hauteur
+// chaque iteration est calculee a partir de l'etat precedent, le resultat ne depend donc pas du nombre de threads
+pub fn thermal_erode(heightmap: &mut ReducedArrayWrapper<f32>, erosion_maps: &mut ErosionMaps, cell_size: f32, threads: usize, erosion_settings: &ErosionOptions) {
+    if erosion_settings.thermal_erosion_iterations == 0 && !erosion_settings.export_debris_map {
+        return
+    }
+
+    println!("starting thermal erosion, number of iterations: {}", erosion_settings.thermal_erosion_iterations);
+
+    let width = heightmap.get_reduced_width();
+    let height = heightmap.get_reduced_height();
+    let tileable = heightmap.is_tileable();
+
+    // difference de hauteur maximale entre deux voisins directs
+    let talus = erosion_settings.talus_angle.to_radians().tan() as f32 * heightmap.get_scaling() as f32 * cell_size;
+    let transfer_rate = erosion_settings.thermal_transfer_rate as f32;
+
+    let mut heights: Arr2d<f32> = Arr2d::zeros(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            *heights.get_mut(x, y).unwrap() = *heightmap.get(x, y).unwrap();
+        }
+    }
+
+    if erosion_settings.export_debris_map && erosion_maps.debris.is_none() {
+        erosion_maps.debris = Some(Arr2d::zeros(width, height));
+    }
 
+    // sur un terrain qui se repete, la derniere ligne / colonne est confondue avec la premiere
+    let neighbour = |x: usize, y: usize, dx: i32, dy: i32| -> Option<(usize, usize)> {
+        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+        if tileable {
+            Some((nx.rem_euclid(width as i32 - 1) as usize, ny.rem_euclid(height as i32 - 1) as usize))
+        } else if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+            None
+        } else {
+            Some((nx as usize, ny as usize))
+        }
+    };
+
+    for iteration in 0..erosion_settings.thermal_erosion_iterations {
+        // quantite envoyee par chaque point a chacun de ses voisins
+        let outflows: Vec<Vec<[f32; 8]>> = {
+            let heights = &heights;
+            parallel_map(height, threads, |y| {
+                let mut line: Vec<[f32; 8]> = Vec::with_capacity(width);
+                for x in 0..width {
+                    let h = *heights.get(x, y).unwrap();
+                    let mut excess = [0.0; 8];
+                    let mut total_excess = 0.0;
+                    let mut max_excess: f32 = 0.0;
+
+                    for (k, (dx, dy, distance)) in THERMAL_NEIGHBOURS.iter().enumerate() {
+                        if let Some((nx, ny)) = neighbour(x, y, *dx, *dy) {
+                            let e = h - *heights.get(nx, ny).unwrap() - talus * distance;
+                            if e > 0.0 {
+                                excess[k] = e;
+                                total_excess += e;
+                                max_excess = f32::max(max_excess, e);
+                            }
+                        }
+                    }
+
+                    // la moitie de l'exces suffit a ramener la pente a l'angle de talus
+                    if total_excess > 0.0 {
+                        let moved = transfer_rate * max_excess / 2.0;
+                        for e in excess.iter_mut() {
+                            *e *= moved / total_excess;
+                        }
+                    }
+                    line.push(excess);
+                }
+                line
+            })
+        };
+
+        let lines: Vec<Vec<(f32, f32)>> = {
+            let heights = &heights;
+            let outflows = &outflows;
+            parallel_map(height, threads, |y| {
+                (0..width).map(|x| {
+                    let mut received = 0.0;
+                    for (k, (dx, dy, _)) in THERMAL_NEIGHBOURS.iter().enumerate() {
+                        if let Some((nx, ny)) = neighbour(x, y, *dx, *dy) {
+                            // les voisins sont ranges par paires de directions opposees
+                            received += outflows[ny][nx][k ^ 1];
+                        }
+                    }
+                    (*heights.get(x, y).unwrap() - outflows[y][x].iter().sum::<f32>() + received, received)
+                }).collect()
+            })
+        };
+
+        for (y, line) in lines.into_iter().enumerate() {
+            for (x, (h, received)) in line.into_iter().enumerate() {
+                *heights.get_mut(x, y).unwrap() = h;
+                if let Some(debris) = erosion_maps.debris.as_mut() {
+                    *debris.get_mut(x, y).unwrap() += received;
+                }
             }
-            assert!(to_depose >= 0.0);
-            assert!(to_erode >= 0.0);
-            sediment_stocked -= to_depose;
-            assert!(sediment_stocked >= 0.0);
-
-            *heightmap.get_mut(arr_pos_x, arr_pos_y).unwrap() += (to_depose * (1.0 - offset_x) * (1.0 - offset_y)) as f32;
-            *heightmap.get_mut(arr_pos_x + 1, arr_pos_y).unwrap() += (to_depose * offset_x * (1.0 - offset_y)) as f32;
-            *heightmap.get_mut(arr_pos_x, arr_pos_y + 1).unwrap() += (to_depose * (1.0 - offset_x) * offset_y) as f32;
-            *heightmap.get_mut(arr_pos_x + 1, arr_pos_y + 1).unwrap() += (to_depose * offset_x * offset_y) as f32;
-
-            quantity_of_water *= 0.95;
-            speed = f64::sqrt(speed * speed + height_difference.abs() / settings.max_terrain_height as f64 * 1.0);
-
-            
-            // // pixel = color_map.get_mut_pixel(heightmap.convert(arr_pos_y), heightmap.convert(arr_pos_x)).unwrap();
-            // // *pixel.0 += 1.0 / settings.number_of_erosion_iterations as f32 * 1000.0;
-            // // *pixel.1 += (current_lifetime as f32) / 200.0 / settings.number_of_erosion_iterations as f32 * 1000.0;
-            
-                 
         }
 
+        if (iteration + 1) % 10 == 0 {
+            println!("{} iterations done.", iteration + 1)
+        }
     }
 
+    for y in 0..height {
+        for x in 0..width {
+            *heightmap.get_mut(x, y).unwrap() = *heights.get(x, y).unwrap();
+        }
+    }
 
+    if tileable {
+        heightmap.wrap_edges();
+        if let Some(debris) = erosion_maps.debris.as_mut() {
+            debris.wrap_edges();
+        }
+    }
 }
 
 
+// niveaux de l'erosion, du niveau du mesh vers la pleine resolution: distance entre les points erodes et parametres du niveau
+// sans ErosionOptions::erosion_level_iterations, une seule erosion au niveau du mesh,
+// ou a pleine resolution pour le modele des tuyaux
+pub fn erosion_levels(n_iteration_difference: u32, erosion_settings: &ErosionOptions) -> Vec<(usize, ErosionOptions)> {
+    if erosion_settings.erosion_level_iterations.is_empty() {
+        let step = match erosion_settings.hydraulic_erosion_model {
+            HydraulicErosionModel::Droplets => 2_usize.pow(n_iteration_difference),
+            HydraulicErosionModel::Pipes => 1
+        };
+        return vec![(step, erosion_settings.clone())]
+    }
+
+    // les niveaux au dela de la pleine resolution sont ignores
+    erosion_settings.erosion_level_iterations.iter().take(n_iteration_difference as usize + 1).enumerate().map(|(level, iterations)| {
+        let mut level_settings = erosion_settings.clone();
+        level_settings.number_of_erosion_iterations = *iterations;
+        level_settings.pipe_iterations = *iterations;
+
+        // le rayon est borne par la validation des parametres
+        level_settings.radius = erosion_settings.get_level_radius(level) as u8;
+
+        (2_usize.pow(n_iteration_difference - level as u32), level_settings)
+    }).collect()
+}
 
 
+// erode les points de output espaces de step
+// a partir du deuxieme niveau (level > 0), les cartes du niveau sont ajoutees a celles du premier
+#[allow(clippy::too_many_arguments)]
+pub fn erode_level(output: &mut Arr2d<f32>, step: usize, level: usize, rng: &mut Mcg128Xsl64, color_map: &mut ColorMapArray,
+    erosion_maps: &mut ErosionMaps, hardness: &Hardness, settings: &GenerationOptions, level_settings: &ErosionOptions) {
 
+    println!("erosion level {}: one point out of {}", level, step);
 
+    let mut level_maps = ErosionMaps::default();
+    let maps = if level == 0 { &mut *erosion_maps } else { &mut level_maps };
+
+    if settings.tileable {
+        erode(&mut ReducedArrayWrapper::new_tileable(output, step), rng, color_map, maps, hardness, settings, level_settings);
+    } else {
+        erode(&mut ReducedArrayWrapper::new(output, step), rng, color_map, maps, hardness, settings, level_settings);
+    }
+
+    if level > 0 {
+        erosion_maps.add_level(&level_maps);
+    }
+}
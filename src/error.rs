@@ -0,0 +1,84 @@
+use std::{fmt, io, path::PathBuf};
+
+use config::ConfigError;
+use image::{ImageError, error::{EncodingError, ImageFormatHint}};
+
+use crate::settings::SettingViolation;
+
+
+#[derive(Debug)]
+pub enum Error {
+    // fichier de parametres introuvable, mal forme ou champ inconnu
+    Settings { path: PathBuf, source: ConfigError },
+
+    // parametres lus correctement mais hors des valeurs autorisees
+    InvalidSettings(Vec<SettingViolation>),
+
+    // template introuvable ou impossible a decoder
+    TemplateIo { path: PathBuf, source: ImageError },
+
+    // autre image d'entree (durete de la roche) introuvable ou impossible a decoder
+    ImageIo { path: PathBuf, source: ImageError },
+
+    // tableau d'entree trop petit pour les dimensions demandees par les parametres
+    DimensionMismatch { name: String, setting: String, expected: (usize, usize), found: (usize, usize) },
+
+    // image ou fichier de sortie impossible a ecrire
+    OutputIo { path: PathBuf, source: ImageError },
+
+    DisplayerLaunch { path: String, source: io::Error }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+
+impl Error {
+    pub fn output_io(path: PathBuf, source: io::Error) -> Error {
+        Error::OutputIo { path, source: ImageError::IoError(source) }
+    }
+
+    // erreur d'un encodeur utilise sans passer par image (tiff, exr)
+    pub fn output_encoding<E: Into<Box<dyn std::error::Error + Send + Sync>>>(path: PathBuf, format: ImageFormatHint, source: E) -> Error {
+        Error::OutputIo { path, source: ImageError::Encoding(EncodingError::new(format, source)) }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Settings { path, source } =>
+                write!(f, "invalid settings file \"{}\": {}", path.display(), source),
+            Error::InvalidSettings(violations) => {
+                write!(f, "invalid settings:")?;
+                for violation in violations {
+                    write!(f, "\n    {}", violation)?;
+                }
+                Ok(())
+            }
+            Error::TemplateIo { path, source } =>
+                write!(f, "could not read template \"{}\": {}", path.display(), source),
+            Error::ImageIo { path, source } =>
+                write!(f, "could not read image \"{}\": {}", path.display(), source),
+            Error::DimensionMismatch { name, setting, expected, found } =>
+                write!(f, "\"{}\" is {}x{} but {} requires at least {}x{}", name, found.0, found.1, setting, expected.0, expected.1),
+            Error::OutputIo { path, source } =>
+                write!(f, "could not write \"{}\": {}", path.display(), source),
+            Error::DisplayerLaunch { path, source } =>
+                write!(f, "could not launch the displayer \"{}\": {}", path, source)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Settings { source, .. } => Some(source),
+            Error::InvalidSettings(_) => None,
+            Error::TemplateIo { source, .. } => Some(source),
+            Error::ImageIo { source, .. } => Some(source),
+            Error::DimensionMismatch { .. } => None,
+            Error::OutputIo { source, .. } => Some(source),
+            Error::DisplayerLaunch { source, .. } => Some(source)
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use serde_derive::Deserialize;
+
+use crate::{utils::{Arr2d, derive_rng, rand, HARDNESS_STREAM}, noise::{GradientNoise, HeightmapAlgorithm, fractal_noise}, settings::{GenerationOptions, ErosionOptions}};
+
+
+// nombre d'octaves du bruit de durete
+const HARDNESS_NOISE_OCTAVES: u32 = 3;
+
+
+// origine de la durete de la roche, qui reduit la quantite erodee par l'eau
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HardnessSource {
+    #[default]
+    Uniform,  // toute la roche est erodee de la meme facon
+    Layers,  // strates horizontales: la durete depend de l'altitude
+    Noise,  // bruit fractal
+    Image  // niveaux de gris d'une image
+}
+
+
+// durete de la roche, entre 0 (erodee normalement) et 1 (non erodee)
+// le champ (bruit ou image) est donne point par point, les strates dependent de l'altitude courante du point
+#[derive(Clone, Default)]
+pub struct Hardness {
+    field: Option<Arr2d<f32>>,
+    layers: Vec<f32>,
+    layer_thickness: f32
+}
+
+impl Hardness {
+    // durete d'un tableau de width x height points dont le point (0, 0) est en origin dans le monde (non nulle pour un chunk)
+    // image: image de durete (valeurs entre 0 et 1) a la taille du terrain, repetee dans le monde
+    pub fn new(width: usize, height: usize, origin: (i64, i64), image: Option<&Arr2d<f32>>, settings: &GenerationOptions,
+        erosion_settings: &ErosionOptions) -> Hardness {
+
+        let max_hardness = erosion_settings.max_hardness;
+
+        match erosion_settings.hardness_source {
+            HardnessSource::Uniform => Hardness::default(),
+            HardnessSource::Layers => Hardness {
+                field: None,
+                layers: erosion_settings.hardness_layers.clone(),
+                layer_thickness: erosion_settings.hardness_layer_thickness
+            },
+            HardnessSource::Noise => {
+                // meme echelle que le bruit de la heightmap (fill_noise_heightmap), avec sa propre frequence et son propre generateur
+                let (terrain_w, terrain_h) = settings.get_terrain_size();
+                let scale = (usize::max(terrain_w, terrain_h) - 1) as f32;
+                let tile_size = if settings.tileable { Some(((terrain_w - 1) as f32 / scale, (terrain_h - 1) as f32 / scale)) } else { None };
+
+                let mut noise_settings = settings.clone();
+                noise_settings.noise_frequency = erosion_settings.hardness_noise_frequency;
+
+                let mut rng = derive_rng(settings.seed, &[HARDNESS_STREAM]);
+                let mut noise = GradientNoise::new(&mut rng);
+                let offsets: Vec<(f32, f32)> = (0..HARDNESS_NOISE_OCTAVES).map(|_| (rand(&mut rng) * 256.0, rand(&mut rng) * 256.0)).collect();
+
+                let mut field: Arr2d<f32> = Arr2d::zeros(width, height);
+                for x in 0..width {
+                    for y in 0..height {
+                        *field.get_mut(x, y).unwrap() = fractal_noise(&mut noise, &offsets,
+                            (origin.0 + x as i64) as f32 / scale, (origin.1 + y as i64) as f32 / scale, tile_size, HeightmapAlgorithm::Fbm, &noise_settings)
+                            * max_hardness;
+                    }
+                }
+                Hardness { field: Some(field), layers: vec![], layer_thickness: 0.0 }
+            }
+            HardnessSource::Image => {
+                let image = image.expect("hardness image not loaded");
+                let (period_x, period_y) = (image.get_width() as i64 - 1, image.get_height() as i64 - 1);
+
+                let mut field: Arr2d<f32> = Arr2d::zeros(width, height);
+                for x in 0..width {
+                    for y in 0..height {
+                        let (image_x, image_y) = ((origin.0 + x as i64).rem_euclid(period_x), (origin.1 + y as i64).rem_euclid(period_y));
+                        *field.get_mut(x, y).unwrap() = *image.get(image_x as usize, image_y as usize).unwrap() * max_hardness;
+                    }
+                }
+                Hardness { field: Some(field), layers: vec![], layer_thickness: 0.0 }
+            }
+        }
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.field.is_none() && self.layers.is_empty()
+    }
+
+    // durete au point (x, y), dont l'altitude est height
+    pub fn get(&self, x: usize, y: usize, height: f32) -> f32 {
+        if let Some(field) = &self.field {
+            *field.get(x, y).unwrap()
+        } else if !self.layers.is_empty() {
+            let layer = (height / self.layer_thickness).floor() as i64;
+            self.layers[layer.rem_euclid(self.layers.len() as i64) as usize]
+        } else {
+            0.0
+        }
+    }
+
+    // durete aux points espaces de step, pour l'erosion d'un tableau reduit
+    pub fn reduced(&self, step: usize) -> Hardness {
+        Hardness {
+            field: self.field.as_ref().map(|field| {
+                let mut reduced: Arr2d<f32> = Arr2d::zeros((field.get_width() - 1) / step + 1, (field.get_height() - 1) / step + 1);
+                for x in 0..reduced.get_width() {
+                    for y in 0..reduced.get_height() {
+                        *reduced.get_mut(x, y).unwrap() = *field.get(x * step, y * step).unwrap();
+                    }
+                }
+                reduced
+            }),
+            layers: self.layers.clone(),
+            layer_thickness: self.layer_thickness
+        }
+    }
+
+    // durete de la zone de width x height commencant en (x0, y0), pris de l'autre cote du terrain s'il se repete
+    pub fn tile(&self, x0: i32, y0: i32, width: usize, height: usize, tileable: bool) -> Hardness {
+        Hardness {
+            field: self.field.as_ref().map(|field| {
+                let mut tile: Arr2d<f32> = Arr2d::zeros(width, height);
+                for x in 0..width {
+                    for y in 0..height {
+                        let (fx, fy) = (x0 + x as i32, y0 + y as i32);
+                        *tile.get_mut(x, y).unwrap() = if tileable { *field.get_wrapped(fx, fy) } else { *field.geti(fx, fy).unwrap() };
+                    }
+                }
+                tile
+            }),
+            layers: self.layers.clone(),
+            layer_thickness: self.layer_thickness
+        }
+    }
+
+    // carte de la durete de heightmap, None si elle est uniforme
+    pub fn evaluate(&self, heightmap: &Arr2d<f32>) -> Option<Arr2d<f32>> {
+        if self.is_uniform() {
+            return None
+        }
+
+        let mut map: Arr2d<f32> = Arr2d::zeros(heightmap.get_width(), heightmap.get_height());
+        for x in 0..map.get_width() {
+            for y in 0..map.get_height() {
+                *map.get_mut(x, y).unwrap() = self.get(x, y, *heightmap.get(x, y).unwrap());
+            }
+        }
+        Some(map)
+    }
+}
@@ -0,0 +1,130 @@
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
+
+use image::{ColorType, ImageFormat, error::ImageFormatHint};
+use serde_derive::Deserialize;
+
+use crate::{error::{Error, Result}, image_generation::value_range, settings::{GenerationOptions, HeightmapExportOptions}, utils::{Arr2d, parallel_map}};
+
+
+// format du fichier de la heightmap
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeightmapFormat {
+    #[default]
+    Png8,  // niveaux de gris sur 8 bits repetes en RGB, lu par le displayer
+    Png16,  // niveaux de gris sur 16 bits
+    Tiff,  // reels sur 32 bits
+    Exr  // reels sur 32 bits, canal Y
+}
+
+
+impl HeightmapFormat {
+    // nom dans le fichier de parametres
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeightmapFormat::Png8 => "png8",
+            HeightmapFormat::Png16 => "png16",
+            HeightmapFormat::Tiff => "tiff",
+            HeightmapFormat::Exr => "exr"
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HeightmapFormat::Png8 | HeightmapFormat::Png16 => "png",
+            HeightmapFormat::Tiff => "tif",
+            HeightmapFormat::Exr => "exr"
+        }
+    }
+
+    // valeur du pixel blanc des formats entiers, None pour les formats reels
+    fn max_value(&self) -> Option<f32> {
+        match self {
+            HeightmapFormat::Png8 => Some(u8::MAX as f32),
+            HeightmapFormat::Png16 => Some(u16::MAX as f32),
+            HeightmapFormat::Tiff | HeightmapFormat::Exr => None
+        }
+    }
+}
+
+
+// hauteur d'un point = offset + scale * valeur du pixel, ramenee entre 0 et 1 pour les formats entiers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeightScale {
+    pub offset: f32,
+    pub scale: f32
+}
+
+
+// ecrit <name>.png, .tif ou .exr et <name>.txt, qui decrit le format et l'echelle des hauteurs
+// les hauteurs sont ramenees entre le minimum et le maximum de la heightmap, ou gardees telles quelles avec absolute_heights
+// (les formats entiers couvrent alors absolute_height_range, les hauteurs en dehors sont tronquees)
+pub fn save_heightmap(heightmap: &Arr2d<f32>, out_dir: &Path, name: &str, export_settings: &HeightmapExportOptions, settings: &GenerationOptions,
+                      threads: usize) -> Result<()> {
+    let range = if export_settings.absolute_heights { Some(export_settings.get_absolute_height_range(settings)) } else { None };
+    save_map(heightmap, out_dir, name, export_settings.heightmap_format, range, threads)
+}
+
+
+// meme chose que save_heightmap pour une carte quelconque: range donne les valeurs du noir et du blanc des formats entiers,
+// les formats reels gardent alors les valeurs telles quelles; None: valeurs ramenees entre le minimum et le maximum de la carte
+pub fn save_map(map: &Arr2d<f32>, out_dir: &Path, name: &str, format: HeightmapFormat, range: Option<(f32, f32)>, threads: usize) -> Result<()> {
+    let width = map.get_width();
+    let height = map.get_height();
+
+    let (min_height, max_height) = value_range(map, threads);
+    let height_scale = match (range, format.max_value()) {
+        (Some(_), None) => HeightScale { offset: 0.0, scale: 1.0 },
+        (Some((low, high)), Some(_)) => HeightScale { offset: low, scale: high - low },
+        // tableau constant: tout est au minimum
+        (None, _) => HeightScale { offset: min_height, scale: if max_height > min_height { max_height - min_height } else { 1.0 } }
+    };
+
+    let values: Vec<f32> = parallel_map(height, threads, |y| {
+        map.get_vec()[y * width..(y + 1) * width].iter().map(|h| (h - height_scale.offset) / height_scale.scale).collect::<Vec<f32>>()
+    }).concat();
+
+    let path = out_dir.join(format!("{}.{}", name, format.extension()));
+    if let Some(max_value) = format.max_value() {
+        let clipped = values.iter().filter(|value| !(0.0..=1.0).contains(*value)).count();
+        if clipped > 0 {
+            println!("warning - {} points of {} outside of [{}, {}] (values from {} to {}) are clipped",
+                clipped, name, height_scale.offset, height_scale.offset + height_scale.scale, min_height, max_height);
+        }
+
+        // comme generate_heightmap_image, la valeur est tronquee
+        let quantized = values.iter().map(|value| (value.clamp(0.0, 1.0) * max_value) as u16);
+        let (buffer, color): (Vec<u8>, ColorType) = if format == HeightmapFormat::Png8 {
+            (quantized.flat_map(|value| [value as u8; 3]).collect(), ColorType::Rgb8)
+        } else {
+            // image attend les echantillons de 16 bits dans l'ordre de la machine et les convertit pour le png
+            (quantized.flat_map(u16::to_ne_bytes).collect(), ColorType::L16)
+        };
+        image::save_buffer(&path, &buffer, width as u32, height as u32, color)
+            .map_err(|source| Error::OutputIo { path, source })?;
+    } else {
+        match format {
+            HeightmapFormat::Tiff => {
+                let file = File::create(&path).map_err(|e| Error::output_io(path.clone(), e))?;
+                tiff::encoder::TiffEncoder::new(BufWriter::new(file))
+                    .and_then(|mut encoder| encoder.write_image::<tiff::encoder::colortype::Gray32Float>(width as u32, height as u32, &values))
+                    .map_err(|e| Error::output_encoding(path, ImageFormatHint::Exact(ImageFormat::Tiff), e))?;
+            }
+            _ => {
+                use exr::prelude::{Image, SpecificChannels, Vec2, WritableImage};
+
+                let channels = SpecificChannels::build().with_channel("Y").with_pixel_fn(|Vec2(x, y)| (values[y * width + x],));
+                Image::from_channels((width, height), channels).write().to_file(&path)
+                    .map_err(|e| Error::output_encoding(path, ImageFormatHint::Name("exr".to_string()), e))?;
+            }
+        }
+    }
+
+    // une ligne par valeur: "nom valeur"
+    let scale_path = out_dir.join(format!("{}.txt", name));
+    let description = format!("file {}.{}\nformat {}\nsize {} {}\noffset {}\nscale {}\nmin_height {}\nmax_height {}",
+        name, format.extension(), format.name(), width, height, height_scale.offset, height_scale.scale, min_height, max_height);
+    File::create(&scale_path)
+        .and_then(|mut file| file.write_all(description.as_bytes()))
+        .map_err(|e| Error::output_io(scale_path, e))
+}
@@ -1,13 +1,14 @@
-use crate::utils::{Arr2d, ColorMapArray};
+use crate::utils::{Arr2d, ColorMapArray, parallel_map};
 
 
 
 
-pub fn generate_colormap_image(cm: &ColorMapArray, w: usize) -> Vec<u8> {
+// image de w x h pixels, le pixel de la ligne x et de la colonne y correspond a cm(x, y)
+pub fn generate_colormap_image(cm: &ColorMapArray, w: usize, h: usize) -> Vec<u8> {
     let mut output: Vec<u8> = vec![];
     let mut v: u8;
 
-    for x in 0..w {
+    for x in 0..h {
         for y in 0..w {
             if let Some((r, g, b)) = cm.get_pixel(x, y) {
 
@@ -33,37 +34,41 @@ pub fn generate_colormap_image(cm: &ColorMapArray, w: usize) -> Vec<u8> {
     output
 }
 
-pub fn generate_heightmap_image(v: &Arr2d<f32>, flattens: bool) -> Vec<u8> {
+// les lignes de l'image sont converties en parallele sur `threads` threads
+pub fn generate_heightmap_image(v: &Arr2d<f32>, flattens: bool, threads: usize) -> Vec<u8> {
 
     println!("converting to image buffer...");
 
-    let mut min_value = f32::INFINITY;
-    let mut max_value = -f32::INFINITY;
+    let width = v.get_width();
+    let rows: Vec<&[f32]> = v.get_vec().chunks(width).collect();
 
-    for value in v.get_vec().iter() {
-        min_value = f32::min(min_value, *value);
-        max_value = f32::max(max_value, *value);
-    }
-
-    let mut normalized_value: f32;
+    let (min_value, max_value) = value_range(v, threads);
 
-    let mut output: Vec<u8> = vec![];
+    // tableau constant (par exemple une carte de l'erosion vide): tout est au minimum
+    let range = if max_value > min_value { max_value - min_value } else { 1.0 };
 
     println!("begin iterations.");
 
-    for n in v.get_vec().iter() {
-        normalized_value = (n - min_value) / (max_value - min_value);
+    let output: Vec<u8> = parallel_map(rows.len(), threads, |y| {
+        let mut normalized_value: f32;
+        let mut row: Vec<u8> = Vec::with_capacity(width * 3);
 
-        assert!(0.0 <= normalized_value && normalized_value <= 1.0, "invalid value: {}", normalized_value);
-        
-        if flattens {
-            normalized_value = normalized_value.sqrt();
+        for n in rows[y].iter() {
+            normalized_value = (n - min_value) / range;
+
+            assert!((0.0..=1.0).contains(&normalized_value), "invalid value: {}", normalized_value);
+            
+            if flattens {
+                normalized_value = normalized_value.sqrt();
+            }
+
+            row.push((normalized_value * 255.0) as u8);
+            row.push((normalized_value * 255.0) as u8);
+            row.push((normalized_value * 255.0) as u8);
         }
 
-        output.push((normalized_value * 255.0) as u8);
-        output.push((normalized_value * 255.0) as u8);
-        output.push((normalized_value * 255.0) as u8);
-    };
+        row
+    }).concat();
 
     println!("conversion done.");
 
@@ -72,6 +77,12 @@ pub fn generate_heightmap_image(v: &Arr2d<f32>, flattens: bool) -> Vec<u8> {
 }
 
 
+// valeurs minimale et maximale du tableau, les lignes sont parcourues en parallele
+pub fn value_range(v: &Arr2d<f32>, threads: usize) -> (f32, f32) {
+    let width = v.get_width();
+    let rows: Vec<&[f32]> = v.get_vec().chunks(width).collect();
 
-
-
+    parallel_map(rows.len(), threads, |y| {
+        rows[y].iter().fold((f32::INFINITY, -f32::INFINITY), |(min_value, max_value), value| (f32::min(min_value, *value), f32::max(max_value, *value)))
+    }).into_iter().fold((f32::INFINITY, -f32::INFINITY), |(min_value, max_value), (row_min, row_max)| (f32::min(min_value, row_min), f32::max(max_value, row_max)))
+}
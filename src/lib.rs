@@ -0,0 +1,27 @@
+#![allow(non_snake_case)]
+
+
+pub mod diamondsquare;
+pub mod sky_generation;
+pub mod utils;
+pub mod image_generation;
+pub mod terrain_texture_generation;
+pub mod settings;
+pub mod erosion;
+pub mod pipe_erosion;
+pub mod hardness;
+pub mod rivers;
+pub mod water;
+pub mod biomes;
+pub mod materials;
+pub mod normal_map;
+pub mod terrain_analysis;
+pub mod heightmap_export;
+pub mod noise;
+pub mod pipeline;
+pub mod error;
+
+pub use error::{Error, Result};
+pub use pipeline::{TerrainPipeline, PipelineOutput, SkyOutput, TerrainOutput, TemplateSource};
+pub use settings::{GenerationOptions, LaunchOptions, ErosionOptions, RiverOptions, WaterOptions, BiomeOptions, NormalMapOptions, TerrainAnalysisOptions, HeightmapExportOptions, Settings};
+pub use utils::{Arr2d, ColorMapArray};
@@ -1,29 +1,81 @@
-#![allow(non_snake_case)]
+use std::{fs::File, io::Write, path::{Path, PathBuf}, process::Command};
 
+use mapgeneration::{Error, PipelineOutput, Result, TerrainOutput, TerrainPipeline, image_generation::{generate_colormap_image, generate_heightmap_image},
+    heightmap_export::{save_heightmap, save_map}, settings::{LaunchOptions, Settings}};
 
-use std::{process::Command, io::Write};
+mod cli;
 
-mod diamondsquare;
-mod sky_generation;
-mod utils;
-mod image_generation;
-mod terrain_texture_generation;
-mod settings;
-mod gradient_map_generation;
-mod erosion;
+use cli::Cli;
 
-use rand_pcg::Mcg128Xsl64;
-use settings::{LaunchOptions, Settings};
-use terrain_texture_generation::generate_terrain_texture;
 
-use crate::{diamondsquare::{diamond_square_2, generate_demisphere_heightmap}, image_generation::{generate_colormap_image, generate_heightmap_image}, sky_generation::{LightSpectrum, generate_sky_colormap}, utils::{Arr2d, ColorMapArray, PI}, gradient_map_generation::generate_gradient_map};
 
 
+fn main() {
+    let cli = match cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("Error - {}\n\n{}", message, cli::USAGE);
+            std::process::exit(2)
+        }
+    };
 
+    let result = match cli.command {
+        cli::Command::Help => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        cli::Command::Repl => {
+            repl(&cli);
+            Ok(())
+        }
+        command => load_settings(&cli).and_then(|settings| run_command(command, &settings, &cli.out_dir))
+    };
+
+    if let Err(error) = result {
+        eprintln!("Error - {}", error);
+        std::process::exit(1)
+    }
+}
+
+
+fn load_settings(cli: &Cli) -> Result<Settings> {
+    let settings = Settings::load(&cli.settings_path, &cli.overrides)?;
+    settings.validate()?;
+    Ok(settings)
+}
 
-fn main() {
-    let mut settings = settings::Settings::new().expect("did not work welp");
-    println!("{:?}", settings);
+
+fn run_command(command: cli::Command, settings: &Settings, out_dir: &Path) -> Result<()> {
+    let pipeline = TerrainPipeline::new(settings.generation_options.clone())
+        .with_launch_options(&settings.launch_options)
+        .with_erosion_options(&settings.erosion)
+        .with_river_options(&settings.rivers)
+        .with_water_options(&settings.water)
+        .with_biome_options(&settings.biomes)
+        .with_normal_map_options(&settings.normal_map)
+        .with_terrain_analysis_options(&settings.terrain_analysis)
+        .with_materials(&settings.materials);
+
+    let pipeline = match command {
+        cli::Command::Sky => pipeline.terrain_heightmap(false),
+        cli::Command::Terrain => pipeline.sky_heightmap(false).sky_texture(false).terrain_heightmap(true).terrain_texture(false),
+        // la texture du ciel fournit les couleurs utilisees pour eclairer le terrain
+        cli::Command::Texture => pipeline.sky_heightmap(false).sky_texture(true).terrain_heightmap(true).terrain_texture(true),
+        cli::Command::Display => return display(&settings.launch_options, out_dir),
+        cli::Command::Chunk(cx, cy) => {
+            std::fs::create_dir_all(out_dir).map_err(|e| Error::output_io(out_dir.to_path_buf(), e))?;
+            return save_terrain(&pipeline.generate_chunk(cx, cy)?, out_dir, settings)
+        }
+        _ => pipeline
+    };
+
+    save_outputs(&pipeline.run()?, out_dir, settings)
+}
+
+
+fn repl(cli: &Cli) {
+    // une erreur est affichee sans quitter la session, les parametres seront relus au prochain "generate"
+    let mut settings = reload_settings(cli);
 
     let mut command = String::new();
     while command != "quit" {
@@ -34,121 +86,197 @@ fn main() {
         std::io::stdin().read_line(&mut command).unwrap_or_else(|_| {println!("Error - invalid input"); 0});
         command = command.trim().to_lowercase();
 
-        if command == "display" {
-            display(&settings.launch_options)
-        } else if command == "generate -c" {
-            generate(&settings)
-        } else if command == "generate" {
-            settings = settings::Settings::new().expect("did not work welp");
-            generate(&settings)
-        } else if command != "quit" {
-            println!("Error - unknown command: \"{}\"", command)
+        if command == "generate" {
+            settings = reload_settings(cli);
+        }
+
+        let result = match (command.as_str(), &settings) {
+            ("quit", _) => Ok(()),
+            ("display", Some(settings)) => display(&settings.launch_options, &cli.out_dir),
+            ("generate" | "generate -c", Some(settings)) => run_command(cli::Command::Generate, settings, &cli.out_dir),
+            ("display" | "generate" | "generate -c", None) => {
+                println!("Error - no valid settings loaded, fix \"{}\" and enter \"generate\"", cli.settings_path.display());
+                Ok(())
+            }
+            _ => {
+                println!("Error - unknown command: \"{}\"", command);
+                Ok(())
+            }
+        };
+
+        if let Err(error) = result {
+            println!("Error - {}", error)
         }
 
     }
 }
 
 
-fn generate(settings: &Settings) {
+fn reload_settings(cli: &Cli) -> Option<Settings> {
+    match load_settings(cli) {
+        Ok(settings) => {
+            println!("{:?}", settings);
+            Some(settings)
+        }
+        Err(error) => {
+            println!("Error - {}", error);
+            None
+        }
+    }
+}
 
 
-    let demisphere_radius = 20 * 4;
-    let demisphere_width = demisphere_radius * 8 * 2 + 1;
+fn save_image(path: PathBuf, buffer: &[u8], width: usize, height: usize) -> Result<()> {
+    image::save_buffer(&path, buffer, width as u32, height as u32, image::ColorType::Rgb8)
+        .map_err(|source| Error::OutputIo { path, source })
+}
 
-    let mut ambient_col_out = [1.0, 1.0, 1.0];
-    let mut sun_col_out = [1.0, 1.0, 1.0];
 
-    let demisphere_heightmap_width = 2_usize.pow(7);
-    
-    if settings.launch_options.generate_sky_heightmap {
-        let mut demisphere_heightmap: Arr2d<f32> = Arr2d::zeros(demisphere_heightmap_width, demisphere_heightmap_width);
-        generate_demisphere_heightmap(&mut demisphere_heightmap, demisphere_heightmap_width);
+fn save_outputs(output: &PipelineOutput, out_dir: &Path, settings: &Settings) -> Result<()> {
+    // nombre de threads utilises pour convertir les heightmaps en images
+    let threads = settings.generation_options.get_threads();
 
-        image::save_buffer("demisphere_heightmap.png",
-        &generate_heightmap_image(&demisphere_heightmap, false), demisphere_heightmap_width as u32, demisphere_heightmap_width as u32, image::ColorType::Rgb8)
-        .expect("welp");
+    std::fs::create_dir_all(out_dir).map_err(|e| Error::output_io(out_dir.to_path_buf(), e))?;
 
+    if let Some(demisphere_heightmap) = &output.sky.heightmap {
+        save_image(out_dir.join("demisphere_heightmap.png"),
+        &generate_heightmap_image(demisphere_heightmap, false, threads), demisphere_heightmap.get_width(), demisphere_heightmap.get_height())?;
     }
 
-    
-    if settings.launch_options.generate_sky_texture {
-        let mut demisphere_colormap: ColorMapArray = ColorMapArray::new_empty(demisphere_width, demisphere_width);
-
-        let sun_angle = settings.generation_options.sun_angle * PI / 180.0;
-    
-        let mut incident_light_spectrum = LightSpectrum::new();
-    
-        generate_sky_colormap(&mut demisphere_colormap, demisphere_width,
-            settings.generation_options.planet_radius, settings.generation_options.atmosphere_radius, &mut incident_light_spectrum, sun_angle,
-            settings.generation_options.sun_size, settings.generation_options.ambient_sky_light, &mut ambient_col_out, &mut sun_col_out
-        );
-    
-        image::save_buffer("demisphere_colormap.png",
-         &generate_colormap_image(&demisphere_colormap, demisphere_width), demisphere_width as u32, demisphere_width as u32, image::ColorType::Rgb8)
-         .expect("welp");
-        
-    }
+    if let Some(demisphere_colormap) = &output.sky.colormap {
+        let sun_color = output.sky.sun_color;
+        let cfg_path = out_dir.join("cfg.data");
 
-    if settings.launch_options.generate_terrain_heightmap {
+        File::create(&cfg_path)
+            .and_then(|mut file| file.write_all(format!("{}\n{}\n{}", sun_color[0], sun_color[1], sun_color[2]).as_bytes()))
+            .map_err(|e| Error::output_io(cfg_path, e))?;
 
-        let template_img = image::io::Reader::open("template.png").expect("welp").decode().expect("welp");
+        let demisphere_width = demisphere_colormap.get_width();
+        save_image(out_dir.join("demisphere_colormap.png"),
+         &generate_colormap_image(demisphere_colormap, demisphere_width, demisphere_width), demisphere_width, demisphere_width)?;
+    }
 
-        let n: usize = settings.generation_options.terrain_power_of_two as usize;
-    
-        let mut rng = Mcg128Xsl64::new(settings.generation_options.seed as u128);
-    
-        let template = Arr2d::from_dynamic_image(template_img, settings.generation_options.max_terrain_height, &settings.generation_options);
-    
-        let scaling = usize::pow(2, n as u32 - settings.generation_options.template_power_of_two);
-        let w = 2_usize.pow(n as u32) + 1;
+    if let Some(terrain) = &output.terrain {
+        save_terrain(terrain, out_dir, settings)?;
+    }
 
-        let reduced_w = 2_usize.pow(settings.generation_options.mesh_power_of_two) + 1;
+    Ok(())
+}
 
-        let mut terrain_heightmap: Arr2d<f32> = Arr2d::init_with_value(w, w, 10.0);
-        let mut reduced_terrain_heightmap: Arr2d<f32> = Arr2d::init_with_value(reduced_w, reduced_w, 10.0);
 
-        let mut terrain_colormap: ColorMapArray = ColorMapArray::new_empty(w, w);
+fn save_terrain(terrain: &TerrainOutput, out_dir: &Path, settings: &Settings) -> Result<()> {
+    let threads = settings.generation_options.get_threads();
 
-        diamond_square_2(&template, &mut terrain_heightmap,
-             n, &mut reduced_terrain_heightmap, scaling, settings.generation_options.irregularity, n as u32 - settings.generation_options.mesh_power_of_two, &mut rng, &mut terrain_colormap,
-            &settings.generation_options);
+    // a la resolution du mesh pour le displayer, et a pleine resolution
+    save_heightmap(&terrain.mesh_heightmap, out_dir, "heightmap", &settings.heightmap_export, &settings.generation_options, threads)?;
+    save_heightmap(&terrain.heightmap, out_dir, "heightmap_full", &settings.heightmap_export, &settings.generation_options, threads)?;
 
-        //erode(&mut ReducedArrayWrapper::new(&mut reduced_terrain_heightmap, n as u32 - 1, n as u32 - 1), 200000, settings.generation_options.max_terrain_height, &mut rng);
-            
-        image::save_buffer("heightmap.png",
-        &generate_heightmap_image(&reduced_terrain_heightmap, false),
-        reduced_w as u32, reduced_w as u32, image::ColorType::Rgb8).expect("welp");
-    
-        if settings.launch_options.generate_terrain_texture {
+    // dans le format de la heightmap: pente et orientation sur une echelle fixe en png, valeurs reelles en tiff et en exr
+    if let Some(analysis) = &terrain.analysis {
+        for (name, map, range) in analysis.named_maps() {
+            save_map(map, out_dir, name, settings.heightmap_export.heightmap_format, range, threads)?;
+        }
+    }
 
-            let mut terrain_gradientmap: Arr2d<f32> = Arr2d::init_with_value(reduced_w, reduced_w, 10.0);
-            generate_gradient_map(&reduced_terrain_heightmap, &mut terrain_gradientmap, settings.generation_options.max_terrain_height, reduced_w, 2, 5);
+    // a la resolution du mesh, ou a pleine resolution pour l'erosion par tuyaux sans niveaux
+    for (name, map) in terrain.erosion_maps.named_maps() {
+        save_image(out_dir.join(name),
+        &generate_heightmap_image(map, false, threads),
+        map.get_width(), map.get_height())?;
+    }
 
-            image::save_buffer("gradientmap.png",
-            &generate_heightmap_image(&terrain_gradientmap, false),
-            reduced_w as u32, reduced_w as u32, image::ColorType::Rgb8).expect("welp");
-    
-            generate_terrain_texture(&mut terrain_colormap, &mut terrain_heightmap, &terrain_gradientmap, w.div_euclid(reduced_w) + 1, w, settings.generation_options.max_terrain_height * 2_f32.powi(1),
-                settings.generation_options.shadow_direction, settings.generation_options.sun_angle * PI / 180.0,  &mut rng, &ambient_col_out, &sun_col_out, &settings.generation_options);
+    if let Some(rivers) = &terrain.rivers {
+        let w = terrain.heightmap.get_width();
+        let h = terrain.heightmap.get_height();
+        save_image(out_dir.join("rivermask.png"),
+        &generate_heightmap_image(&rivers.mask, false, threads),
+        w, h)?;
+
+        // un troncon par ligne: "x,y x,y ...", en points de la heightmap complete
+        let polylines_path = out_dir.join("rivers.txt");
+        let polylines: Vec<String> = rivers.polylines.iter()
+            .map(|polyline| polyline.iter().map(|(x, y)| format!("{},{}", x, y)).collect::<Vec<String>>().join(" "))
+            .collect();
+
+        File::create(&polylines_path)
+            .and_then(|mut file| file.write_all(polylines.join("\n").as_bytes()))
+            .map_err(|e| Error::output_io(polylines_path, e))?;
+    }
+
+    if let Some(water) = &terrain.water {
+        let w = terrain.heightmap.get_width();
+        let h = terrain.heightmap.get_height();
+        for (name, map) in water.named_maps() {
+            save_image(out_dir.join(name),
+            &generate_heightmap_image(map, false, threads),
+            w, h)?;
+        }
+    }
 
-            image::save_buffer("colormap.png",
-            &generate_colormap_image(&terrain_colormap, w - 1), w as u32 - 1, w as u32 - 1, image::ColorType::Rgb8)
-            .expect("welp");
+    if let Some(biomes) = &terrain.biomes {
+        let w = terrain.heightmap.get_width();
+        let h = terrain.heightmap.get_height();
+        for (name, map) in biomes.named_maps() {
+            save_image(out_dir.join(name),
+            &generate_heightmap_image(map, false, threads),
+            w, h)?;
         }
 
+        // la valeur de chaque pixel est l'indice du biome, sans normalisation, decrit dans biomes.txt
+        let ids: Vec<u8> = biomes.id.get_vec().iter().flat_map(|id| [*id, *id, *id]).collect();
+        save_image(out_dir.join("biomemap.png"), &ids, w, h)?;
+
+        let legend_path = out_dir.join("biomes.txt");
+        let legend: Vec<String> = biomes.names.iter().enumerate().map(|(id, name)| format!("{} {}", id, name)).collect();
+        File::create(&legend_path)
+            .and_then(|mut file| file.write_all(legend.join("\n").as_bytes()))
+            .map_err(|e| Error::output_io(legend_path, e))?;
+    }
+
+    if let Some(shadowmask) = &terrain.shadowmask {
+        // sans normalisation: blanc eclaire, noir dans l'ombre
+        let w = terrain.heightmap.get_width();
+        let h = terrain.heightmap.get_height();
+        let values: Vec<u8> = shadowmask.get_vec().iter().flat_map(|value| [(value * 255.0).round() as u8; 3]).collect();
+        save_image(out_dir.join("shadowmask.png"), &values, w, h)?;
+    }
 
+    if let Some(skyviewmap) = &terrain.skyviewmap {
+        // sans normalisation: blanc sous un ciel entierement visible
+        let w = terrain.heightmap.get_width();
+        let h = terrain.heightmap.get_height();
+        let values: Vec<u8> = skyviewmap.get_vec().iter().flat_map(|value| [(value * 255.0).round() as u8; 3]).collect();
+        save_image(out_dir.join("skyviewmap.png"), &values, w, h)?;
     }
 
+    if let Some(normalmap) = &terrain.normalmap {
+        // chaque composante, entre -1 et 1, est ramenee entre 0 et 255
+        let w = terrain.heightmap.get_width();
+        let h = terrain.heightmap.get_height();
+        let values: Vec<u8> = normalmap.get_vec().iter().flat_map(|normal| normal.map(|value| ((value * 0.5 + 0.5) * 255.0).round() as u8)).collect();
+        save_image(out_dir.join("normalmap.png"), &values, w, h)?;
+    }
 
+    if let Some(colormap) = &terrain.colormap {
+        // la derniere ligne et la derniere colonne ne sont pas exportees
+        let w = terrain.heightmap.get_width();
+        let h = terrain.heightmap.get_height();
+        save_image(out_dir.join("colormap.png"),
+        &generate_colormap_image(colormap, w - 1, h - 1), w - 1, h - 1)?;
+    }
+
+    Ok(())
 }
 
 
-fn display(launch_settings: &LaunchOptions) {
+// le displayer lit les images dans son repertoire courant
+fn display(launch_settings: &LaunchOptions, out_dir: &Path) -> Result<()> {
     let _output = Command::new(&launch_settings.displayer_path)
+        .current_dir(out_dir)
         // .arg("cmd")
         // .arg(r"C:\Users\Hélène Le Berre\rp\mapgeneration\displayer\main.py")
         .output()
-        .expect("failed to execute");
-}
-
+        .map_err(|source| Error::DisplayerLaunch { path: launch_settings.displayer_path.clone(), source })?;
 
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use serde_derive::Deserialize;
+
+use crate::{utils::{Arr2d, derive_rng, rand, MATERIAL_STREAM}, noise::{GradientNoise, HeightmapAlgorithm, fractal_noise}, settings::GenerationOptions};
+
+
+// nombre d'octaves du bruit des materiaux
+const MATERIAL_NOISE_OCTAVES: u32 = 3;
+
+
+// intervalle de valeurs dans lequel un materiau est applique, avec une transition de largeur blend centree sur ses bornes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    #[serde(default = "default_condition_min")]
+    pub min: f32,
+    #[serde(default = "default_condition_max")]
+    pub max: f32,
+    #[serde(default)]
+    pub blend: f32
+}
+
+fn default_condition_min() -> f32 { f32::NEG_INFINITY }
+fn default_condition_max() -> f32 { f32::INFINITY }
+
+impl Condition {
+    // 1 dans l'intervalle, 0 hors de l'intervalle elargi de blend / 2
+    fn weight(&self, value: f32) -> f32 {
+        if self.blend > 0.0 {
+            ((value - self.min) / self.blend + 0.5).clamp(0.0, 1.0) * ((self.max - value) / self.blend + 0.5).clamp(0.0, 1.0)
+        } else if self.min <= value && value <= self.max {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+
+// materiau peint sur le terrain la ou ses conditions sont remplies, par dessus les materiaux precedents
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Material {
+    pub name: String,
+
+    // couleur (0 a 255), ou degrade selon la hauteur: [[hauteur, r, g, b], ...] par hauteurs croissantes
+    pub color: Option<[f32; 3]>,
+    #[serde(default)]
+    pub color_ramp: Vec<[f32; 4]>,
+    // prend la couleur du biome quand les biomes sont generes
+    #[serde(default)]
+    pub use_biome_color: bool,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+
+    // hauteur, pente en degres (comme rock_threshold), courbures en plan et de profil et position topographique
+    // (voir TerrainAnalysis) et bruit (entre 0 et 1)
+    pub height: Option<Condition>,
+    pub slope: Option<Condition>,
+    pub plan_curvature: Option<Condition>,
+    pub profile_curvature: Option<Condition>,
+    pub tpi: Option<Condition>,
+    pub noise: Option<Condition>,
+    #[serde(default = "default_material_noise_frequency")]
+    pub noise_frequency: f32
+}
+
+fn default_opacity() -> f32 { 1.0 }
+fn default_material_noise_frequency() -> f32 { 8.0 }
+
+impl Material {
+    fn base_color(&self, height: f32, biome_color: Option<[f32; 3]>) -> [f32; 3] {
+        if let Some(biome_color) = biome_color.filter(|_| self.use_biome_color) {
+            return biome_color
+        }
+
+        let color = if self.color_ramp.is_empty() {
+            self.color.unwrap_or([0.0; 3])
+        } else {
+            let ramp = &self.color_ramp;
+            let i = ramp.iter().position(|stop| stop[0] > height).unwrap_or(ramp.len());
+            if i == 0 {
+                [ramp[0][1], ramp[0][2], ramp[0][3]]
+            } else if i == ramp.len() {
+                [ramp[i - 1][1], ramp[i - 1][2], ramp[i - 1][3]]
+            } else {
+                let t = (height - ramp[i - 1][0]) / (ramp[i][0] - ramp[i - 1][0]);
+                [1, 2, 3].map(|channel| ramp[i - 1][channel] * (1.0 - t) + ramp[i][channel] * t)
+            }
+        };
+
+        color.map(|channel| channel / 255.0)
+    }
+}
+
+
+// materiaux prets a etre evalues sur un tableau de width x height points, avec le bruit de chaque materiau
+pub struct Palette {
+    materials: Vec<Material>,
+    noises: Vec<Option<Arr2d<f32>>>
+}
+
+impl Palette {
+    // origin: position du point (0, 0) dans le monde, pour que le bruit se raccorde entre chunks
+    pub fn new(materials: &[Material], width: usize, height: usize, origin: (i64, i64), settings: &GenerationOptions) -> Palette {
+        let (terrain_w, terrain_h) = settings.get_terrain_size();
+        let scale = (usize::max(terrain_w, terrain_h) - 1) as f32;
+        let tile_size = if settings.tileable { Some(((terrain_w - 1) as f32 / scale, (terrain_h - 1) as f32 / scale)) } else { None };
+
+        let noises = materials.iter().enumerate().map(|(i, material)| {
+            material.noise.as_ref()?;
+
+            let mut noise_settings = settings.clone();
+            noise_settings.noise_frequency = material.noise_frequency;
+
+            let mut rng = derive_rng(settings.seed, &[MATERIAL_STREAM, i as i64]);
+            let mut noise = GradientNoise::new(&mut rng);
+            let offsets: Vec<(f32, f32)> = (0..MATERIAL_NOISE_OCTAVES).map(|_| (rand(&mut rng) * 256.0, rand(&mut rng) * 256.0)).collect();
+
+            let mut field: Arr2d<f32> = Arr2d::zeros(width, height);
+            for x in 0..width {
+                for y in 0..height {
+                    *field.get_mut(x, y).unwrap() = fractal_noise(&mut noise, &offsets,
+                        (origin.0 + x as i64) as f32 / scale, (origin.1 + y as i64) as f32 / scale, tile_size, HeightmapAlgorithm::Fbm, &noise_settings);
+                }
+            }
+            Some(field)
+        }).collect();
+
+        Palette { materials: materials.to_vec(), noises }
+    }
+
+    // couleur du point (x, y): les materiaux sont appliques dans l'ordre par dessus base_color (la couleur du terrain sans
+    // materiaux), chacun en proportion du poids de ses conditions
+    #[allow(clippy::too_many_arguments)]
+    pub fn color(&self, x: usize, y: usize, height: f32, slope: f32, plan_curvature: f32, profile_curvature: f32, tpi: f32, biome_color: Option<[f32; 3]>,
+                 base_color: [f32; 3]) -> [f32; 3] {
+        let mut color = base_color;
+
+        for (material, noise) in self.materials.iter().zip(self.noises.iter()) {
+            let mut weight = material.opacity;
+            for (condition, value) in [(&material.height, height), (&material.slope, slope), (&material.plan_curvature, plan_curvature),
+                                       (&material.profile_curvature, profile_curvature), (&material.tpi, tpi)] {
+                if let Some(condition) = condition {
+                    weight *= condition.weight(value);
+                }
+            }
+            if let (Some(condition), Some(noise)) = (&material.noise, noise) {
+                weight *= condition.weight(*noise.get(x, y).unwrap());
+            }
+
+            if weight > 0.0 {
+                let material_color = material.base_color(height, biome_color);
+                for channel in 0..3 {
+                    color[channel] = color[channel] * (1.0 - weight) + material_color[channel] * weight;
+                }
+            }
+        }
+
+        color
+    }
+}
@@ -0,0 +1,243 @@
+use rand_core::RngCore;
+use rand_pcg::Mcg128Xsl64;
+use serde_derive::Deserialize;
+
+use crate::{utils::{Arr2d, ColorMapArray, bilinear_interpolation, rand}, erosion::{erosion_levels, erode_level, ErosionMaps}, hardness::Hardness, settings::{GenerationOptions, ErosionOptions}};
+
+
+// algorithme utilise pour generer la heightmap de base, avant l'erosion
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeightmapAlgorithm {
+    #[default]
+    DiamondSquare,
+    Fbm,  // somme d'octaves de bruit de gradient
+    Ridged,  // multifractal "ridged": cretes marquees
+    Billow  // valeur absolue du bruit: collines arrondies
+}
+
+const SQRT_2: f32 = std::f32::consts::SQRT_2;
+const HALF_SQRT_2: f32 = SQRT_2 / 2.0;
+
+const GRADIENTS: [[f32; 2]; 8] = [
+    [1.0, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0],
+    [HALF_SQRT_2, HALF_SQRT_2], [-HALF_SQRT_2, HALF_SQRT_2], [HALF_SQRT_2, -HALF_SQRT_2], [-HALF_SQRT_2, -HALF_SQRT_2]
+];
+
+
+// bruit de gradient (Perlin) en 2 dimensions
+pub struct GradientNoise {
+    permutation: Vec<usize>,  // 256 valeurs melangees, repetees deux fois pour eviter un modulo
+    period: Option<(i32, i32)>  // nombre de mailles apres lequel le bruit se repete
+}
+
+impl GradientNoise {
+    pub fn new(rng: &mut Mcg128Xsl64) -> GradientNoise {
+        let mut permutation: Vec<usize> = (0..256).collect();
+
+        for i in (1..256).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            permutation.swap(i, j);
+        }
+
+        let copy = permutation.clone();
+        permutation.extend(copy);
+
+        GradientNoise { permutation, period: None }
+    }
+
+    pub fn set_period(&mut self, period: Option<(i32, i32)>) {
+        self.period = period;
+    }
+
+    fn gradient(&self, x: i32, y: i32) -> [f32; 2] {
+        let (x, y) = match self.period {
+            Some((px, py)) => (x.rem_euclid(px), y.rem_euclid(py)),
+            None => (x, y)
+        };
+
+        let hash = self.permutation[self.permutation[(x & 255) as usize] + (y & 255) as usize];
+        GRADIENTS[hash & 7]
+    }
+
+    // valeur du bruit en (x, y), approximativement dans [-1, 1]
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let x0 = x.floor();
+        let y0 = y.floor();
+
+        let xi = x0 as i32;
+        let yi = y0 as i32;
+
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let dot = |ix: i32, iy: i32, dx: f32, dy: f32| {
+            let g = self.gradient(ix, iy);
+            g[0] * dx + g[1] * dy
+        };
+
+        let v00 = dot(xi, yi, fx, fy);
+        let v10 = dot(xi + 1, yi, fx - 1.0, fy);
+        let v01 = dot(xi, yi + 1, fx, fy - 1.0);
+        let v11 = dot(xi + 1, yi + 1, fx - 1.0, fy - 1.0);
+
+        bilinear_interpolation(fade(fx), fade(fy), v00, v01, v10, v11) * SQRT_2
+    }
+}
+
+// l'arrondi peut donner une valeur tres legerement superieure a 1 pour t proche de 1
+fn fade(t: f32) -> f32 {
+    (t * t * t * (t * (t * 6.0 - 15.0) + 10.0)).min(1.0)
+}
+
+
+// somme des octaves au point (x, y) exprime en fraction de la largeur du terrain, resultat dans [0, 1]
+// si tile_size est donne (taille du terrain dans la meme unite), chaque octave se repete exactement sur cette taille
+pub fn fractal_noise(noise: &mut GradientNoise, offsets: &[(f32, f32)], x: f32, y: f32, tile_size: Option<(f32, f32)>,
+    algorithm: HeightmapAlgorithm, settings: &GenerationOptions) -> f32 {
+    let mut frequency = settings.noise_frequency;
+    let mut amplitude = 1.0;
+    let mut amplitude_sum = 0.0;
+
+    let mut sum = 0.0;
+    let mut weight = 1.0_f32;  // pour le ridged: les cretes des octaves precedents attenuent les suivants
+
+    let mut n: f32;
+    let mut signal: f32;
+
+    for (offset_x, offset_y) in offsets.iter() {
+        n = match tile_size {
+            Some((tile_w, tile_h)) => {
+                // nombre entier de mailles sur le terrain, la frequence est ajustee sur chaque axe
+                let period_x = (tile_w * frequency).round().max(1.0);
+                let period_y = (tile_h * frequency).round().max(1.0);
+                noise.set_period(Some((period_x as i32, period_y as i32)));
+                noise.sample(x / tile_w * period_x + offset_x, y / tile_h * period_y + offset_y)
+            }
+            None => noise.sample(x * frequency + offset_x, y * frequency + offset_y)
+        }.clamp(-1.0, 1.0);
+
+        signal = match algorithm {
+            HeightmapAlgorithm::Ridged => {
+                let ridge = (1.0 - n.abs()).powi(2) * weight;
+                weight = (ridge * 2.0).clamp(0.0, 1.0);
+                ridge
+            }
+            HeightmapAlgorithm::Billow => n.abs(),
+            _ => (n + 1.0) / 2.0
+        };
+
+        sum += signal * amplitude;
+        amplitude_sum += amplitude;
+
+        frequency *= settings.noise_lacunarity;
+        amplitude *= settings.noise_persistence;
+    }
+
+    sum / amplitude_sum
+}
+
+
+// genere la heightmap avec du bruit fractal, puis l'erode a la resolution du mesh comme le fait diamond_square_2
+#[allow(clippy::too_many_arguments)]
+pub fn generate_noise_heightmap(output: &mut Arr2d<f32>, n_iteration_difference: u32, rng: &mut Mcg128Xsl64, color_map: &mut ColorMapArray, erosion_maps: &mut ErosionMaps,
+    hardness: &Hardness, settings: &GenerationOptions, erosion_settings: &ErosionOptions) {
+
+    fill_noise_heightmap(output, (0, 0), rng, settings);
+    erode_noise_heightmap(output, n_iteration_difference, rng, color_map, erosion_maps, hardness, settings, erosion_settings);
+}
+
+
+// remplit output avec le bruit fractal, origin etant la position de output(0, 0) dans le monde (non nulle pour un chunk)
+// le bruit est le meme pour un meme etat de rng: des tableaux voisins generes avec la meme seed se raccordent
+pub fn fill_noise_heightmap(output: &mut Arr2d<f32>, origin: (i64, i64), rng: &mut Mcg128Xsl64, settings: &GenerationOptions) {
+
+    let w = output.get_width();
+    let h = output.get_height();
+
+    // meme echelle sur les deux axes pour ne pas etirer le relief quand le terrain n'est pas carre
+    let scale = (usize::max(w, h) - 1) as f32;
+
+    let mut noise = GradientNoise::new(rng);
+
+    let tile_size = if settings.tileable {
+        Some(((w - 1) as f32 / scale, (h - 1) as f32 / scale))
+    } else {
+        None
+    };
+
+    // decalage aleatoire par octave pour que les octaves ne soient pas alignes sur l'origine
+    let offsets: Vec<(f32, f32)> = (0..settings.noise_octaves).map(|_| (rand(rng) * 256.0, rand(rng) * 256.0)).collect();
+
+    for x in 0..w {
+        for y in 0..h {
+            *output.get_mut(x, y).unwrap() = fractal_noise(&mut noise, &offsets,
+                (origin.0 + x as i64) as f32 / scale, (origin.1 + y as i64) as f32 / scale, tile_size, settings.heightmap_algorithm, settings)
+                * settings.max_terrain_height;
+        }
+    }
+
+    println!("noise generation done.");
+}
+
+
+// erode output a la resolution du mesh, puis aux niveaux plus fins de ErosionOptions::erosion_level_iterations
+#[allow(clippy::too_many_arguments)]
+pub fn erode_noise_heightmap(output: &mut Arr2d<f32>, n_iteration_difference: u32, rng: &mut Mcg128Xsl64,
+    color_map: &mut ColorMapArray, erosion_maps: &mut ErosionMaps, hardness: &Hardness,
+    settings: &GenerationOptions, erosion_settings: &ErosionOptions) {
+
+    if settings.tileable {
+        // les bords sont egaux aux erreurs d'arrondi pres: on les rend identiques avant l'erosion
+        output.wrap_edges();
+    }
+
+    for (level, (reduced_output_step, level_settings)) in erosion_levels(n_iteration_difference, erosion_settings).iter().enumerate() {
+        let before_erosion = output.clone();
+        erode_level(output, *reduced_output_step, level, rng, color_map, erosion_maps, hardness, settings, level_settings);
+        interpolate_erosion_delta(output, &before_erosion, *reduced_output_step);
+    }
+
+    println!("generation done.");
+}
+
+
+// l'erosion ne modifie que les points espaces de reduced_output_step: la difference avec before_erosion est interpolee
+// sur les points intermediaires (le long des bords, elle ne depend que des points du bord)
+pub fn interpolate_erosion_delta(output: &mut Arr2d<f32>, before_erosion: &Arr2d<f32>, reduced_output_step: usize) {
+    if reduced_output_step == 1 {
+        return
+    }
+
+    let w = output.get_width();
+    let h = output.get_height();
+
+    let mut erosion_delta: Arr2d<f32> = Arr2d::zeros((w - 1) / reduced_output_step + 1, (h - 1) / reduced_output_step + 1);
+    for rx in 0..erosion_delta.get_width() {
+        for ry in 0..erosion_delta.get_height() {
+            let (x, y) = (rx * reduced_output_step, ry * reduced_output_step);
+            *erosion_delta.get_mut(rx, ry).unwrap() = output.get(x, y).unwrap() - before_erosion.get(x, y).unwrap();
+        }
+    }
+
+    let max_rx = erosion_delta.get_width() - 1;
+    let max_ry = erosion_delta.get_height() - 1;
+
+    for x in 0..w {
+        for y in 0..h {
+            if x % reduced_output_step == 0 && y % reduced_output_step == 0 {
+                continue
+            }
+
+            let rx = x / reduced_output_step;
+            let ry = y / reduced_output_step;
+
+            let d = bilinear_interpolation(
+                (x - rx * reduced_output_step) as f32 / reduced_output_step as f32, (y - ry * reduced_output_step) as f32 / reduced_output_step as f32,
+                *erosion_delta.get(rx, ry).unwrap(), *erosion_delta.get(rx, usize::min(ry + 1, max_ry)).unwrap(),
+                *erosion_delta.get(usize::min(rx + 1, max_rx), ry).unwrap(), *erosion_delta.get(usize::min(rx + 1, max_rx), usize::min(ry + 1, max_ry)).unwrap());
+
+            *output.get_mut(x, y).unwrap() += d;
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use serde_derive::Deserialize;
+
+use crate::{utils::{Arr2d, parallel_map}, settings::NormalMapOptions, terrain_analysis::{clamped_height, sobel_gradient}};
+
+
+// sens de la composante verte de la normal map
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalConvention {
+    #[default]
+    Opengl,  // le vert pointe vers le haut de l'image (y = 0)
+    Directx  // le vert pointe vers le bas de l'image
+}
+
+
+// calcul des derivees de la hauteur
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalFilter {
+    #[default]
+    Sobel,  // differences ponderees sur les 3 lignes (ou colonnes) voisines, moins sensible au bruit
+    CentralDifferences  // difference entre les deux voisins directs
+}
+
+
+// normales en espace tangent (x vers la droite, z hors du terrain) de chaque point de heightmap, unitaires
+// cell_size: largeur d'un point en unites de hauteur
+pub fn generate_normal_map(heightmap: &Arr2d<f32>, cell_size: f32, tileable: bool, normal_settings: &NormalMapOptions, threads: usize) -> Arr2d<[f32; 3]> {
+    println!("generating normal map...");
+
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
+
+    let get = |x: i32, y: i32| clamped_height(heightmap, x, y, tileable);
+
+    let columns: Vec<Vec<[f32; 3]>> = parallel_map(width, threads, |x| {
+        let x = x as i32;
+        (0..height as i32).map(|y| {
+            // derivees selon x et selon y (vers le bas de l'image), en unites de hauteur par unite de hauteur
+            let (dx, dy) = match normal_settings.normal_filter {
+                NormalFilter::Sobel => sobel_gradient(heightmap, x, y, cell_size, tileable),
+                NormalFilter::CentralDifferences => ((get(x + 1, y) - get(x - 1, y)) / (2.0 * cell_size), (get(x, y + 1) - get(x, y - 1)) / (2.0 * cell_size))
+            };
+
+            let green = match normal_settings.normal_convention {
+                NormalConvention::Opengl => dy,
+                NormalConvention::Directx => -dy
+            };
+            let length = (dx * dx + dy * dy + 1.0).sqrt();
+            [-dx / length, green / length, 1.0 / length]
+        }).collect()
+    });
+
+    let mut normals: Arr2d<[f32; 3]> = Arr2d::from_vec(vec![[0.0, 0.0, 1.0]; width * height], width, height);
+    for (x, column) in columns.into_iter().enumerate() {
+        for (y, normal) in column.into_iter().enumerate() {
+            *normals.get_mut(x, y).unwrap() = normal;
+        }
+    }
+
+    normals
+}
@@ -0,0 +1,300 @@
+use crate::{utils::{Arr2d, parallel_map}, erosion::ErosionMaps, hardness::Hardness, settings::ErosionOptions};
+
+
+// directions des tuyaux reliant un point a ses 4 voisins: gauche, droite, haut, bas
+const PIPES: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+
+// fraction du debit conservee d'une iteration a l'autre: sans frottement, l'eau accelere indefiniment sur une pente
+// et finit par osciller d'un point a l'autre
+const FLUX_DAMPING: f32 = 0.5;
+
+
+// hauteur d'eau en dessous de laquelle la vitesse n'est pas calculee (simple film de pluie)
+const MIN_DEPTH: f32 = 0.01;
+
+
+// acces aux voisins d'un point de la grille, sur un tore si le terrain se repete
+// (la derniere ligne / colonne est alors confondue avec la premiere)
+#[derive(Clone, Copy)]
+struct Grid {
+    width: usize,
+    height: usize,
+    tileable: bool
+}
+
+impl Grid {
+    fn neighbour(&self, x: usize, y: usize, dx: i32, dy: i32) -> Option<(usize, usize)> {
+        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+        if self.tileable {
+            Some((nx.rem_euclid(self.width as i32 - 1) as usize, ny.rem_euclid(self.height as i32 - 1) as usize))
+        } else if nx < 0 || ny < 0 || nx >= self.width as i32 || ny >= self.height as i32 {
+            None
+        } else {
+            Some((nx as usize, ny as usize))
+        }
+    }
+}
+
+
+// recopie les lignes calculees en parallele dans arr
+fn write_rows(arr: &mut Arr2d<f32>, rows: Vec<Vec<f32>>) {
+    let width = arr.get_width();
+    for (y, row) in rows.into_iter().enumerate() {
+        arr.get_mut_vec()[y * width..(y + 1) * width].copy_from_slice(&row);
+    }
+}
+
+
+// erosion hydraulique sur grille (modele des tuyaux virtuels): la pluie remplit chaque point, l'eau s'ecoule vers
+// les voisins plus bas a travers des tuyaux, et le champ de vitesse qui en resulte erode, transporte et depose le sediment
+// heightmap est modifiee sur place; cell_size est la distance entre deux points, en unites de hauteur
+// hardness est donnee aux points de heightmap, l'eau dissout moins la roche dure
+// les cartes d'eau, de vitesse et de sediment de la derniere iteration sont ecrites dans erosion_maps
+// chaque etape est calculee a partir de l'etat precedent: le resultat ne depend pas du nombre de threads
+pub fn pipe_erode(heightmap: &mut Arr2d<f32>, cell_size: f32, tileable: bool, erosion_maps: &mut ErosionMaps, hardness: &Hardness, threads: usize,
+    erosion_settings: &ErosionOptions) {
+
+    println!("starting pipe erosion, number of iterations: {}", erosion_settings.pipe_iterations);
+
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
+    let grid = Grid { width, height, tileable };
+
+    let dt = erosion_settings.pipe_time_step as f32;
+    let gravity = erosion_settings.gravity as f32;
+    let rainfall = erosion_settings.rainfall_rate as f32;
+    let evaporation = erosion_settings.pipe_evaporation_rate as f32;
+    let sediment_capacity = erosion_settings.pipe_sediment_capacity as f32;
+    let dissolving_rate = erosion_settings.pipe_dissolving_rate as f32;
+    let deposition_rate = erosion_settings.pipe_deposition_rate as f32;
+    let min_slope = erosion_settings.min_slope as f32;
+
+    let cell_area = cell_size * cell_size;
+    let max_speed = cell_size / dt;
+
+    let mut water: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut sediment: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut velocity_x: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut velocity_y: Arr2d<f32> = Arr2d::zeros(width, height);
+
+    // debit sortant de chaque point dans chacun des 4 tuyaux
+    let mut flux: Vec<Vec<[f32; 4]>> = vec![vec![[0.0; 4]; width]; height];
+
+    // volume d'eau sorti de chaque point et quantites erodees / deposees, sur toutes les iterations
+    let mut flow: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut eroded: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut deposited: Arr2d<f32> = Arr2d::zeros(width, height);
+
+    for iteration in 0..erosion_settings.pipe_iterations {
+
+        // pluie
+        for d in water.get_mut_vec().iter_mut() {
+            *d += rainfall * dt;
+        }
+
+        // debits: acceleres par la difference de niveau de l'eau, puis limites pour ne pas vider plus que l'eau presente
+        // hors du terrain (s'il ne se repete pas), l'eau s'ecoule au niveau du sol
+        flux = {
+            let (heightmap, water, flux) = (&*heightmap, &water, &flux);
+            parallel_map(height, threads, |y| {
+                (0..width).map(|x| {
+                    let d = *water.get(x, y).unwrap();
+                    let level = *heightmap.get(x, y).unwrap() + d;
+
+                    let mut f = [0.0; 4];
+                    for (k, (dx, dy)) in PIPES.iter().enumerate() {
+                        let level_difference = match grid.neighbour(x, y, *dx, *dy) {
+                            Some((nx, ny)) => level - *heightmap.get(nx, ny).unwrap() - *water.get(nx, ny).unwrap(),
+                            None => d
+                        };
+                        f[k] = f32::max(0.0, FLUX_DAMPING * flux[y][x][k] + dt * gravity * cell_size * level_difference);
+                    }
+
+                    let total: f32 = f.iter().sum();
+                    if total > 0.0 {
+                        let k = f32::min(1.0, d * cell_area / (total * dt));
+                        for v in f.iter_mut() {
+                            *v *= k;
+                        }
+                    }
+                    f
+                }).collect()
+            })
+        };
+
+        for (y, line) in flux.iter().enumerate() {
+            for (x, f) in line.iter().enumerate() {
+                *flow.get_mut(x, y).unwrap() += f.iter().sum::<f32>() * dt;
+            }
+        }
+
+        // hauteur d'eau et vitesse a partir des debits entrants et sortants
+        let rows: Vec<(Vec<f32>, Vec<f32>, Vec<f32>)> = {
+            let (water, flux) = (&water, &flux);
+            parallel_map(height, threads, |y| {
+                let mut new_water = Vec::with_capacity(width);
+                let mut vx = Vec::with_capacity(width);
+                let mut vy = Vec::with_capacity(width);
+
+                for x in 0..width {
+                    // debit recu du voisin dans la direction k (par son tuyau oppose, k ^ 1)
+                    let inflow = |k: usize| match grid.neighbour(x, y, PIPES[k].0, PIPES[k].1) {
+                        Some((nx, ny)) => flux[ny][nx][k ^ 1],
+                        None => 0.0
+                    };
+                    let outflow = flux[y][x];
+
+                    let volume_change = dt * ((0..4).map(inflow).sum::<f32>() - outflow.iter().sum::<f32>());
+                    let d = *water.get(x, y).unwrap();
+                    let d2 = f32::max(0.0, d + volume_change / cell_area);
+
+                    // eau traversant le point dans chaque direction
+                    let water_x = (inflow(0) - outflow[0] + outflow[1] - inflow(1)) / 2.0;
+                    let water_y = (inflow(2) - outflow[2] + outflow[3] - inflow(3)) / 2.0;
+                    let mean_depth = (d + d2) / 2.0;
+
+                    // l'eau ne peut pas parcourir plus d'un point par iteration
+                    new_water.push(d2);
+                    if mean_depth > MIN_DEPTH {
+                        vx.push((water_x / (cell_size * mean_depth)).clamp(-max_speed, max_speed));
+                        vy.push((water_y / (cell_size * mean_depth)).clamp(-max_speed, max_speed));
+                    } else {
+                        vx.push(0.0);
+                        vy.push(0.0);
+                    }
+                }
+
+                (new_water, vx, vy)
+            })
+        };
+
+        let (mut water_rows, mut vx_rows, mut vy_rows) = (Vec::with_capacity(height), Vec::with_capacity(height), Vec::with_capacity(height));
+        for (w, vx, vy) in rows {
+            water_rows.push(w);
+            vx_rows.push(vx);
+            vy_rows.push(vy);
+        }
+        let previous_water = water.clone();
+        write_rows(&mut water, water_rows);
+        write_rows(&mut velocity_x, vx_rows);
+        write_rows(&mut velocity_y, vy_rows);
+
+        // erosion et depot: la capacite depend de la pente du terrain et de la vitesse de l'eau
+        let rows: Vec<(Vec<f32>, Vec<f32>)> = {
+            let (heightmap, water, sediment, velocity_x, velocity_y) = (&*heightmap, &water, &sediment, &velocity_x, &velocity_y);
+            parallel_map(height, threads, |y| {
+                let mut new_height = Vec::with_capacity(width);
+                let mut new_sediment = Vec::with_capacity(width);
+
+                for x in 0..width {
+                    let b = *heightmap.get(x, y).unwrap();
+                    let around = |dx: i32, dy: i32| grid.neighbour(x, y, dx, dy).map(|(nx, ny)| *heightmap.get(nx, ny).unwrap()).unwrap_or(b);
+
+                    let slope_x = (around(1, 0) - around(-1, 0)) / (2.0 * cell_size);
+                    let slope_y = (around(0, 1) - around(0, -1)) / (2.0 * cell_size);
+                    let slope = f32::sqrt(slope_x * slope_x + slope_y * slope_y);
+                    let sin_tilt = slope / f32::sqrt(1.0 + slope * slope);
+
+                    let speed = f32::sqrt(velocity_x.get(x, y).unwrap().powi(2) + velocity_y.get(x, y).unwrap().powi(2));
+                    let capacity = sediment_capacity * f32::max(sin_tilt, min_slope) * speed * *water.get(x, y).unwrap();
+
+                    let s = *sediment.get(x, y).unwrap();
+                    if capacity > s {
+                        let eroded = f32::min(dissolving_rate * (capacity - s) * (1.0 - hardness.get(x, y, b)), f32::max(b, 0.0));
+                        new_height.push(b - eroded);
+                        new_sediment.push(s + eroded);
+                    } else {
+                        let deposited = deposition_rate * (s - capacity);
+                        new_height.push(b + deposited);
+                        new_sediment.push(s - deposited);
+                    }
+                }
+
+                (new_height, new_sediment)
+            })
+        };
+
+        let (mut height_rows, mut sediment_rows) = (Vec::with_capacity(height), Vec::with_capacity(height));
+        for (h, s) in rows {
+            height_rows.push(h);
+            sediment_rows.push(s);
+        }
+        for (y, row) in height_rows.iter().enumerate() {
+            for (x, h) in row.iter().enumerate() {
+                let change = h - *heightmap.get(x, y).unwrap();
+                if change < 0.0 {
+                    *eroded.get_mut(x, y).unwrap() -= change;
+                } else {
+                    *deposited.get_mut(x, y).unwrap() += change;
+                }
+            }
+        }
+        write_rows(heightmap, height_rows);
+        write_rows(&mut sediment, sediment_rows);
+
+        // transport du sediment par le courant: il suit l'eau dans les tuyaux, en proportion du volume d'eau deplace
+        // (la quantite totale est conservee, a part ce qui sort du terrain)
+        let rows: Vec<Vec<f32>> = {
+            let (sediment, previous_water, flux) = (&sediment, &previous_water, &flux);
+            let leaving = move |x: usize, y: usize, k: usize| {
+                let volume = *previous_water.get(x, y).unwrap() * cell_area;
+                if volume > 0.0 {
+                    *sediment.get(x, y).unwrap() * f32::min(1.0, flux[y][x][k] * dt / volume)
+                } else {
+                    0.0
+                }
+            };
+
+            parallel_map(height, threads, |y| {
+                (0..width).map(|x| {
+                    let mut s = *sediment.get(x, y).unwrap();
+                    for (k, (dx, dy)) in PIPES.iter().enumerate() {
+                        s -= leaving(x, y, k);
+                        if let Some((nx, ny)) = grid.neighbour(x, y, *dx, *dy) {
+                            s += leaving(nx, ny, k ^ 1);
+                        }
+                    }
+                    f32::max(0.0, s)
+                }).collect()
+            })
+        };
+        write_rows(&mut sediment, rows);
+
+        // evaporation
+        for d in water.get_mut_vec().iter_mut() {
+            *d *= f32::max(0.0, 1.0 - evaporation * dt);
+        }
+
+        if tileable {
+            for arr in [&mut *heightmap, &mut water, &mut sediment, &mut velocity_x, &mut velocity_y] {
+                arr.wrap_edges();
+            }
+        }
+
+        if (iteration + 1) % 50 == 0 {
+            println!("{} iterations done.", iteration + 1)
+        }
+    }
+
+    let mut velocity: Arr2d<f32> = Arr2d::zeros(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            *velocity.get_mut(x, y).unwrap() = f32::sqrt(velocity_x.get(x, y).unwrap().powi(2) + velocity_y.get(x, y).unwrap().powi(2));
+        }
+    }
+
+    if tileable {
+        for arr in [&mut flow, &mut eroded, &mut deposited] {
+            arr.wrap_edges();
+        }
+    }
+
+    erosion_maps.wetness = Some(water.clone());
+    erosion_maps.flow = Some(flow);
+    erosion_maps.eroded = Some(eroded);
+    erosion_maps.deposited = Some(deposited);
+    erosion_maps.water = Some(water);
+    erosion_maps.velocity = Some(velocity);
+    erosion_maps.sediment = Some(sediment);
+}
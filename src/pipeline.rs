@@ -0,0 +1,1014 @@
+use std::{collections::HashMap, hash::Hash, path::PathBuf, sync::{Arc, Mutex}};
+
+use image::GenericImageView;
+use rand_pcg::Mcg128Xsl64;
+
+use crate::{error::{Error, Result},
+    diamondsquare::{diamond_square_2, diamond_square_chunk, interpolate_chunk, generate_demisphere_heightmap, get_enclosing_power_of_two},
+    settings::{GenerationOptions, LaunchOptions, ErosionOptions, RiverOptions, WaterOptions, BiomeOptions, NormalMapOptions, TerrainAnalysisOptions, validate_materials},
+    sky_generation::{LightSpectrum, generate_sky_colormap},
+    terrain_texture_generation::generate_terrain_texture,
+    utils::{Arr2d, ColorMapArray, ReducedArrayWrapper, PI, derive_rng, next_random_number, parallel_map, CHUNK_HEIGHTMAP_STREAM, CHUNK_TEXTURE_STREAM, CHUNK_EROSION_STREAM},
+    noise::{HeightmapAlgorithm, generate_noise_heightmap, fill_noise_heightmap, interpolate_erosion_delta},
+    erosion::{ErosionMaps, HydraulicErosionModel, erosion_levels, erode_region, droplet_halo},
+    hardness::{Hardness, HardnessSource}, rivers::{RiverNetwork, extract_rivers},
+    water::{WaterMaps, generate_water}, biomes::{BiomeMaps, generate_biomes, wetness_scale},
+    materials::{Material, Palette}, normal_map::generate_normal_map, terrain_analysis::{TerrainAnalysis, analyse_terrain}};
+
+
+pub const DEMISPHERE_RADIUS: usize = 20 * 4;
+pub const DEMISPHERE_WIDTH: usize = DEMISPHERE_RADIUS * 8 * 2 + 1;
+pub const DEMISPHERE_HEIGHTMAP_WIDTH: usize = 128;
+
+// marge minimale, en points du mesh, prise sur les chunks voisins pour analyser le terrain pres des bords
+// (rayon de la fenetre 3x3 plus un point pour l'interpolation), augmentee jusqu'a tpi_radius
+const CHUNK_ANALYSIS_MARGIN: usize = 2;
+
+// nombre de valeurs gardees par chaque table du cache des chunks, voir TerrainPipeline::with_chunk_cache_capacity
+pub const CHUNK_CACHE_CAPACITY: usize = 64;
+
+// nombre maximal de chunks pris du cote d'ou vient la lumiere: les ombres plus longues (soleil tres bas) sont coupees
+const CHUNK_MAX_SHADOW_MARGIN: usize = 4;
+// composante de la direction du soleil en dessous de laquelle aucune ombre ne vient de ce cote
+const SUN_DIRECTION_EPSILON: f32 = 1e-3;
+
+
+// origine de la template utilisee pour initialiser le diamond-square
+pub enum TemplateSource {
+    Path(PathBuf),
+    Array(Arr2d<f32>)
+}
+
+
+pub struct SkyOutput {
+    pub heightmap: Option<Arr2d<f32>>,
+    pub colormap: Option<ColorMapArray>,
+
+    // couleurs calculees lors de la generation du ciel, utilisees pour eclairer le terrain
+    pub ambient_color: [f32; 3],
+    pub sun_color: [f32; 3]
+}
+
+
+pub struct TerrainOutput {
+    pub heightmap: Arr2d<f32>,  // taille GenerationOptions::get_terrain_size
+    pub mesh_heightmap: Arr2d<f32>,  // taille GenerationOptions::get_mesh_size
+    pub analysis: Option<TerrainAnalysis>,  // cartes de meme taille que mesh_heightmap, None sans texture ni generate_terrain_analysis
+    pub colormap: Option<ColorMapArray>,
+    pub erosion_maps: ErosionMaps,  // cartes a la resolution du premier niveau de l'erosion, voir TerrainPipeline::erosion_map_size
+    pub rivers: Option<RiverNetwork>,  // masque de meme taille que heightmap, None si les rivieres sont desactivees
+    pub water: Option<WaterMaps>,  // cartes de meme taille que heightmap, None sans mer ni lacs
+    pub biomes: Option<BiomeMaps>,  // cartes de meme taille que heightmap, None si les biomes sont desactives
+    pub shadowmask: Option<Arr2d<f32>>,  // meme taille que heightmap, 1 eclaire et 0 dans l'ombre, None sans texture
+    pub normalmap: Option<Arr2d<[f32; 3]>>,  // normales unitaires, meme taille que heightmap, None si la normal map est desactivee
+    pub skyviewmap: Option<Arr2d<f32>>  // part du ciel visible, meme taille que heightmap, None sans texture ou sans occlusion ambiante
+}
+
+
+pub struct PipelineOutput {
+    pub sky: SkyOutput,
+    pub terrain: Option<TerrainOutput>
+}
+
+
+// table de taille limitee: une fois pleine, la valeur utilisee le moins recemment est retiree pour faire de la place
+struct LruCache<K, V> {
+    capacity: usize,
+    clock: u64,  // incremente a chaque acces, date de la derniere utilisation des valeurs
+    entries: HashMap<K, (u64, V)>
+}
+
+impl<K: Hash + Eq + Copy, V: Clone> LruCache<K, V> {
+    fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache { capacity: usize::max(capacity, 1), clock: 0, entries: HashMap::new() }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|(last_use, value)| {
+            *last_use = clock;
+            value.clone()
+        })
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, (last_use, _))| *last_use).map(|(key, _)| *key) {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(key, (self.clock, value));
+    }
+}
+
+
+// erosion, a un niveau de l'erosion, de la zone d'un chunk entouree d'une marge de halo points du niveau prise sur ses voisins
+struct ChunkErosion {
+    heights: Arr2d<f32>,  // points du niveau
+    maps: ErosionMaps,
+    halo: usize
+}
+
+
+// heightmap erodee d'un chunk et cartes de l'erosion
+type ChunkData = (Arc<Arr2d<f32>>, Arc<ErosionMaps>);
+
+
+// valeurs reutilisees d'un appel a generate_chunk a l'autre, qui ne dependent que des parametres
+// les chunks retires des tables sont recalcules a l'identique s'ils sont de nouveau demandes
+struct ChunkCache {
+    chunks: LruCache<(i32, i32), ChunkData>,
+    level_heightmaps: LruCache<(usize, i32, i32), Arc<Arr2d<f32>>>,  // par (niveau, cx, cy), voir chunk_level_heightmap
+    zones: LruCache<(usize, i32, i32), Arc<ChunkErosion>>,
+    hardness_image: Option<Arc<Arr2d<f32>>>,
+    wetness_scale: Option<f32>,
+    sky_colors: Option<([f32; 3], [f32; 3])>
+}
+
+impl ChunkCache {
+    fn new(capacity: usize) -> ChunkCache {
+        ChunkCache {
+            chunks: LruCache::new(capacity),
+            level_heightmaps: LruCache::new(capacity),
+            zones: LruCache::new(capacity),
+            hardness_image: None,
+            wetness_scale: None,
+            sky_colors: None
+        }
+    }
+}
+
+
+// enchaine les etapes de generation: ciel, heightmap (avec erosion), analyse du terrain, texture
+pub struct TerrainPipeline {
+    options: GenerationOptions,
+    erosion: ErosionOptions,
+    rivers: RiverOptions,
+    water: WaterOptions,
+    biomes: BiomeOptions,
+    normal_map: NormalMapOptions,
+    terrain_analysis: TerrainAnalysisOptions,
+    materials: Vec<Material>,
+    template: TemplateSource,
+
+    generate_sky_heightmap: bool,
+    generate_sky_texture: bool,
+    generate_terrain_heightmap: bool,
+    generate_terrain_texture: bool,
+
+    chunk_cache_capacity: usize,
+    chunk_cache: Mutex<ChunkCache>
+}
+
+impl TerrainPipeline {
+    pub fn new(options: GenerationOptions) -> TerrainPipeline {
+        TerrainPipeline {
+            options,
+            erosion: ErosionOptions::default(),
+            rivers: RiverOptions::default(),
+            water: WaterOptions::default(),
+            biomes: BiomeOptions::default(),
+            normal_map: NormalMapOptions::default(),
+            terrain_analysis: TerrainAnalysisOptions::default(),
+            materials: vec![],
+            template: TemplateSource::Path(PathBuf::from("template.png")),
+            generate_sky_heightmap: true,
+            generate_sky_texture: true,
+            generate_terrain_heightmap: true,
+            generate_terrain_texture: true,
+            chunk_cache_capacity: CHUNK_CACHE_CAPACITY,
+            chunk_cache: Mutex::new(ChunkCache::new(CHUNK_CACHE_CAPACITY))
+        }
+    }
+
+    pub fn with_template_path<P: Into<PathBuf>>(mut self, path: P) -> TerrainPipeline {
+        self.template = TemplateSource::Path(path.into());
+        self
+    }
+
+    pub fn with_template(mut self, template: Arr2d<f32>) -> TerrainPipeline {
+        self.template = TemplateSource::Array(template);
+        self
+    }
+
+    // reprend les etapes activees dans la section [launch_options]
+    pub fn with_launch_options(mut self, launch_options: &LaunchOptions) -> TerrainPipeline {
+        self.generate_sky_heightmap = launch_options.generate_sky_heightmap;
+        self.generate_sky_texture = launch_options.generate_sky_texture;
+        self.generate_terrain_heightmap = launch_options.generate_terrain_heightmap;
+        self.generate_terrain_texture = launch_options.generate_terrain_texture;
+        self.clear_chunk_cache();
+        self
+    }
+
+    // reprend les parametres de la section [erosion]
+    pub fn with_erosion_options(mut self, erosion: &ErosionOptions) -> TerrainPipeline {
+        self.erosion = erosion.clone();
+        self.clear_chunk_cache();
+        self
+    }
+
+    // reprend les parametres de la section [rivers], les rivieres ne sont pas generees pour les chunks
+    pub fn with_river_options(mut self, rivers: &RiverOptions) -> TerrainPipeline {
+        self.rivers = rivers.clone();
+        self
+    }
+
+    // reprend les parametres de la section [water], les chunks n'ont que la mer
+    pub fn with_water_options(mut self, water: &WaterOptions) -> TerrainPipeline {
+        self.water = water.clone();
+        self
+    }
+
+    // reprend les parametres de la section [biomes]
+    pub fn with_biome_options(mut self, biomes: &BiomeOptions) -> TerrainPipeline {
+        self.biomes = biomes.clone();
+        self
+    }
+
+    // reprend les parametres de la section [normal_map]
+    pub fn with_normal_map_options(mut self, normal_map: &NormalMapOptions) -> TerrainPipeline {
+        self.normal_map = normal_map.clone();
+        self
+    }
+
+    // reprend les parametres de la section [terrain_analysis]
+    pub fn with_terrain_analysis_options(mut self, terrain_analysis: &TerrainAnalysisOptions) -> TerrainPipeline {
+        self.terrain_analysis = terrain_analysis.clone();
+        self
+    }
+
+    // reprend la section [[materials]], vide: roche et neige
+    pub fn with_materials(mut self, materials: &[Material]) -> TerrainPipeline {
+        self.materials = materials.to_vec();
+        self
+    }
+
+    // nombre de chunks (heightmaps et etapes de leur erosion) gardes en memoire entre deux appels a generate_chunk
+    pub fn with_chunk_cache_capacity(mut self, capacity: usize) -> TerrainPipeline {
+        self.chunk_cache_capacity = capacity;
+        self.clear_chunk_cache();
+        self
+    }
+
+    pub fn sky_heightmap(mut self, enabled: bool) -> TerrainPipeline {
+        self.generate_sky_heightmap = enabled;
+        self
+    }
+
+    pub fn sky_texture(mut self, enabled: bool) -> TerrainPipeline {
+        self.generate_sky_texture = enabled;
+        self.clear_chunk_cache();
+        self
+    }
+
+    pub fn terrain_heightmap(mut self, enabled: bool) -> TerrainPipeline {
+        self.generate_terrain_heightmap = enabled;
+        self
+    }
+
+    // uniquement pris en compte si la generation de la heightmap du terrain est activee
+    pub fn terrain_texture(mut self, enabled: bool) -> TerrainPipeline {
+        self.generate_terrain_texture = enabled;
+        self
+    }
+
+    pub fn options(&self) -> &GenerationOptions {
+        &self.options
+    }
+
+    pub fn erosion_options(&self) -> &ErosionOptions {
+        &self.erosion
+    }
+
+    pub fn river_options(&self) -> &RiverOptions {
+        &self.rivers
+    }
+
+    pub fn water_options(&self) -> &WaterOptions {
+        &self.water
+    }
+
+    pub fn biome_options(&self) -> &BiomeOptions {
+        &self.biomes
+    }
+
+    pub fn normal_map_options(&self) -> &NormalMapOptions {
+        &self.normal_map
+    }
+
+    pub fn terrain_analysis_options(&self) -> &TerrainAnalysisOptions {
+        &self.terrain_analysis
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn run(&self) -> Result<PipelineOutput> {
+        self.options.validate()?;
+        self.erosion.validate()?;
+        self.rivers.validate()?;
+        self.water.validate()?;
+        self.biomes.validate()?;
+        self.terrain_analysis.validate()?;
+        validate_materials(&self.materials)?;
+
+        let sky = self.run_sky()?;
+
+        let terrain = if self.generate_terrain_heightmap {
+            Some(self.run_terrain(&sky.ambient_color, &sky.sun_color)?)
+        } else {
+            None
+        };
+
+        Ok(PipelineOutput { sky, terrain })
+    }
+
+    pub fn run_sky(&self) -> Result<SkyOutput> {
+        let mut ambient_color = [1.0, 1.0, 1.0];
+        let mut sun_color = [1.0, 1.0, 1.0];
+
+        let heightmap = if self.generate_sky_heightmap {
+            let mut demisphere_heightmap: Arr2d<f32> = Arr2d::zeros(DEMISPHERE_HEIGHTMAP_WIDTH, DEMISPHERE_HEIGHTMAP_WIDTH);
+            generate_demisphere_heightmap(&mut demisphere_heightmap, DEMISPHERE_HEIGHTMAP_WIDTH);
+            Some(demisphere_heightmap)
+        } else {
+            None
+        };
+
+        let colormap = if self.generate_sky_texture {
+            let mut demisphere_colormap: ColorMapArray = ColorMapArray::new_empty(DEMISPHERE_WIDTH, DEMISPHERE_WIDTH);
+
+            let sun_angle = self.options.sun_angle * PI / 180.0;
+
+            let mut incident_light_spectrum = LightSpectrum::new();
+
+            generate_sky_colormap(&mut demisphere_colormap, DEMISPHERE_WIDTH,
+                self.options.planet_radius, self.options.atmosphere_radius, &mut incident_light_spectrum, sun_angle,
+                self.options.sun_size, self.options.ambient_sky_light, &mut ambient_color, &mut sun_color
+            );
+            Some(demisphere_colormap)
+        } else {
+            None
+        };
+
+        Ok(SkyOutput { heightmap, colormap, ambient_color, sun_color })
+    }
+
+    // charge la template et verifie qu'elle couvre au moins 2^template_power_of_two + 1 pixels de cote
+    pub fn load_template(&self) -> Result<Arr2d<f32>> {
+        let settings = &self.options;
+        let w = 2_usize.pow(settings.template_power_of_two) + 1;
+        let setting = format!("template_power_of_two = {}", settings.template_power_of_two);
+
+        match &self.template {
+            TemplateSource::Path(path) => {
+                let template_img = image::io::Reader::open(path)
+                    .map_err(|e| Error::TemplateIo { path: path.clone(), source: image::ImageError::IoError(e) })?
+                    .decode()
+                    .map_err(|source| Error::TemplateIo { path: path.clone(), source })?;
+
+                let found = (template_img.width() as usize, template_img.height() as usize);
+                if found.0 < w || found.1 < w {
+                    return Err(Error::DimensionMismatch { name: path.display().to_string(), setting, expected: (w, w), found })
+                }
+
+                Ok(Arr2d::from_dynamic_image(template_img, settings.max_terrain_height, w, w))
+            }
+            TemplateSource::Array(template) => {
+                let found = (template.get_width(), template.get_height());
+                if found != (w, w) {
+                    return Err(Error::DimensionMismatch { name: "template array".to_string(), setting, expected: (w, w), found })
+                }
+
+                Ok(template.clone())
+            }
+        }
+    }
+
+    pub fn run_terrain(&self, ambient_color: &[f32; 3], sun_color: &[f32; 3]) -> Result<TerrainOutput> {
+        let settings = &self.options;
+
+        let mut rng = Mcg128Xsl64::new(settings.seed as u128);
+
+        let (w, h) = settings.get_terrain_size();
+        let (mesh_w, mesh_h) = settings.get_mesh_size();
+
+        let n_iteration_difference = settings.terrain_power_of_two - settings.mesh_power_of_two;
+
+        // stockee transposee, voir generate_terrain_texture
+        let mut colormap: ColorMapArray = ColorMapArray::new_empty(h, w);
+        let mut erosion_maps = ErosionMaps::default();
+
+        // l'erosion est appliquee pendant la generation, a la resolution du mesh
+        let (mut heightmap, hardness) = if settings.heightmap_algorithm == HeightmapAlgorithm::DiamondSquare {
+            let template = self.load_template()?;
+
+            // le diamond-square travaille sur un carre de 2^n + 1 contenant le terrain, qui est ensuite recadre
+            let n = get_enclosing_power_of_two(w, h, settings);
+            let enclosing_w = 2_usize.pow(n as u32) + 1;
+            let scaling = usize::pow(2, n as u32 - settings.template_power_of_two);
+
+            let mut enclosing_heightmap: Arr2d<f32> = Arr2d::init_with_value(enclosing_w, enclosing_w, 10.0);
+            let hardness = self.hardness(enclosing_w, enclosing_w, (0, 0))?;
+
+            diamond_square_2(&template, &mut enclosing_heightmap,
+                n, scaling, settings.irregularity, n_iteration_difference, &mut rng, &mut colormap,
+                &mut erosion_maps, &hardness, settings, &self.erosion);
+
+            if (enclosing_w, enclosing_w) == (w, h) {
+                (enclosing_heightmap, hardness)
+            } else {
+                (enclosing_heightmap.crop(w, h), hardness)
+            }
+        } else {
+            let mut heightmap: Arr2d<f32> = Arr2d::init_with_value(w, h, 10.0);
+            let hardness = self.hardness(w, h, (0, 0))?;
+            generate_noise_heightmap(&mut heightmap, n_iteration_difference, &mut rng, &mut colormap, &mut erosion_maps, &hardness, settings, &self.erosion);
+            (heightmap, hardness)
+        };
+
+        // les rivieres suivent l'ecoulement sur tout le terrain, a pleine resolution, et sont creusees apres l'erosion
+        let rivers = if self.rivers.generate_rivers {
+            Some(extract_rivers(&mut heightmap, settings.tileable, &self.rivers))
+        } else {
+            None
+        };
+
+        // la mer et les lacs remplissent le terrain une fois creuse par les rivieres
+        let water = if self.water.is_enabled() {
+            Some(generate_water(&heightmap, settings.tileable, true, &self.water))
+        } else {
+            None
+        };
+
+        let normalmap = if self.normal_map.generate_normal_map {
+            Some(generate_normal_map(&heightmap, settings.get_cell_size(), settings.tileable, &self.normal_map, settings.get_threads()))
+        } else {
+            None
+        };
+
+        let mesh_heightmap = heightmap.resample(mesh_w, mesh_h);
+
+        // les cartes de l'erosion sont a la resolution du diamond-square avant recadrage: meme traitement que la heightmap
+        let step = self.erosion_levels()[0].0;
+        let (map_w, map_h) = self.erosion_map_size();
+        let mut erosion_maps = erosion_maps.map(|map| map.crop((w - 1) / step + 1, (h - 1) / step + 1).resample(map_w, map_h));
+        erosion_maps.hardness = hardness.evaluate(&heightmap).map(|map| map.resample(map_w, map_h));
+
+        let biomes = if self.biomes.generate_biomes {
+            Some(generate_biomes(&heightmap, 0, water.as_ref(), rivers.as_ref().map(|rivers| &rivers.mask), erosion_maps.wetness.as_ref(),
+                wetness_scale(erosion_maps.wetness.as_ref()), settings.tileable, settings, &self.biomes, &self.water))
+        } else {
+            None
+        };
+
+        let analysis = if self.generate_terrain_texture || self.terrain_analysis.generate_terrain_analysis {
+            Some(analyse_terrain(&mesh_heightmap, settings.get_mesh_cell_size(), self.terrain_analysis.tpi_radius, settings.tileable,
+                settings.get_threads()))
+        } else {
+            None
+        };
+
+        if !self.generate_terrain_texture {
+            return Ok(TerrainOutput { heightmap, mesh_heightmap, analysis, colormap: None, erosion_maps, rivers, water, biomes, shadowmask: None,
+                normalmap, skyviewmap: None })
+        }
+
+        let (shadowmask, skyviewmap) = generate_terrain_texture(&mut colormap, &mut heightmap, analysis.as_ref().unwrap(), &erosion_maps, water.as_ref(), biomes.as_ref(),
+            self.palette(w, h, (0, 0)).as_ref(), settings.get_cell_size(),
+            settings.shadow_direction, settings.sun_azimuth.map(|azimuth| azimuth * PI / 180.0), settings.sun_angle * PI / 180.0, &mut rng, ambient_color, sun_color,
+            settings);
+
+        Ok(TerrainOutput { heightmap, mesh_heightmap, analysis, colormap: Some(colormap), erosion_maps, rivers, water, biomes,
+            shadowmask: Some(shadowmask), normalmap, skyviewmap })
+    }
+
+    // genere le chunk (cx, cy) d'un monde infini, dont la taille est celle du terrain (GenerationOptions::get_terrain_size)
+    // le chunk (cx, cy) commence au point (cx * (width - 1), cy * (height - 1)) du monde: deux chunks voisins
+    // partagent leur bord commun, et le resultat ne depend pas de l'ordre dans lequel les chunks sont generes
+    // les heightmaps des chunks voisins sont generees (et gardees en memoire) pour raccorder l'analyse du terrain et les ombres
+    // les rivieres et les lacs ne sont pas generes: l'ecoulement d'un chunk depend de tout le monde en amont
+    pub fn generate_chunk(&self, cx: i32, cy: i32) -> Result<TerrainOutput> {
+        let settings = &self.options;
+        settings.validate_chunks()?;
+
+        let (w, h) = settings.get_terrain_size();
+        let (mesh_w, mesh_h) = settings.get_mesh_size();
+        let step = settings.get_mesh_step();
+
+        let heightmap = self.chunk_heightmap(cx, cy)?;
+        let mesh_heightmap = heightmap.resample(mesh_w, mesh_h);
+        let erosion_maps = (*self.chunk_erosion_maps(cx, cy)?).clone();
+
+        // les derivees au bord du chunk utilisent les points des chunks voisins
+        let normalmap = if self.normal_map.generate_normal_map {
+            let region = self.chunk_region(cx, cy, 1, 1, w + 2, h + 2)?;
+            Some(generate_normal_map(&region, settings.get_cell_size(), false, &self.normal_map, settings.get_threads()).extract(1, 1, w, h))
+        } else {
+            None
+        };
+
+        if !self.generate_terrain_texture {
+            let water = self.water.sea_level.map(|_| generate_water(&heightmap, false, false, &self.water));
+            let biomes = if self.biomes.generate_biomes {
+                Some(generate_biomes(&heightmap, cy as i64 * (h - 1) as i64, water.as_ref(), None, erosion_maps.wetness.as_ref(), self.chunk_wetness_scale()?,
+                    false, settings, &self.biomes, &self.water))
+            } else {
+                None
+            };
+            let analysis = if self.terrain_analysis.generate_terrain_analysis {
+                let margin = self.chunk_analysis_margin();
+                let region = self.chunk_region(cx, cy, margin, margin, w + 2 * margin, h + 2 * margin)?;
+                let region_mesh = region.resample(mesh_w + 2 * margin / step, mesh_h + 2 * margin / step);
+                Some(analyse_terrain(&region_mesh, settings.get_mesh_cell_size(), self.terrain_analysis.tpi_radius, false,
+                    settings.get_threads()).extract(margin / step, margin / step, mesh_w, mesh_h))
+            } else {
+                None
+            };
+            return Ok(TerrainOutput { heightmap: (*heightmap).clone(), mesh_heightmap, analysis, colormap: None, erosion_maps, rivers: None,
+                water, biomes, shadowmask: None, normalmap, skyviewmap: None })
+        }
+
+        let (ambient_color, sun_color) = self.chunk_sky_colors()?;
+
+        // la texture est calculee sur le chunk entoure de marges prises sur ses voisins: une petite marge pour l'analyse du terrain
+        // et l'occlusion ambiante, et du cote d'ou vient la lumiere, assez de chunks pour contenir l'ombre la plus longue projetee par les voisins
+        // (une hauteur de max_terrain_height donne une ombre de max_terrain_height / (tan(sun_angle) cell_size) points, voir add_shadow)
+        // (le long de sun_azimuth, l'ombre est projetee sur les deux axes)
+        let shadow_length = settings.max_terrain_height / ((settings.sun_angle * PI / 180.0).tan() * settings.get_cell_size());
+        let shadow_chunks = |component: f32, chunk_length: usize| {
+            (shadow_length * component.abs() / chunk_length as f32).ceil().clamp(1.0, CHUNK_MAX_SHADOW_MARGIN as f32) as usize
+        };
+
+        let analysis_margin = self.chunk_analysis_margin();
+        // l'horizon de l'occlusion ambiante est cherche jusqu'a ambient_occlusion_radius points, dans toutes les directions
+        // (marges multiples du pas du mesh, pour que le mesh de la region contienne celui du chunk)
+        let occlusion_margin = if settings.ambient_occlusion_rays > 0 { (settings.ambient_occlusion_radius / step as f32).ceil() as usize * step } else { 0 };
+        let margin = usize::max(analysis_margin, occlusion_margin);
+        let (mut left, mut top, mut right, mut bottom) = (margin, margin, margin, margin);
+        if let Some(azimuth) = settings.sun_azimuth {
+            // direction du soleil dans la heightmap: x vers la droite, y vers le bas
+            let (sun_x, sun_y) = ((azimuth * PI / 180.0).sin(), -(azimuth * PI / 180.0).cos());
+            if sun_x < -SUN_DIRECTION_EPSILON {
+                left = left.max(shadow_chunks(sun_x, w - 1) * (w - 1));
+            } else if sun_x > SUN_DIRECTION_EPSILON {
+                right = right.max(shadow_chunks(sun_x, w - 1) * (w - 1));
+            }
+            if sun_y < -SUN_DIRECTION_EPSILON {
+                top = top.max(shadow_chunks(sun_y, h - 1) * (h - 1));
+            } else if sun_y > SUN_DIRECTION_EPSILON {
+                bottom = bottom.max(shadow_chunks(sun_y, h - 1) * (h - 1));
+            }
+        } else {
+            match settings.shadow_direction {
+                0 => left = left.max(shadow_chunks(1.0, w - 1) * (w - 1)),
+                1 => top = top.max(shadow_chunks(1.0, h - 1) * (h - 1)),
+                2 => right = right.max(shadow_chunks(1.0, w - 1) * (w - 1)),
+                _ => bottom = bottom.max(shadow_chunks(1.0, h - 1) * (h - 1))
+            }
+        }
+
+        let region_w = left + w + right;
+        let region_h = top + h + bottom;
+        let mut region = self.chunk_region(cx, cy, left, top, region_w, region_h)?;
+
+        let region_mesh = region.resample((region_w - 1) / step + 1, (region_h - 1) / step + 1);
+        let map_step = self.erosion_levels()[0].0;
+        let region_erosion_maps = self.chunk_region_erosion_maps(cx, cy, left / map_step, top / map_step, (region_w - 1) / map_step + 1,
+            (region_h - 1) / map_step + 1)?;
+        let region_analysis = analyse_terrain(&region_mesh, settings.get_mesh_cell_size(), self.terrain_analysis.tpi_radius, false,
+            settings.get_threads());
+
+        let mut region_colormap: ColorMapArray = ColorMapArray::new_empty(region_h, region_w);
+        let mut rng = derive_rng(settings.seed, &[CHUNK_TEXTURE_STREAM, cx as i64, cy as i64]);
+
+        let region_water = self.water.sea_level.map(|_| generate_water(&region, false, false, &self.water));
+        // l'humidite apportee par l'eau ne tient compte que de l'eau presente dans les marges
+        let region_biomes = if self.biomes.generate_biomes {
+            Some(generate_biomes(&region, cy as i64 * (h - 1) as i64 - top as i64, region_water.as_ref(), None, region_erosion_maps.wetness.as_ref(),
+                self.chunk_wetness_scale()?, false, settings, &self.biomes, &self.water))
+        } else {
+            None
+        };
+
+        let (region_shadowmask, region_skyviewmap) = generate_terrain_texture(&mut region_colormap, &mut region, &region_analysis, &region_erosion_maps, region_water.as_ref(), region_biomes.as_ref(),
+            self.palette(region_w, region_h, ((cx as i64) * (w - 1) as i64 - left as i64, (cy as i64) * (h - 1) as i64 - top as i64)).as_ref(), settings.get_cell_size(),
+            settings.shadow_direction, settings.sun_azimuth.map(|azimuth| azimuth * PI / 180.0), settings.sun_angle * PI / 180.0, &mut rng, &ambient_color,
+            &sun_color, settings);
+
+        let analysis = region_analysis.extract(left / step, top / step, mesh_w, mesh_h);
+        let colormap = region_colormap.extract(top, left, h, w);  // stockee transposee
+        let water = region_water.map(|water| water.extract(left, top, w, h));
+        let biomes = region_biomes.map(|biomes| biomes.extract(left, top, w, h));
+        let shadowmask = region_shadowmask.extract(left, top, w, h);
+        let skyviewmap = region_skyviewmap.map(|map| map.extract(left, top, w, h));
+
+        Ok(TerrainOutput { heightmap: (*heightmap).clone(), mesh_heightmap, analysis: Some(analysis), colormap: Some(colormap), erosion_maps,
+            rivers: None, water, biomes, shadowmask: Some(shadowmask), normalmap, skyviewmap })
+    }
+
+    // heightmap erodee du chunk (cx, cy), sans texture
+    pub fn chunk_heightmap(&self, cx: i32, cy: i32) -> Result<Arc<Arr2d<f32>>> {
+        Ok(self.chunk_data(cx, cy)?.0)
+    }
+
+    // heightmap erodee et cartes de l'erosion du chunk (cx, cy)
+    // chaque niveau de l'erosion est applique a la zone du chunk entouree d'une marge prise sur ses voisins (voir
+    // chunk_zone_erosion); les zones de deux chunks voisins se recouvrent et y sont melangees (voir blend_chunk_zones):
+    // un point du monde recoit la meme valeur quel que soit le chunk genere, et l'erosion est continue d'un chunk a l'autre
+    fn chunk_data(&self, cx: i32, cy: i32) -> Result<ChunkData> {
+        if let Some(data) = self.chunk_cache.lock().unwrap().chunks.get(&(cx, cy)) {
+            return Ok(data)
+        }
+
+        let settings = &self.options;
+        settings.validate_chunks()?;
+        self.erosion.validate()?;
+
+        println!("generating chunk ({}, {})", cx, cy);
+
+        let (w, h) = settings.get_terrain_size();
+        let levels = self.erosion_levels();
+
+        let mut erosion_maps = ErosionMaps::default();
+        for (level, (step, _)) in levels.iter().enumerate() {
+            let zones = self.chunk_erosion_zones(level, cx, cy)?;
+            let maps = blend_chunk_zones(&zones, cx, cy, (w - 1) / step, (h - 1) / step, |zone| zone.maps.named_maps().into_iter().map(|(_, map)| map).collect());
+
+            let mut level_maps = zones[0].2.maps.clone();
+            for (level_map, map) in level_maps.maps_mut().into_iter().zip(maps) {
+                *level_map = map;
+            }
+
+            if level == 0 {
+                erosion_maps = level_maps;
+            } else {
+                erosion_maps.add_level(&level_maps);
+            }
+        }
+
+        let heightmap = self.chunk_level_heightmap(levels.len(), cx, cy)?;
+
+        let (map_w, map_h) = self.erosion_map_size();
+        let hardness = self.hardness(w, h, (cx as i64 * (w - 1) as i64, cy as i64 * (h - 1) as i64))?;
+        erosion_maps.hardness = hardness.evaluate(&heightmap).map(|map| map.resample(map_w, map_h));
+
+        let data = (heightmap, Arc::new(erosion_maps));
+        self.chunk_cache.lock().unwrap().chunks.insert((cx, cy), data.clone());
+        Ok(data)
+    }
+
+    // niveaux de l'erosion du terrain et des chunks, voir erosion_levels
+    fn erosion_levels(&self) -> Vec<(usize, ErosionOptions)> {
+        erosion_levels(self.options.terrain_power_of_two - self.options.mesh_power_of_two, &self.erosion)
+    }
+
+    // taille des cartes de l'erosion: celle du mesh, ou celle du terrain quand le premier niveau de l'erosion est a pleine
+    // resolution (modele des tuyaux sans ErosionOptions::erosion_level_iterations)
+    pub fn erosion_map_size(&self) -> (usize, usize) {
+        if self.erosion_levels()[0].0 == 1 {
+            self.options.get_terrain_size()
+        } else {
+            self.options.get_mesh_size()
+        }
+    }
+
+    // heightmap du chunk (cx, cy) avant le niveau level de l'erosion (seuls les points espaces du pas du niveau sont
+    // erodes), ou apres le dernier niveau si level est le nombre de niveaux
+    fn chunk_level_heightmap(&self, level: usize, cx: i32, cy: i32) -> Result<Arc<Arr2d<f32>>> {
+        if let Some(heightmap) = self.chunk_cache.lock().unwrap().level_heightmaps.get(&(level, cx, cy)) {
+            return Ok(heightmap)
+        }
+
+        let settings = &self.options;
+        let (w, h) = settings.get_terrain_size();
+        let levels = self.erosion_levels();
+        let diamond_square = settings.heightmap_algorithm == HeightmapAlgorithm::DiamondSquare;
+
+        let heightmap = if level == 0 {
+            let mut heightmap: Arr2d<f32> = Arr2d::init_with_value(w, h, 10.0);
+            if diamond_square {
+                // generateur propre au chunk, utilise pour le bruit interieur
+                let mut rng = derive_rng(settings.seed, &[CHUNK_HEIGHTMAP_STREAM, cx as i64, cy as i64]);
+                diamond_square_chunk(&mut heightmap, cx, cy, settings.terrain_power_of_two - settings.mesh_power_of_two, &mut rng, settings);
+                // le premier niveau de l'erosion peut etre plus fin que le mesh (modele des tuyaux)
+                interpolate_chunk(&mut heightmap, settings.get_mesh_step(), levels[0].0);
+            } else {
+                // le bruit est le meme pour tous les chunks, seule sa position dans le monde change
+                let origin = (cx as i64 * (w - 1) as i64, cy as i64 * (h - 1) as i64);
+                fill_noise_heightmap(&mut heightmap, origin, &mut Mcg128Xsl64::new(settings.seed as u128), settings);
+            }
+            heightmap
+        } else {
+            let step = levels[level - 1].0;
+            let before_erosion = self.chunk_level_heightmap(level - 1, cx, cy)?;
+
+            let zones = self.chunk_erosion_zones(level - 1, cx, cy)?;
+            let eroded = blend_chunk_zones(&zones, cx, cy, (w - 1) / step, (h - 1) / step, |zone| vec![&zone.heights]).remove(0);
+
+            let mut heightmap = (*before_erosion).clone();
+            for rx in 0..eroded.get_width() {
+                for ry in 0..eroded.get_height() {
+                    *heightmap.get_mut(rx * step, ry * step).unwrap() = *eroded.get(rx, ry).unwrap();
+                }
+            }
+
+            // comme diamond_square_steps apres l'erosion, ou erode_noise_heightmap
+            if diamond_square {
+                interpolate_chunk(&mut heightmap, step, levels.get(level).map_or(1, |(next_step, _)| *next_step));
+            } else {
+                interpolate_erosion_delta(&mut heightmap, &before_erosion, step);
+            }
+            heightmap
+        };
+
+        let heightmap = Arc::new(heightmap);
+        self.chunk_cache.lock().unwrap().level_heightmaps.insert((level, cx, cy), heightmap.clone());
+        Ok(heightmap)
+    }
+
+    // zones erodees au niveau level du chunk (cx, cy) et de ses 8 voisins, dans l'ordre des chunks du monde (ligne par ligne)
+    fn chunk_erosion_zones(&self, level: usize, cx: i32, cy: i32) -> Result<Vec<(i32, i32, Arc<ChunkErosion>)>> {
+        let neighbours: Vec<(i32, i32)> = (-1..=1).flat_map(|dcy| (-1..=1).map(move |dcx| (cx + dcx, cy + dcy))).collect();
+
+        let mut zones: Vec<Option<Arc<ChunkErosion>>> = {
+            let mut cache = self.chunk_cache.lock().unwrap();
+            neighbours.iter().map(|(x, y)| cache.zones.get(&(level, *x, *y))).collect()
+        };
+        let missing: Vec<usize> = (0..zones.len()).filter(|k| zones[*k].is_none()).collect();
+
+        if !missing.is_empty() {
+            // les heightmaps des chunks couverts par les zones sont calculees avant, pour ne pas l'etre plusieurs fois en parallele
+            for k in missing.iter() {
+                let (x, y) = neighbours[*k];
+                for (dcx, dcy) in (-1..=1).flat_map(|dcy| (-1..=1).map(move |dcx| (dcx, dcy))) {
+                    self.chunk_level_heightmap(level, x + dcx, y + dcy)?;
+                }
+            }
+
+            let eroded = parallel_map(missing.len(), self.options.get_threads(), |k| {
+                let (x, y) = neighbours[missing[k]];
+                self.chunk_zone_erosion(level, x, y)
+            });
+
+            for (k, zone) in missing.iter().zip(eroded) {
+                let zone = zone?;
+                let (x, y) = neighbours[*k];
+                self.chunk_cache.lock().unwrap().zones.insert((level, x, y), zone.clone());
+                zones[*k] = Some(zone);
+            }
+        }
+
+        Ok(neighbours.into_iter().zip(zones).map(|((x, y), zone)| (x, y, zone.unwrap())).collect())
+    }
+
+    // erode, au niveau level, la zone du chunk (cx, cy) entouree d'une marge de droplet_halo points du niveau (au plus la moitie
+    // d'un chunk, pour que seules les zones des chunks voisins se recouvrent), avec les gouttes du chunk et de ses voisins
+    // qui partent dans la zone: chaque chunk tire ses gouttes sur son propre generateur, le resultat ne depend que de la
+    // position du chunk
+    fn chunk_zone_erosion(&self, level: usize, cx: i32, cy: i32) -> Result<Arc<ChunkErosion>> {
+        let settings = &self.options;
+        let (w, h) = settings.get_terrain_size();
+        let (step, level_settings) = self.erosion_levels().swap_remove(level);
+
+        // taille d'un chunk, sans son dernier point (partage avec le voisin), en points du niveau
+        let (reduced_w, reduced_h) = ((w - 1) / step, (h - 1) / step);
+        let halo = usize::max(1, usize::min(droplet_halo(&level_settings), usize::min(reduced_w, reduced_h) / 2));
+
+        let margin = halo * step;
+        let (zone_w, zone_h) = ((reduced_w + 2 * halo) * step + 1, (reduced_h + 2 * halo) * step + 1);
+        let mut zone = self.assemble_region(cx, cy, margin, margin, zone_w, zone_h, |x, y| self.chunk_level_heightmap(level, x, y))?;
+        let hardness = self.hardness(zone_w, zone_h, (cx as i64 * (w - 1) as i64 - margin as i64, cy as i64 * (h - 1) as i64 - margin as i64))?;
+
+        // les gouttes des 9 chunks sont simulees dans l'ordre du tirage, en alternant les chunks
+        let droplets: Vec<(f64, f64)> = if level_settings.hydraulic_erosion_model == HydraulicErosionModel::Droplets {
+            let starts: Vec<Vec<(f64, f64)>> = (-1..=1).flat_map(|dcy| (-1..=1).map(move |dcx| (dcx, dcy))).map(|(dcx, dcy)| {
+                let mut rng = derive_rng(settings.seed, &[CHUNK_EROSION_STREAM, level as i64, (cx + dcx) as i64, (cy + dcy) as i64]);
+                let (x0, y0) = ((halo as i64 + dcx as i64 * reduced_w as i64) as f64, (halo as i64 + dcy as i64 * reduced_h as i64) as f64);
+                (0..level_settings.number_of_erosion_iterations).map(|_| {
+                    let pos_x: f64 = next_random_number(reduced_w as u64, &mut rng).into();
+                    let pos_y: f64 = next_random_number(reduced_h as u64, &mut rng).into();
+                    (x0 + pos_x, y0 + pos_y)
+                }).collect()
+            }).collect();
+
+            // une goutte a besoin du point suivant pour calculer la pente
+            let (max_x, max_y) = ((reduced_w + 2 * halo - 1) as f64, (reduced_h + 2 * halo - 1) as f64);
+            (0..level_settings.number_of_erosion_iterations as usize)
+                .flat_map(|k| starts.iter().map(move |droplets| droplets[k]))
+                .filter(|(pos_x, pos_y)| (0.0..=max_x).contains(pos_x) && (0.0..=max_y).contains(pos_y))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let mut maps = ErosionMaps::default();
+        erode_region(&mut ReducedArrayWrapper::new(&mut zone, step), &droplets, &mut maps, &hardness, settings, &level_settings);
+
+        let mut heights: Arr2d<f32> = Arr2d::zeros(reduced_w + 2 * halo + 1, reduced_h + 2 * halo + 1);
+        for x in 0..heights.get_width() {
+            for y in 0..heights.get_height() {
+                *heights.get_mut(x, y).unwrap() = *zone.get(x * step, y * step).unwrap();
+            }
+        }
+
+        Ok(Arc::new(ChunkErosion { heights, maps, halo }))
+    }
+
+    // durete de la roche d'un tableau de width x height points dont le point (0, 0) est en origin dans le monde
+    fn hardness(&self, width: usize, height: usize, origin: (i64, i64)) -> Result<Hardness> {
+        let image = if self.erosion.hardness_source == HardnessSource::Image { Some(self.hardness_image()?) } else { None };
+        Ok(Hardness::new(width, height, origin, image.as_deref(), &self.options, &self.erosion))
+    }
+
+    // image de durete, etiree a la taille du terrain et gardee en memoire
+    fn hardness_image(&self) -> Result<Arc<Arr2d<f32>>> {
+        if let Some(image) = &self.chunk_cache.lock().unwrap().hardness_image {
+            return Ok(image.clone())
+        }
+
+        let path = PathBuf::from(&self.erosion.hardness_image);
+        let img = image::io::Reader::open(&path)
+            .map_err(|e| Error::ImageIo { path: path.clone(), source: image::ImageError::IoError(e) })?
+            .decode()
+            .map_err(|source| Error::ImageIo { path: path.clone(), source })?;
+
+        let (image_w, image_h) = (img.width() as usize, img.height() as usize);
+        let (w, h) = self.options.get_terrain_size();
+        let image = Arc::new(Arr2d::from_dynamic_image(img, 1.0, image_w, image_h).resample(w, h));
+
+        self.chunk_cache.lock().unwrap().hardness_image = Some(image.clone());
+        Ok(image)
+    }
+
+    // libere les heightmaps des chunks gardees en memoire
+    pub fn clear_chunk_cache(&self) {
+        *self.chunk_cache.lock().unwrap() = ChunkCache::new(self.chunk_cache_capacity);
+    }
+
+    // assemble la zone de width x height du monde dont le coin superieur gauche est a (left, top) points avant le debut
+    // du chunk (cx, cy)
+    fn chunk_region(&self, cx: i32, cy: i32, left: usize, top: usize, width: usize, height: usize) -> Result<Arr2d<f32>> {
+        self.assemble_region(cx, cy, left, top, width, height, |x, y| self.chunk_heightmap(x, y))
+    }
+
+    // meme chose que chunk_region, chunk(x, y) donnant le tableau de la taille du terrain du chunk (x, y)
+    #[allow(clippy::too_many_arguments)]
+    fn assemble_region<F: Fn(i32, i32) -> Result<Arc<Arr2d<f32>>>>(&self, cx: i32, cy: i32, left: usize, top: usize, width: usize, height: usize,
+        chunk: F) -> Result<Arr2d<f32>> {
+        let (w, h) = self.options.get_terrain_size();
+        let mut region: Arr2d<f32> = Arr2d::zeros(width, height);
+
+        for (dcx, dcy, origin_x, origin_y) in chunks_in_region(w, h, left, top, width, height) {
+            let chunk = chunk(cx + dcx, cy + dcy)?;
+            copy_into_region(&mut region, &chunk, origin_x, origin_y);
+        }
+
+        Ok(region)
+    }
+
+    // meme chose que chunk_region pour les cartes de l'erosion, a leur resolution (voir erosion_map_size): (left, top) et
+    // la taille de la zone sont exprimes en points des cartes
+    fn chunk_region_erosion_maps(&self, cx: i32, cy: i32, left: usize, top: usize, width: usize, height: usize) -> Result<ErosionMaps> {
+        let (map_w, map_h) = self.erosion_map_size();
+        let mut region = self.chunk_erosion_maps(cx, cy)?.map(|_| Arr2d::zeros(width, height));
+
+        for (dcx, dcy, origin_x, origin_y) in chunks_in_region(map_w, map_h, left, top, width, height) {
+            let chunk = self.chunk_erosion_maps(cx + dcx, cy + dcy)?;
+            // tous les chunks calculent les memes cartes, dans le meme ordre
+            for (region_map, (_, map)) in region.maps_mut().into_iter().zip(chunk.named_maps()) {
+                copy_into_region(region_map, map, origin_x, origin_y);
+            }
+        }
+
+        Ok(region)
+    }
+
+    // cartes de l'erosion du chunk (cx, cy), voir erosion_map_size
+    fn chunk_erosion_maps(&self, cx: i32, cy: i32) -> Result<Arc<ErosionMaps>> {
+        Ok(self.chunk_data(cx, cy)?.1)
+    }
+
+    // marge, en points de la heightmap, prise sur les chunks voisins pour analyser le terrain pres des bords,
+    // multiple du pas du mesh pour que le mesh de la region contienne celui du chunk
+    fn chunk_analysis_margin(&self) -> usize {
+        usize::max(CHUNK_ANALYSIS_MARGIN, self.terrain_analysis.tpi_radius) * self.options.get_mesh_step()
+    }
+
+    // materiaux de la texture d'un tableau de width x height dont le point (0, 0) est en origin dans le monde, None sans materiaux
+    fn palette(&self, width: usize, height: usize, origin: (i64, i64)) -> Option<Palette> {
+        if self.materials.is_empty() {
+            None
+        } else {
+            Some(Palette::new(&self.materials, width, height, origin, &self.options))
+        }
+    }
+
+    // wetness de reference des biomes, prise sur le chunk (0, 0) pour etre la meme pour tous les chunks
+    fn chunk_wetness_scale(&self) -> Result<f32> {
+        if let Some(scale) = self.chunk_cache.lock().unwrap().wetness_scale {
+            return Ok(scale)
+        }
+
+        let scale = wetness_scale(self.chunk_erosion_maps(0, 0)?.wetness.as_ref());
+
+        self.chunk_cache.lock().unwrap().wetness_scale = Some(scale);
+        Ok(scale)
+    }
+
+    // couleurs ambiante et du soleil, calculees une seule fois pour tous les chunks
+    fn chunk_sky_colors(&self) -> Result<([f32; 3], [f32; 3])> {
+        if let Some(colors) = self.chunk_cache.lock().unwrap().sky_colors {
+            return Ok(colors)
+        }
+
+        let sky = self.run_sky()?;
+        let colors = (sky.ambient_color, sky.sun_color);
+
+        self.chunk_cache.lock().unwrap().sky_colors = Some(colors);
+        Ok(colors)
+    }
+}
+
+
+// chunks voisins touches par la zone de width x height dont le coin superieur gauche est a (left, top) points avant le
+// debut du chunk central, avec la position (origin_x, origin_y) de leur coin superieur gauche dans la zone
+fn chunks_in_region(chunk_w: usize, chunk_h: usize, left: usize, top: usize, width: usize, height: usize) -> Vec<(i32, i32, i64, i64)> {
+    let dcx_range = -(left.div_ceil(chunk_w - 1) as i64)..=((width - left - 1).div_ceil(chunk_w - 1) as i64);
+    let dcy_range = -(top.div_ceil(chunk_h - 1) as i64)..=((height - top - 1).div_ceil(chunk_h - 1) as i64);
+
+    let mut chunks = vec![];
+    for dcx in dcx_range {
+        for dcy in dcy_range.clone() {
+            let origin_x = left as i64 + dcx * (chunk_w - 1) as i64;
+            let origin_y = top as i64 + dcy * (chunk_h - 1) as i64;
+
+            // la zone ne fait qu'effleurer certains chunks des coins
+            if origin_x < width as i64 && origin_x + (chunk_w as i64) > 0 && origin_y < height as i64 && origin_y + (chunk_h as i64) > 0 {
+                chunks.push((dcx as i32, dcy as i32, origin_x, origin_y));
+            }
+        }
+    }
+    chunks
+}
+
+// poids de la zone d'un chunk au point t (en points du niveau, depuis le debut du chunk, de periode period): 1 dans le chunk,
+// decroissant lineairement jusqu'a 0 sur les 2 * halo points centres sur ses bords, ou la zone du chunk voisin prend le
+// relais: la somme des poids des zones qui recouvrent un point vaut 1
+fn zone_weight(t: i64, period: usize, halo: usize) -> f32 {
+    let distance_to_zone_border = i64::min(t + halo as i64, (period + halo) as i64 - t);
+    (distance_to_zone_border as f32 / (2 * halo) as f32).clamp(0.0, 1.0)
+}
+
+// valeurs aux points du niveau du chunk (cx, cy), de (reduced_w + 1) x (reduced_h + 1) points, des tableaux values(zone)
+// des zones erodees qui le recouvrent, melangees selon zone_weight
+// deux chunks voisins font exactement les memes calculs sur leur bord commun: memes zones, dans le meme ordre, avec les memes poids
+fn blend_chunk_zones<F: Fn(&ChunkErosion) -> Vec<&Arr2d<f32>>>(zones: &[(i32, i32, Arc<ChunkErosion>)], cx: i32, cy: i32, reduced_w: usize, reduced_h: usize,
+    values: F) -> Vec<Arr2d<f32>> {
+    let mut blended: Vec<Arr2d<f32>> = values(&zones[0].2).iter().map(|_| Arr2d::zeros(reduced_w + 1, reduced_h + 1)).collect();
+
+    for (x, y, zone) in zones {
+        let zone_values = values(zone);
+        let (dx, dy) = ((x - cx) as i64 * reduced_w as i64, (y - cy) as i64 * reduced_h as i64);
+
+        for rx in 0..=reduced_w {
+            let t_x = rx as i64 - dx;
+            let weight_x = zone_weight(t_x, reduced_w, zone.halo);
+            if weight_x == 0.0 {
+                continue
+            }
+
+            for ry in 0..=reduced_h {
+                let t_y = ry as i64 - dy;
+                let weight_y = zone_weight(t_y, reduced_h, zone.halo);
+                if weight_y == 0.0 {
+                    continue
+                }
+
+                let (zone_x, zone_y) = ((t_x + zone.halo as i64) as usize, (t_y + zone.halo as i64) as usize);
+                for (map, zone_map) in blended.iter_mut().zip(zone_values.iter()) {
+                    *map.get_mut(rx, ry).unwrap() += weight_x * weight_y * *zone_map.get(zone_x, zone_y).unwrap();
+                }
+            }
+        }
+    }
+
+    blended
+}
+
+// recopie la partie de chunk qui recouvre region, chunk commencant au point (origin_x, origin_y) de region
+fn copy_into_region(region: &mut Arr2d<f32>, chunk: &Arr2d<f32>, origin_x: i64, origin_y: i64) {
+    let x_range = i64::max(0, origin_x)..i64::min(region.get_width() as i64, origin_x + chunk.get_width() as i64);
+    let y_range = i64::max(0, origin_y)..i64::min(region.get_height() as i64, origin_y + chunk.get_height() as i64);
+
+    for x in x_range {
+        for y in y_range.clone() {
+            *region.get_mut(x as usize, y as usize).unwrap() = *chunk.get((x - origin_x) as usize, (y - origin_y) as usize).unwrap();
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::{cmp::{Ordering, Reverse}, collections::{BinaryHeap, VecDeque}};
+
+use serde_derive::Deserialize;
+
+use crate::{utils::{Arr2d, PI}, settings::RiverOptions};
+
+
+// voisins d'un point, dans l'ordre des facettes de D-infinity: chaque facette est formee d'un voisin direct et du
+// voisin en diagonale qui le suit
+pub(crate) const NEIGHBOURS: [(i32, i32); 8] = [(1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1), (0, 1), (1, 1)];
+
+// difference de hauteur ajoutee en remplissant une cuvette, pour que l'eau s'ecoule sur les zones remplies
+const FILL_EPSILON: f64 = 1e-6;
+
+
+// methode de calcul de la direction d'ecoulement
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowDirection {
+    #[default]
+    D8,  // toute l'eau va vers le voisin de plus grande pente
+    DInfinity  // l'eau est partagee entre les deux voisins de la facette de plus grande pente (Tarboton)
+}
+
+
+// reseau de rivieres extrait de la heightmap
+pub struct RiverNetwork {
+    // 1 dans le lit creuse des rivieres, 0 ailleurs, meme taille que la heightmap
+    pub mask: Arr2d<f32>,
+    // trace de chaque troncon, de sa source a sa confluence ou a son embouchure, en points de la heightmap
+    pub polylines: Vec<Vec<(f32, f32)>>
+}
+
+
+// grille de travail: sur un terrain qui se repete, la derniere ligne / colonne est confondue avec la premiere
+pub(crate) struct Grid {
+    pub width: usize,
+    pub height: usize,
+    pub tileable: bool
+}
+
+impl Grid {
+    pub fn new(heightmap: &Arr2d<f32>, tileable: bool) -> Grid {
+        if tileable {
+            Grid { width: heightmap.get_width() - 1, height: heightmap.get_height() - 1, tileable }
+        } else {
+            Grid { width: heightmap.get_width(), height: heightmap.get_height(), tileable }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    // hauteurs des points de la grille, indices par y * width + x
+    pub fn heights(&self, heightmap: &Arr2d<f32>) -> Vec<f64> {
+        (0..self.len()).map(|index| *heightmap.get(index % self.width, index / self.width).unwrap() as f64).collect()
+    }
+
+    pub fn neighbour(&self, index: usize, (dx, dy): (i32, i32)) -> Option<usize> {
+        let (x, y) = ((index % self.width) as i32 + dx, (index / self.width) as i32 + dy);
+        if self.tileable {
+            Some(y.rem_euclid(self.height as i32) as usize * self.width + x.rem_euclid(self.width as i32) as usize)
+        } else if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            None
+        } else {
+            Some(y as usize * self.width + x as usize)
+        }
+    }
+
+    pub fn is_border(&self, index: usize) -> bool {
+        let (x, y) = (index % self.width, index / self.width);
+        !self.tileable && (x == 0 || y == 0 || x == self.width - 1 || y == self.height - 1)
+    }
+}
+
+
+// point de la file de priorite du remplissage, le plus bas sort en premier
+#[derive(PartialEq)]
+struct Cell(f64, usize);
+
+impl Eq for Cell {}
+
+impl PartialOrd for Cell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Cell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+
+// remplit les cuvettes (priority-flood, Barnes et al. 2014): l'eau part des bords du terrain, ou du point le plus bas s'il se repete,
+// et chaque point atteint est au moins aussi haut que celui d'ou elle vient, plus epsilon (0: les cuvettes remplies sont planes)
+// les points d'une cuvette sont parcourus en largeur depuis son deversoir, pour que l'eau y converge vers lui
+pub(crate) fn fill_depressions(heights: &[f64], grid: &Grid, epsilon: f64) -> Vec<f64> {
+    let mut filled = heights.to_vec();
+    let mut done = vec![false; heights.len()];
+    let mut queue: BinaryHeap<Reverse<Cell>> = BinaryHeap::new();
+    let mut pit: VecDeque<usize> = VecDeque::new();
+
+    if grid.tileable {
+        let lowest = (0..heights.len()).min_by(|a, b| heights[*a].total_cmp(&heights[*b])).unwrap();
+        queue.push(Reverse(Cell(heights[lowest], lowest)));
+        done[lowest] = true;
+    } else {
+        for index in (0..heights.len()).filter(|index| grid.is_border(*index)) {
+            queue.push(Reverse(Cell(heights[index], index)));
+            done[index] = true;
+        }
+    }
+
+    while let Some(index) = pit.pop_front().or_else(|| queue.pop().map(|Reverse(Cell(_, index))| index)) {
+        let level = filled[index];
+        for direction in NEIGHBOURS {
+            if let Some(neighbour) = grid.neighbour(index, direction) {
+                if !done[neighbour] {
+                    done[neighbour] = true;
+                    if heights[neighbour] <= level + epsilon {
+                        filled[neighbour] = level + epsilon;
+                        pit.push_back(neighbour);
+                    } else {
+                        queue.push(Reverse(Cell(filled[neighbour], neighbour)));
+                    }
+                }
+            }
+        }
+    }
+
+    filled
+}
+
+
+// voisins vers lesquels s'ecoule l'eau d'un point, avec la fraction de l'eau envoyee a chacun
+fn receivers(filled: &[f64], grid: &Grid, index: usize, flow_direction: FlowDirection) -> Vec<(usize, f64)> {
+    if grid.is_border(index) {
+        return vec![]  // l'eau quitte le terrain
+    }
+
+    let height = filled[index];
+
+    match flow_direction {
+        FlowDirection::D8 => {
+            let mut best: Option<(usize, f64)> = None;
+            for (k, direction) in NEIGHBOURS.iter().enumerate() {
+                if let Some(neighbour) = grid.neighbour(index, *direction) {
+                    let distance = if k % 2 == 0 { 1.0 } else { std::f64::consts::SQRT_2 };
+                    let slope = (height - filled[neighbour]) / distance;
+                    if slope > 0.0 && best.is_none_or(|(_, best_slope)| slope > best_slope) {
+                        best = Some((neighbour, slope));
+                    }
+                }
+            }
+            best.map(|(neighbour, _)| vec![(neighbour, 1.0)]).unwrap_or_default()
+        }
+        FlowDirection::DInfinity => {
+            let quarter = PI as f64 / 4.0;
+            let mut best: Option<(usize, usize, f64, f64)> = None;  // voisin direct, diagonale, angle dans la facette, pente
+
+            for facet in 0..8 {
+                // une facette sur deux part d'une diagonale: le voisin direct est alors le second point
+                let (first, second) = if facet % 2 == 0 { (facet, facet + 1) } else { ((facet + 1) % 8, facet) };
+                let (Some(direct), Some(diagonal)) = (grid.neighbour(index, NEIGHBOURS[first]), grid.neighbour(index, NEIGHBOURS[second])) else { continue };
+
+                let s1 = height - filled[direct];
+                let s2 = filled[direct] - filled[diagonal];
+                let mut angle = f64::atan2(s2, s1);
+                let slope = if angle <= 0.0 {
+                    angle = 0.0;
+                    s1
+                } else if angle >= quarter {
+                    angle = quarter;
+                    (height - filled[diagonal]) / std::f64::consts::SQRT_2
+                } else {
+                    f64::sqrt(s1 * s1 + s2 * s2)
+                };
+
+                if slope > 0.0 && best.is_none_or(|(_, _, _, best_slope)| slope > best_slope) {
+                    best = Some((direct, diagonal, angle, slope));
+                }
+            }
+
+            match best {
+                Some((direct, diagonal, angle, _)) => {
+                    let to_diagonal = angle / quarter;
+                    vec![(direct, 1.0 - to_diagonal), (diagonal, to_diagonal)].into_iter().filter(|(_, fraction)| *fraction > 0.0).collect()
+                }
+                None => vec![]
+            }
+        }
+    }
+}
+
+
+// extrait les rivieres de heightmap et les creuse dans la heightmap
+// un point fait partie d'une riviere quand la surface qu'il draine depasse river_threshold (fraction du terrain); le lit a une
+// demi-largeur de river_width points a la source, qui croit comme (surface drainee / seuil)^river_width_exponent, et une profondeur
+// proportionnelle a sa largeur
+pub fn extract_rivers(heightmap: &mut Arr2d<f32>, tileable: bool, river_settings: &RiverOptions) -> RiverNetwork {
+    println!("extracting rivers...");
+
+    let grid = Grid::new(heightmap, tileable);
+    let n = grid.len();
+
+    let heights = grid.heights(heightmap);
+    let filled = fill_depressions(&heights, &grid, FILL_EPSILON);
+
+    let flow: Vec<Vec<(usize, f64)>> = (0..n).map(|index| receivers(&filled, &grid, index, river_settings.flow_direction)).collect();
+
+    // surface drainee, en points: chaque point recoit l'eau de ceux qui sont plus hauts que lui
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| filled[*b].total_cmp(&filled[*a]).then(a.cmp(b)));
+
+    let mut accumulation = vec![1.0_f64; n];
+    for index in order.iter() {
+        for (receiver, fraction) in flow[*index].iter() {
+            accumulation[*receiver] += accumulation[*index] * fraction;
+        }
+    }
+
+    let threshold = f64::max(river_settings.river_threshold as f64 * n as f64, 1.0);
+    let is_river = |index: usize| accumulation[index] >= threshold;
+    // une cuvette remplie plus profonde que le lit est un lac que l'eau traverse: elle n'est pas creusee
+    let is_lake = |index: usize, depth: f64| filled[index] - heights[index] > depth;
+
+    // creuse le lit en partant des sources: en aval, un lit plus large recouvre le precedent
+    let mut mask: Arr2d<f32> = Arr2d::zeros(heightmap.get_width(), heightmap.get_height());
+    let width_exponent = river_settings.river_width_exponent as f64;
+
+    for index in order.iter().copied().filter(|index| is_river(*index)) {
+        let half_width = f64::min(river_settings.river_width as f64 * (accumulation[index] / threshold).powf(width_exponent), river_settings.max_river_width as f64);
+        let depth = river_settings.river_depth as f64 * half_width / river_settings.river_width as f64;
+        if is_lake(index, depth) {
+            continue
+        }
+        let bed = filled[index] - depth;
+
+        let reach = half_width.ceil() as i32;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let distance = f64::sqrt((dx * dx + dy * dy) as f64);
+                if distance > half_width {
+                    continue
+                }
+
+                if let Some(neighbour) = grid.neighbour(index, (dx, dy)).filter(|neighbour| !is_lake(*neighbour, depth)) {
+                    let (x, y) = (neighbour % grid.width, neighbour / grid.width);
+                    let target = (bed + depth * (distance / half_width).powi(2)) as f32;
+
+                    let height = heightmap.get_mut(x, y).unwrap();
+                    *height = f32::min(*height, target);
+                    *mask.get_mut(x, y).unwrap() = 1.0;
+                }
+            }
+        }
+    }
+
+    if tileable {
+        heightmap.wrap_edges();
+        mask.wrap_edges();
+    }
+
+    // troncons: d'une source (aucun point de riviere en amont) ou d'une confluence jusqu'a la confluence ou l'embouchure suivante
+    let mut upstream_rivers = vec![0_u8; n];
+    for index in (0..n).filter(|index| is_river(*index)) {
+        if let Some(receiver) = main_receiver(&flow[index]) {
+            upstream_rivers[receiver] = upstream_rivers[receiver].saturating_add(1);
+        }
+    }
+
+    let mut polylines = vec![];
+    for start in (0..n).filter(|index| is_river(*index) && upstream_rivers[*index] != 1) {
+        // un point avec un seul affluent continue le troncon de cet affluent
+        let mut polyline = vec![((start % grid.width) as f32, (start / grid.width) as f32)];
+        let mut index = start;
+
+        while let Some(receiver) = main_receiver(&flow[index]) {
+            polyline.push(((receiver % grid.width) as f32, (receiver / grid.width) as f32));
+            if upstream_rivers[receiver] != 1 {
+                break
+            }
+            index = receiver;
+        }
+
+        polylines.push(polyline);
+    }
+
+    println!("{} river segments extracted.", polylines.len());
+
+    RiverNetwork { mask, polylines }
+}
+
+
+// voisin recevant le plus d'eau, qui continue le trace de la riviere
+fn main_receiver(receivers: &[(usize, f64)]) -> Option<usize> {
+    receivers.iter().max_by(|a, b| a.1.total_cmp(&b.1)).map(|(receiver, _)| *receiver)
+}
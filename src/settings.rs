@@ -1,8 +1,50 @@
+use std::{fmt, path::Path};
+
 use config::{ConfigError, Config, File};
 use serde_derive::Deserialize;
 
+use crate::{error::{Error, Result}, noise::HeightmapAlgorithm, erosion::{ThermalErosionOrder, HydraulicErosionModel}, hardness::HardnessSource, rivers::FlowDirection, biomes::{Biome, default_biome_table}, materials::Material,
+    normal_map::{NormalConvention, NormalFilter}, heightmap_export::HeightmapFormat};
+
+
+// sections du fichier de parametres dans lesquelles une valeur passee en ligne de commande peut etre remplacee
+pub const SETTINGS_SECTIONS: [&str; 9] = ["launch_options", "generation_options", "erosion", "rivers", "water", "biomes", "normal_map",
+    "terrain_analysis", "heightmap_export"];
+
+// rayon maximal des gouttes d'eau, en points du niveau erode
+const MAX_EROSION_RADIUS: u8 = 32;
+
+// champs de la section [erosion], pour retrouver la section d'un champ absent du fichier
+const EROSION_FIELDS: [&str; 34] = ["number_of_erosion_iterations", "inertia", "radius", "capacity_factor", "initial_lifetime",
+    "parallel_erosion", "deposition_speed", "erosion_speed", "evaporation_rate", "gravity", "min_slope", "min_capacity", "speed_based_capacity",
+    "thermal_erosion_iterations", "talus_angle", "thermal_transfer_rate", "thermal_erosion_order", "export_debris_map",
+    "hydraulic_erosion_model", "pipe_iterations", "pipe_time_step", "rainfall_rate", "pipe_evaporation_rate", "pipe_sediment_capacity",
+    "pipe_dissolving_rate", "pipe_deposition_rate", "erosion_level_iterations", "erosion_radius_scaling",
+    "hardness_source", "hardness_layers", "hardness_layer_thickness", "hardness_noise_frequency", "hardness_image", "max_hardness"];
+
+// champs de la section [rivers]
+const RIVER_FIELDS: [&str; 7] = ["generate_rivers", "flow_direction", "river_threshold", "river_width", "max_river_width",
+    "river_width_exponent", "river_depth"];
+
+// champs de la section [water]
+const WATER_FIELDS: [&str; 5] = ["sea_level", "generate_lakes", "min_lake_depth", "beach_width", "beach_height"];
 
-#[derive(Debug, Deserialize)]
+// champs de la section [biomes]
+const BIOME_FIELDS: [&str; 12] = ["generate_biomes", "equator_temperature", "latitude_top", "latitude_bottom", "latitude_gradient", "lapse_rate",
+    "base_moisture", "water_moisture", "moisture_distance", "wetness_moisture", "temperature_blend", "moisture_blend"];
+
+// champs de la section [normal_map]
+const NORMAL_MAP_FIELDS: [&str; 3] = ["generate_normal_map", "normal_convention", "normal_filter"];
+
+// champs de la section [terrain_analysis]
+const TERRAIN_ANALYSIS_FIELDS: [&str; 2] = ["generate_terrain_analysis", "tpi_radius"];
+
+// champs de la section [heightmap_export]
+const HEIGHTMAP_EXPORT_FIELDS: [&str; 3] = ["heightmap_format", "absolute_heights", "absolute_height_range"];
+
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LaunchOptions {
     pub generate_sky_heightmap: bool,
     pub generate_sky_texture: bool,
@@ -13,7 +55,8 @@ pub struct LaunchOptions {
 }
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct GenerationOptions {
     pub seed: u64,
 
@@ -22,9 +65,37 @@ pub struct GenerationOptions {
 
     pub template_power_of_two: u32,
 
+    // dimensions quelconques, remplacent 2^terrain_power_of_two + 1 et la taille du mesh qui s'en deduit
+    pub terrain_width: Option<usize>,
+    pub terrain_height: Option<usize>,
+    pub mesh_width: Option<usize>,
+    pub mesh_height: Option<usize>,
+
+    #[serde(default)]
+    pub heightmap_algorithm: HeightmapAlgorithm,
+
+    // le terrain se repete: les bords opposes de la heightmap sont identiques et la colormap se raccorde sans couture
+    #[serde(default)]
+    pub tileable: bool,
+
     pub max_terrain_height: f32,
     pub irregularity: f32,
 
+    // largeur d'un point de la heightmap en unites de hauteur, pour les ombres, l'occlusion ambiante, les normales,
+    // l'analyse du terrain et l'erosion thermique; None: un point du mesh mesure une unite, comme dans le displayer
+    #[serde(default)]
+    pub cell_size: Option<f32>,
+
+    // bruit fractal, utilise si heightmap_algorithm n'est pas "diamond_square"
+    #[serde(default = "default_noise_octaves")]
+    pub noise_octaves: u32,
+    #[serde(default = "default_noise_frequency")]
+    pub noise_frequency: f32,
+    #[serde(default = "default_noise_lacunarity")]
+    pub noise_lacunarity: f32,
+    #[serde(default = "default_noise_persistence")]
+    pub noise_persistence: f32,
+
     pub sun_angle: f32,
     pub sun_size: f32,
     pub atmosphere_radius: f32,
@@ -33,6 +104,40 @@ pub struct GenerationOptions {
     pub ambient_sky_light: f32,
 
     pub shadow_direction: u8,
+    // azimut du soleil en degres (0: vers le haut de la heightmap, 90: vers la droite), remplace shadow_direction: les ombres
+    // sont calculees en remontant vers le soleil depuis chaque point, avec une penombre donnee par sun_size
+    #[serde(default)]
+    pub sun_azimuth: Option<f32>,
+
+    // pente, en degres, a partir de laquelle la neige ne tient plus
+    pub rock_threshold: f32,
+    // intensite de la coloration des ravines (erodedmap) et des depots de sediments (depositedmap), 0: desactivee
+    #[serde(default = "default_erosion_coloration")]
+    pub erosion_coloration: f32,
+
+    // occlusion ambiante: nombre de directions dans lesquelles l'horizon est cherche (0: desactivee), jusqu'a
+    // ambient_occlusion_radius points de la heightmap, et fraction de la lumiere du ciel retiree la ou il est cache
+    #[serde(default)]
+    pub ambient_occlusion_rays: u32,
+    #[serde(default = "default_ambient_occlusion_radius")]
+    pub ambient_occlusion_radius: f32,
+    #[serde(default = "default_ambient_occlusion_strength")]
+    pub ambient_occlusion_strength: f32,
+
+    // nombre de threads utilises par les etapes parallelisees, 0: autant que de coeurs disponibles
+    // le resultat ne depend pas de cette valeur
+    #[serde(default)]
+    pub threads: usize
+
+}
+
+
+// parametres des gouttes d'eau simulees par l'erosion
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErosionOptions {
+    #[serde(default)]
+    pub hydraulic_erosion_model: HydraulicErosionModel,
 
     pub number_of_erosion_iterations: u32,
     pub inertia: f64,
@@ -40,27 +145,868 @@ pub struct GenerationOptions {
     pub capacity_factor: f64,
     pub initial_lifetime: u8,
 
-    pub rock_threshold: f32
+    // simule les gouttes par lots repartis sur les threads, le resultat differe de l'erosion sequentielle
+    // mais ne depend pas du nombre de threads
+    #[serde(default)]
+    pub parallel_erosion: bool,
+
+    // fraction de l'ecart entre capacite et sediment transporte deposee / erodee a chaque etape
+    #[serde(default = "default_deposition_speed")]
+    pub deposition_speed: f64,
+    #[serde(default = "default_erosion_speed")]
+    pub erosion_speed: f64,
+    // fraction de l'eau d'une goutte qui s'evapore a chaque etape
+    #[serde(default = "default_evaporation_rate")]
+    pub evaporation_rate: f64,
+    // acceleration d'une goutte qui descend d'une unite de hauteur, ou de l'eau dans les tuyaux
+    // une goutte avance de sa vitesse (au plus un point par etape) et s'arrete quand elle n'en a plus
+    #[serde(default = "default_gravity")]
+    pub gravity: f64,
+    // pente minimale (sinus de l'inclinaison pour les tuyaux) prise en compte dans la capacite, pour que les zones plates soient tout de meme erodees
+    #[serde(default)]
+    pub min_slope: f64,
+    #[serde(default = "default_min_capacity")]
+    pub min_capacity: f64,
+    // la capacite est proportionnelle a la vitesse de la goutte
+    #[serde(default = "default_speed_based_capacity")]
+    pub speed_based_capacity: bool,
+
+    // erosion thermique, desactivee si thermal_erosion_iterations vaut 0
+    #[serde(default)]
+    pub thermal_erosion_iterations: u32,
+    // pente, en degres, au dela de laquelle le materiau glisse vers le bas
+    #[serde(default = "default_talus_angle")]
+    pub talus_angle: f64,
+    // fraction de l'exces de materiau deplacee a chaque iteration
+    #[serde(default = "default_thermal_transfer_rate")]
+    pub thermal_transfer_rate: f64,
+    #[serde(default)]
+    pub thermal_erosion_order: ThermalErosionOrder,
+    // exporte le materiau deplace par l'erosion thermique (debrismap.png)
+    #[serde(default)]
+    pub export_debris_map: bool,
+
+    // erosion par tuyaux (hydraulic_erosion_model = "pipes"), les temps sont en unites de pipe_time_step
+    #[serde(default = "default_pipe_iterations")]
+    pub pipe_iterations: u32,
+    #[serde(default = "default_pipe_time_step")]
+    pub pipe_time_step: f64,
+    // hauteur d'eau tombant sur chaque point par unite de temps
+    #[serde(default = "default_rainfall_rate")]
+    pub rainfall_rate: f64,
+    // fraction de l'eau evaporee par unite de temps
+    #[serde(default = "default_pipe_evaporation_rate")]
+    pub pipe_evaporation_rate: f64,
+    // sediment transporte par unite de vitesse, de pente et de hauteur d'eau
+    #[serde(default = "default_pipe_sediment_capacity")]
+    pub pipe_sediment_capacity: f64,
+    // fraction de l'ecart entre capacite et sediment transporte erodee / deposee a chaque iteration
+    #[serde(default = "default_pipe_dissolving_rate")]
+    pub pipe_dissolving_rate: f64,
+    #[serde(default = "default_pipe_deposition_rate")]
+    pub pipe_deposition_rate: f64,
+
+    // erosion a plusieurs niveaux du diamond-square, du niveau du mesh vers la pleine resolution: nombre d'iterations
+    // (gouttes, ou iterations des tuyaux) a chaque niveau, vide: une seule erosion au niveau du mesh
+    #[serde(default)]
+    pub erosion_level_iterations: Vec<u32>,
+    // facteur applique au rayon des gouttes, en points du niveau, a chaque niveau plus fin
+    #[serde(default = "default_erosion_radius_scaling")]
+    pub erosion_radius_scaling: f64,
+
+    // durete de la roche, entre 0 et 1: la quantite erodee par l'eau est multipliee par 1 - durete
+    #[serde(default)]
+    pub hardness_source: HardnessSource,
+    // durete des strates successives, du bas vers le haut, repetees (hardness_source = "layers")
+    #[serde(default = "default_hardness_layers")]
+    pub hardness_layers: Vec<f32>,
+    #[serde(default = "default_hardness_layer_thickness")]
+    pub hardness_layer_thickness: f32,
+    #[serde(default = "default_hardness_noise_frequency")]
+    pub hardness_noise_frequency: f32,
+    // image en niveaux de gris, blanc: roche la plus dure, etiree sur le terrain (ou sur chaque chunk)
+    #[serde(default = "default_hardness_image")]
+    pub hardness_image: String,
+    // durete du blanc de l'image et du maximum du bruit
+    #[serde(default = "default_max_hardness")]
+    pub max_hardness: f32
+}
+
+
+impl Default for ErosionOptions {
+    fn default() -> Self {
+        ErosionOptions {
+            hydraulic_erosion_model: HydraulicErosionModel::default(),
+            number_of_erosion_iterations: 127000,
+            inertia: 0.01,
+            radius: 3,
+            capacity_factor: 120.0,
+            initial_lifetime: 10,
+            parallel_erosion: false,
+            deposition_speed: default_deposition_speed(),
+            erosion_speed: default_erosion_speed(),
+            evaporation_rate: default_evaporation_rate(),
+            gravity: default_gravity(),
+            min_slope: 0.0,
+            min_capacity: default_min_capacity(),
+            speed_based_capacity: default_speed_based_capacity(),
+            thermal_erosion_iterations: 0,
+            talus_angle: default_talus_angle(),
+            thermal_transfer_rate: default_thermal_transfer_rate(),
+            thermal_erosion_order: ThermalErosionOrder::default(),
+            export_debris_map: false,
+            pipe_iterations: default_pipe_iterations(),
+            pipe_time_step: default_pipe_time_step(),
+            rainfall_rate: default_rainfall_rate(),
+            pipe_evaporation_rate: default_pipe_evaporation_rate(),
+            pipe_sediment_capacity: default_pipe_sediment_capacity(),
+            pipe_dissolving_rate: default_pipe_dissolving_rate(),
+            pipe_deposition_rate: default_pipe_deposition_rate(),
+            erosion_level_iterations: vec![],
+            erosion_radius_scaling: default_erosion_radius_scaling(),
+            hardness_source: HardnessSource::default(),
+            hardness_layers: default_hardness_layers(),
+            hardness_layer_thickness: default_hardness_layer_thickness(),
+            hardness_noise_frequency: default_hardness_noise_frequency(),
+            hardness_image: default_hardness_image(),
+            max_hardness: default_max_hardness()
+        }
+    }
+}
+
+
+fn default_deposition_speed() -> f64 { 0.3 }
+fn default_erosion_speed() -> f64 { 0.3 }
+fn default_evaporation_rate() -> f64 { 0.05 }
+fn default_gravity() -> f64 { 4.0 }
+fn default_min_capacity() -> f64 { 0.01 }
+fn default_speed_based_capacity() -> bool { true }
+fn default_talus_angle() -> f64 { 40.0 }
+fn default_thermal_transfer_rate() -> f64 { 0.5 }
+fn default_pipe_iterations() -> u32 { 200 }
+fn default_pipe_time_step() -> f64 { 0.05 }
+fn default_rainfall_rate() -> f64 { 0.2 }
+fn default_pipe_evaporation_rate() -> f64 { 0.1 }
+fn default_pipe_sediment_capacity() -> f64 { 0.5 }
+fn default_pipe_dissolving_rate() -> f64 { 0.3 }
+fn default_pipe_deposition_rate() -> f64 { 0.3 }
+fn default_erosion_radius_scaling() -> f64 { 1.0 }
+fn default_hardness_layers() -> Vec<f32> { vec![0.8, 0.2, 0.5, 0.1] }
+fn default_hardness_layer_thickness() -> f32 { 8.0 }
+fn default_hardness_noise_frequency() -> f32 { 4.0 }
+fn default_hardness_image() -> String { "hardness.png".to_string() }
+fn default_max_hardness() -> f32 { 0.9 }
+
+
+// reseau de rivieres extrait de la heightmap erodee
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RiverOptions {
+    #[serde(default)]
+    pub generate_rivers: bool,
+    #[serde(default)]
+    pub flow_direction: FlowDirection,
+    // fraction du terrain qu'un point doit drainer pour faire partie d'une riviere
+    #[serde(default = "default_river_threshold")]
+    pub river_threshold: f32,
+    // demi-largeur du lit, en points de la heightmap, a la source puis au plus
+    #[serde(default = "default_river_width")]
+    pub river_width: f32,
+    #[serde(default = "default_max_river_width")]
+    pub max_river_width: f32,
+    // la largeur croit comme (surface drainee / surface drainee a la source)^river_width_exponent
+    #[serde(default = "default_river_width_exponent")]
+    pub river_width_exponent: f32,
+    // profondeur du lit a la source, proportionnelle a la largeur en aval
+    #[serde(default = "default_river_depth")]
+    pub river_depth: f32
+}
+
+
+impl Default for RiverOptions {
+    fn default() -> Self {
+        RiverOptions {
+            generate_rivers: false,
+            flow_direction: FlowDirection::default(),
+            river_threshold: default_river_threshold(),
+            river_width: default_river_width(),
+            max_river_width: default_max_river_width(),
+            river_width_exponent: default_river_width_exponent(),
+            river_depth: default_river_depth()
+        }
+    }
+}
+
+
+fn default_river_threshold() -> f32 { 0.002 }
+fn default_river_width() -> f32 { 1.0 }
+fn default_max_river_width() -> f32 { 6.0 }
+fn default_river_width_exponent() -> f32 { 0.5 }
+fn default_river_depth() -> f32 { 1.0 }
+
+
+// mer et lacs, colores selon leur profondeur par la texture
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaterOptions {
+    // hauteur de la mer, None: pas de mer
+    pub sea_level: Option<f32>,
+    // remplit les cuvettes du terrain (non generes pour les chunks)
+    #[serde(default)]
+    pub generate_lakes: bool,
+    // profondeur minimale d'une cuvette pour qu'elle soit remplie
+    #[serde(default = "default_min_lake_depth")]
+    pub min_lake_depth: f32,
+    // largeur de la plage, en points de la heightmap complete, et hauteur au dessus de l'eau a partir de laquelle elle disparait
+    #[serde(default = "default_beach_width")]
+    pub beach_width: u32,
+    #[serde(default = "default_beach_height")]
+    pub beach_height: f32
+}
+
+
+impl Default for WaterOptions {
+    fn default() -> Self {
+        WaterOptions {
+            sea_level: None,
+            generate_lakes: false,
+            min_lake_depth: default_min_lake_depth(),
+            beach_width: default_beach_width(),
+            beach_height: default_beach_height()
+        }
+    }
+}
+
+
+fn default_min_lake_depth() -> f32 { 0.5 }
+fn default_beach_width() -> u32 { 3 }
+fn default_beach_height() -> f32 { 1.5 }
+
+
+// biomes, qui remplacent la neige sur les zones peu pentues
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BiomeOptions {
+    #[serde(default)]
+    pub generate_biomes: bool,
+
+    // temperature en degres: equator_temperature a l'equateur et au niveau de la mer
+    #[serde(default = "default_equator_temperature")]
+    pub equator_temperature: f32,
+    // latitude, en degres, de la premiere et de la derniere ligne du terrain (du chunk (x, 0) pour les chunks)
+    #[serde(default = "default_latitude_top")]
+    pub latitude_top: f32,
+    #[serde(default = "default_latitude_bottom")]
+    pub latitude_bottom: f32,
+    // baisse de temperature par degre de latitude et par unite de hauteur au dessus de la mer
+    #[serde(default = "default_latitude_gradient")]
+    pub latitude_gradient: f32,
+    #[serde(default = "default_lapse_rate")]
+    pub lapse_rate: f32,
+
+    // humidite, entre 0 et 1
+    #[serde(default = "default_base_moisture")]
+    pub base_moisture: f32,
+    // ajoutee au bord de l'eau, divisee par e tous les moisture_distance points
+    #[serde(default = "default_water_moisture")]
+    pub water_moisture: f32,
+    #[serde(default = "default_moisture_distance")]
+    pub moisture_distance: f32,
+    // ajoutee la ou l'erosion a fait passer beaucoup d'eau
+    #[serde(default = "default_wetness_moisture")]
+    pub wetness_moisture: f32,
+
+    // ecarts de temperature et d'humidite sur lesquels deux biomes voisins sont melanges
+    #[serde(default = "default_temperature_blend")]
+    pub temperature_blend: f32,
+    #[serde(default = "default_moisture_blend")]
+    pub moisture_blend: f32,
+
+    // biomes, le premier dont les intervalles contiennent le point l'emporte a poids egal
+    #[serde(default = "default_biome_table")]
+    pub table: Vec<Biome>
+}
+
+
+impl Default for BiomeOptions {
+    fn default() -> Self {
+        BiomeOptions {
+            generate_biomes: false,
+            equator_temperature: default_equator_temperature(),
+            latitude_top: default_latitude_top(),
+            latitude_bottom: default_latitude_bottom(),
+            latitude_gradient: default_latitude_gradient(),
+            lapse_rate: default_lapse_rate(),
+            base_moisture: default_base_moisture(),
+            water_moisture: default_water_moisture(),
+            moisture_distance: default_moisture_distance(),
+            wetness_moisture: default_wetness_moisture(),
+            temperature_blend: default_temperature_blend(),
+            moisture_blend: default_moisture_blend(),
+            table: default_biome_table()
+        }
+    }
+}
+
+
+fn default_equator_temperature() -> f32 { 30.0 }
+fn default_latitude_top() -> f32 { 50.0 }
+fn default_latitude_bottom() -> f32 { 30.0 }
+fn default_latitude_gradient() -> f32 { 0.4 }
+fn default_lapse_rate() -> f32 { 0.2 }
+fn default_base_moisture() -> f32 { 0.2 }
+fn default_water_moisture() -> f32 { 0.6 }
+fn default_moisture_distance() -> f32 { 40.0 }
+fn default_wetness_moisture() -> f32 { 0.3 }
+fn default_temperature_blend() -> f32 { 3.0 }
+fn default_moisture_blend() -> f32 { 0.05 }
+
+
+// normales du terrain a pleine resolution, pour eclairer le terrain dans un moteur
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NormalMapOptions {
+    #[serde(default)]
+    pub generate_normal_map: bool,
+    #[serde(default)]
+    pub normal_convention: NormalConvention,
+    #[serde(default)]
+    pub normal_filter: NormalFilter
+}
+
 
+// pente, orientation, courbures et position topographique, a la resolution du mesh, toujours calculees pour la texture
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TerrainAnalysisOptions {
+    // calcule aussi les cartes sans texture
+    #[serde(default)]
+    pub generate_terrain_analysis: bool,
+    // demi-cote, en points du mesh, du carre dont la hauteur moyenne est comparee a celle du point
+    #[serde(default = "default_tpi_radius")]
+    pub tpi_radius: usize
 }
 
 
-#[derive(Debug, Deserialize)]
+impl Default for TerrainAnalysisOptions {
+    fn default() -> Self {
+        TerrainAnalysisOptions {
+            generate_terrain_analysis: false,
+            tpi_radius: default_tpi_radius()
+        }
+    }
+}
+
+
+fn default_tpi_radius() -> usize { 8 }
+
+
+// format et echelle du fichier de la heightmap du terrain (heightmap.png, .tif ou .exr), decrits dans heightmap.txt
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeightmapExportOptions {
+    #[serde(default)]
+    pub heightmap_format: HeightmapFormat,
+    // hauteurs non ramenees entre le minimum et le maximum de chaque image, identiques d'un chunk a l'autre
+    #[serde(default)]
+    pub absolute_heights: bool,
+    // hauteurs du noir et du blanc des formats png avec absolute_heights, None: de 0 a 2 * max_terrain_height
+    pub absolute_height_range: Option<[f32; 2]>
+}
+
+
+fn default_noise_octaves() -> u32 { 8 }
+fn default_noise_frequency() -> f32 { 4.0 }
+fn default_noise_lacunarity() -> f32 { 2.0 }
+fn default_noise_persistence() -> f32 { 0.5 }
+
+fn default_erosion_coloration() -> f32 { 1.0 }
+fn default_ambient_occlusion_radius() -> f32 { 32.0 }
+fn default_ambient_occlusion_strength() -> f32 { 1.0 }
+
+
+// contrainte non respectee par un champ des parametres
+#[derive(Debug, Clone)]
+pub struct SettingViolation {
+    pub field: String,
+    pub value: String,
+    pub allowed: String
+}
+
+impl fmt::Display for SettingViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}, allowed: {}", self.field, self.value, self.allowed)
+    }
+}
+
+
+// accumule les contraintes non respectees pour toutes les rapporter en une fois
+struct Validator {
+    section: &'static str,
+    violations: Vec<SettingViolation>
+}
+
+impl Validator {
+    fn check<T: fmt::Display>(&mut self, is_valid: bool, field: &str, value: T, allowed: String) {
+        if !is_valid {
+            self.violations.push(SettingViolation { field: format!("{}.{}", self.section, field), value: value.to_string(), allowed })
+        }
+    }
+
+    fn check_range<T: fmt::Display + PartialOrd>(&mut self, field: &str, value: T, min: T, max: T) {
+        let allowed = format!("{} to {}", min, max);
+        self.check(min <= value && value <= max, field, value, allowed)
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidSettings(self.violations))
+        }
+    }
+}
+
+
+impl GenerationOptions {
+    // taille de la heightmap complete
+    pub fn get_terrain_size(&self) -> (usize, usize) {
+        let w = 2_usize.pow(self.terrain_power_of_two) + 1;
+        (self.terrain_width.unwrap_or(w), self.terrain_height.unwrap_or(w))
+    }
+
+    // distance entre 2 points du mesh sur la heightmap complete, l'erosion travaille a cette resolution
+    pub fn get_mesh_step(&self) -> usize {
+        2_usize.pow(self.terrain_power_of_two.saturating_sub(self.mesh_power_of_two))
+    }
+
+    // largeur d'un point de la heightmap en unites de hauteur
+    pub fn get_cell_size(&self) -> f32 {
+        self.cell_size.unwrap_or(1.0 / self.get_mesh_step() as f32)
+    }
+
+    // largeur d'un point du mesh en unites de hauteur
+    pub fn get_mesh_cell_size(&self) -> f32 {
+        self.get_cell_size() * self.get_mesh_step() as f32
+    }
+
+    // taille de la heightmap reduite utilisee pour le mesh
+    pub fn get_mesh_size(&self) -> (usize, usize) {
+        let (w, h) = self.get_terrain_size();
+        let step = self.get_mesh_step();
+        (self.mesh_width.unwrap_or((w - 1) / step + 1), self.mesh_height.unwrap_or((h - 1) / step + 1))
+    }
+
+    pub fn get_threads(&self) -> usize {
+        if self.threads == 0 {
+            std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+        } else {
+            self.threads
+        }
+    }
+
+    // verifie les parametres avant de lancer la generation, une valeur invalide pouvant faire paniquer
+    // ou produire des NaN loin dans le code
+    pub fn validate(&self) -> Result<()> {
+        let mut v = Validator { section: "generation_options", violations: vec![] };
+
+        v.check_range("terrain_power_of_two", self.terrain_power_of_two, 1, 16);
+        v.check_range("mesh_power_of_two", self.mesh_power_of_two, 1, self.terrain_power_of_two.max(1));
+        v.check_range("template_power_of_two", self.template_power_of_two, 0, self.terrain_power_of_two);
+
+        // les dimensions ne sont calculables que si les puissances de 2 sont valides
+        if self.terrain_power_of_two <= 16 && self.mesh_power_of_two <= self.terrain_power_of_two {
+            let step = self.get_mesh_step();
+            for (field, value) in [("terrain_width", self.terrain_width), ("terrain_height", self.terrain_height)] {
+                if let Some(value) = value {
+                    // l'erosion a besoin d'au moins 2 points du mesh dans chaque direction
+                    v.check(value > step, field, value, format!("more than {} (2^(terrain_power_of_two - mesh_power_of_two))", step));
+                }
+            }
+
+            let (w, h) = self.get_terrain_size();
+
+            if self.tileable {
+                if self.heightmap_algorithm == HeightmapAlgorithm::DiamondSquare {
+                    // le diamond-square recadre son carre englobant, ce qui casserait la periodicite
+                    let square = 2_usize.pow(self.terrain_power_of_two) + 1;
+                    v.check((w, h) == (square, square), "tileable", self.tileable,
+                        format!("false unless terrain_width = terrain_height = {} (2^terrain_power_of_two + 1) with diamond_square", square));
+                } else {
+                    // les points du mesh doivent tomber sur les bords pour que l'erosion fasse le tour du terrain
+                    v.check((w - 1) % step == 0 && (h - 1) % step == 0, "tileable", self.tileable,
+                        format!("false unless terrain_width - 1 and terrain_height - 1 are multiples of {}", step));
+                }
+            }
+
+            if let Some(mesh_width) = self.mesh_width {
+                v.check_range("mesh_width", mesh_width, 2, w.max(2));
+            }
+            if let Some(mesh_height) = self.mesh_height {
+                v.check_range("mesh_height", mesh_height, 2, h.max(2));
+            }
+        }
+
+        v.check(self.max_terrain_height > 0.0, "max_terrain_height", self.max_terrain_height, "greater than 0".to_string());
+        v.check_range("irregularity", self.irregularity, 0.0, 1.0);
+
+        v.check_range("noise_octaves", self.noise_octaves, 1, 16);
+        v.check(self.noise_frequency > 0.0, "noise_frequency", self.noise_frequency, "greater than 0".to_string());
+        v.check(self.noise_lacunarity >= 1.0, "noise_lacunarity", self.noise_lacunarity, "1 or more".to_string());
+        v.check(self.noise_persistence > 0.0 && self.noise_persistence <= 1.0, "noise_persistence", self.noise_persistence,
+            "0 (excluded) to 1".to_string());
+
+        v.check_range("sun_angle", self.sun_angle, 0.0, 89.0);
+        v.check(self.sun_size >= 0.0, "sun_size", self.sun_size, "0 or more".to_string());
+        v.check(self.planet_radius > 0.0, "planet_radius", self.planet_radius, "greater than 0".to_string());
+        v.check(self.atmosphere_radius > self.planet_radius, "atmosphere_radius", self.atmosphere_radius,
+            format!("greater than planet_radius ({})", self.planet_radius));
+        v.check(self.ambient_sky_light >= 0.0, "ambient_sky_light", self.ambient_sky_light, "0 or more".to_string());
+
+        v.check_range("shadow_direction", self.shadow_direction, 0, 3);
+        if let Some(sun_azimuth) = self.sun_azimuth {
+            v.check_range("sun_azimuth", sun_azimuth, 0.0, 360.0);
+        }
+        if let Some(cell_size) = self.cell_size {
+            v.check(cell_size > 0.0, "cell_size", cell_size, "greater than 0".to_string());
+        }
+        v.check_range("rock_threshold", self.rock_threshold, 0.0, 90.0);
+        v.check(self.erosion_coloration >= 0.0, "erosion_coloration", self.erosion_coloration, "0 or more".to_string());
+        v.check_range("ambient_occlusion_rays", self.ambient_occlusion_rays, 0, 256);
+        v.check(self.ambient_occlusion_radius >= 1.0, "ambient_occlusion_radius", self.ambient_occlusion_radius, "1 or more".to_string());
+        v.check_range("ambient_occlusion_strength", self.ambient_occlusion_strength, 0.0, 1.0);
+
+        v.finish()
+    }
+
+    // contraintes supplementaires pour TerrainPipeline::generate_chunk: les chunks doivent se raccorder point a point,
+    // y compris a la resolution du mesh
+    pub fn validate_chunks(&self) -> Result<()> {
+        self.validate()?;
+
+        let mut v = Validator { section: "generation_options", violations: vec![] };
+
+        let (w, h) = self.get_terrain_size();
+        let step = self.get_mesh_step();
+
+        v.check(!self.tileable, "tileable", self.tileable, "false when generating chunks".to_string());
+
+        if self.heightmap_algorithm == HeightmapAlgorithm::DiamondSquare {
+            let square = 2_usize.pow(self.terrain_power_of_two) + 1;
+            for (field, value) in [("terrain_width", w), ("terrain_height", h)] {
+                v.check(value == square, field, value, format!("{} (2^terrain_power_of_two + 1) when generating diamond_square chunks", square));
+            }
+        } else {
+            for (field, value) in [("terrain_width", w), ("terrain_height", h)] {
+                v.check((value - 1) % step == 0, field, value, format!("a multiple of {} plus 1 when generating chunks", step));
+            }
+        }
+
+        // les zones erodees des chunks debordent d'au moins un point du mesh sur les voisins, sans depasser la moitie d'un chunk
+        for (field, value) in [("terrain_width", w), ("terrain_height", h)] {
+            v.check(value > 2 * step, field, value, format!("at least {} (a mesh of 3 points) when generating chunks", 2 * step + 1));
+        }
+
+        for (field, value, default) in [("mesh_width", self.mesh_width, (w - 1) / step + 1), ("mesh_height", self.mesh_height, (h - 1) / step + 1)] {
+            if let Some(value) = value {
+                v.check(value == default, field, value, format!("unset or {} when generating chunks", default));
+            }
+        }
+
+        v.finish()
+    }
+}
+
+
+impl ErosionOptions {
+    // rayon des gouttes, en points du niveau, au niveau level de erosion_level_iterations
+    pub fn get_level_radius(&self, level: usize) -> f64 {
+        (self.radius as f64 * self.erosion_radius_scaling.powi(level as i32)).round().max(1.0)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let mut v = Validator { section: "erosion", violations: vec![] };
+
+        v.check(0.0 <= self.inertia && self.inertia < 1.0, "inertia", self.inertia, "0 to 1 (excluded)".to_string());
+        v.check_range("radius", self.radius, 1, MAX_EROSION_RADIUS);
+        v.check(self.capacity_factor >= 0.0, "capacity_factor", self.capacity_factor, "0 or more".to_string());
+
+        v.check_range("deposition_speed", self.deposition_speed, 0.0, 1.0);
+        v.check_range("erosion_speed", self.erosion_speed, 0.0, 1.0);
+        v.check(0.0 <= self.evaporation_rate && self.evaporation_rate < 1.0, "evaporation_rate", self.evaporation_rate,
+            "0 to 1 (excluded)".to_string());
+        v.check(self.gravity >= 0.0, "gravity", self.gravity, "0 or more".to_string());
+        v.check(self.min_slope >= 0.0, "min_slope", self.min_slope, "0 or more".to_string());
+        v.check(self.min_capacity >= 0.0, "min_capacity", self.min_capacity, "0 or more".to_string());
+
+        v.check_range("talus_angle", self.talus_angle, 0.0, 89.0);
+        v.check(self.thermal_transfer_rate > 0.0 && self.thermal_transfer_rate <= 1.0, "thermal_transfer_rate", self.thermal_transfer_rate,
+            "0 (excluded) to 1".to_string());
+
+        v.check(self.pipe_time_step > 0.0 && self.pipe_time_step <= 1.0, "pipe_time_step", self.pipe_time_step, "0 (excluded) to 1".to_string());
+        v.check(self.rainfall_rate >= 0.0, "rainfall_rate", self.rainfall_rate, "0 or more".to_string());
+        v.check(self.pipe_evaporation_rate >= 0.0, "pipe_evaporation_rate", self.pipe_evaporation_rate, "0 or more".to_string());
+        v.check(self.pipe_sediment_capacity >= 0.0, "pipe_sediment_capacity", self.pipe_sediment_capacity, "0 or more".to_string());
+        v.check_range("pipe_dissolving_rate", self.pipe_dissolving_rate, 0.0, 1.0);
+        v.check_range("pipe_deposition_rate", self.pipe_deposition_rate, 0.0, 1.0);
+
+        v.check(self.erosion_radius_scaling > 0.0, "erosion_radius_scaling", self.erosion_radius_scaling, "greater than 0".to_string());
+        if self.erosion_radius_scaling > 1.0 && self.erosion_level_iterations.len() > 1 {
+            // le rayon grandit a chaque niveau: celui du dernier niveau (meme s'il est ignore au dela de la pleine resolution) reste borne
+            let last_level = self.erosion_level_iterations.len() - 1;
+            let max_radius = self.get_level_radius(last_level);
+            v.check(max_radius <= MAX_EROSION_RADIUS as f64, "erosion_radius_scaling", self.erosion_radius_scaling,
+                format!("small enough that radius * erosion_radius_scaling^{} is at most {} (it is {})", last_level, MAX_EROSION_RADIUS, max_radius));
+        }
+
+        v.check(self.hardness_source != HardnessSource::Layers || !self.hardness_layers.is_empty(), "hardness_layers", "[]",
+            "at least one layer when hardness_source is \"layers\"".to_string());
+        for hardness in self.hardness_layers.iter() {
+            v.check_range("hardness_layers", *hardness, 0.0, 1.0);
+        }
+        v.check(self.hardness_layer_thickness > 0.0, "hardness_layer_thickness", self.hardness_layer_thickness, "greater than 0".to_string());
+        v.check(self.hardness_noise_frequency > 0.0, "hardness_noise_frequency", self.hardness_noise_frequency, "greater than 0".to_string());
+        v.check_range("max_hardness", self.max_hardness, 0.0, 1.0);
+
+        v.finish()
+    }
+}
+
+
+impl RiverOptions {
+    pub fn validate(&self) -> Result<()> {
+        let mut v = Validator { section: "rivers", violations: vec![] };
+
+        v.check(self.river_threshold > 0.0 && self.river_threshold <= 1.0, "river_threshold", self.river_threshold,
+            "0 (excluded) to 1".to_string());
+        v.check(self.river_width > 0.0, "river_width", self.river_width, "greater than 0".to_string());
+        v.check(self.max_river_width >= self.river_width, "max_river_width", self.max_river_width,
+            format!("river_width ({}) or more", self.river_width));
+        v.check(self.river_width_exponent >= 0.0, "river_width_exponent", self.river_width_exponent, "0 or more".to_string());
+        v.check(self.river_depth >= 0.0, "river_depth", self.river_depth, "0 or more".to_string());
+
+        v.finish()
+    }
+}
+
+
+impl WaterOptions {
+    // vrai si la mer ou les lacs sont generes
+    pub fn is_enabled(&self) -> bool {
+        self.sea_level.is_some() || self.generate_lakes
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let mut v = Validator { section: "water", violations: vec![] };
+
+        v.check(self.min_lake_depth >= 0.0, "min_lake_depth", self.min_lake_depth, "0 or more".to_string());
+        v.check(self.beach_height > 0.0, "beach_height", self.beach_height, "greater than 0".to_string());
+
+        v.finish()
+    }
+}
+
+
+impl BiomeOptions {
+    pub fn validate(&self) -> Result<()> {
+        let mut v = Validator { section: "biomes", violations: vec![] };
+
+        v.check_range("latitude_top", self.latitude_top, -90.0, 90.0);
+        v.check_range("latitude_bottom", self.latitude_bottom, -90.0, 90.0);
+        v.check(self.moisture_distance > 0.0, "moisture_distance", self.moisture_distance, "greater than 0".to_string());
+        v.check(self.temperature_blend > 0.0, "temperature_blend", self.temperature_blend, "greater than 0".to_string());
+        v.check(self.moisture_blend > 0.0, "moisture_blend", self.moisture_blend, "greater than 0".to_string());
+
+        v.check_range("table", self.table.len(), 1, 256);
+        for biome in self.table.iter() {
+            let field = format!("table.{}", biome.name);
+            v.check(biome.temperature[0] <= biome.temperature[1], &format!("{}.temperature", field), format!("{:?}", biome.temperature),
+                "[min, max] with min <= max".to_string());
+            v.check(biome.moisture[0] <= biome.moisture[1], &format!("{}.moisture", field), format!("{:?}", biome.moisture),
+                "[min, max] with min <= max".to_string());
+            for channel in biome.color {
+                v.check_range(&format!("{}.color", field), channel, 0.0, 255.0);
+            }
+        }
+
+        v.finish()
+    }
+}
+
+
+impl TerrainAnalysisOptions {
+    pub fn validate(&self) -> Result<()> {
+        let mut v = Validator { section: "terrain_analysis", violations: vec![] };
+
+        v.check_range("tpi_radius", self.tpi_radius, 1, 256);
+
+        v.finish()
+    }
+}
+
+
+impl HeightmapExportOptions {
+    pub fn get_absolute_height_range(&self, settings: &GenerationOptions) -> (f32, f32) {
+        self.absolute_height_range.map(|[low, high]| (low, high)).unwrap_or((0.0, 2.0 * settings.max_terrain_height))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let mut v = Validator { section: "heightmap_export", violations: vec![] };
+
+        if let Some([low, high]) = self.absolute_height_range {
+            v.check(low < high, "absolute_height_range", format!("{:?}", [low, high]), "[min, max] with min < max".to_string());
+        }
+
+        v.finish()
+    }
+}
+
+
+// verifie la section [[materials]]
+pub fn validate_materials(materials: &[Material]) -> Result<()> {
+    let mut v = Validator { section: "materials", violations: vec![] };
+
+    for material in materials.iter() {
+        let field = |name: &str| format!("{}.{}", material.name, name);
+
+        v.check(material.color.is_some() || !material.color_ramp.is_empty(), &field("color"), "none",
+            "a color or a color_ramp".to_string());
+        for channel in material.color.iter().flatten() {
+            v.check_range(&field("color"), *channel, 0.0, 255.0);
+        }
+        for (i, stop) in material.color_ramp.iter().enumerate() {
+            for channel in &stop[1..] {
+                v.check_range(&field("color_ramp"), *channel, 0.0, 255.0);
+            }
+            if i > 0 {
+                v.check(stop[0] > material.color_ramp[i - 1][0], &field("color_ramp"), stop[0],
+                    format!("a height greater than the previous one ({})", material.color_ramp[i - 1][0]));
+            }
+        }
+        v.check_range(&field("opacity"), material.opacity, 0.0, 1.0);
+        v.check(material.noise_frequency > 0.0, &field("noise_frequency"), material.noise_frequency, "greater than 0".to_string());
+
+        for (name, condition) in [("height", &material.height), ("slope", &material.slope), ("plan_curvature", &material.plan_curvature),
+                                  ("profile_curvature", &material.profile_curvature), ("tpi", &material.tpi), ("noise", &material.noise)] {
+            if let Some(condition) = condition {
+                v.check(condition.min <= condition.max, &field(name), format!("{} to {}", condition.min, condition.max),
+                    "min <= max".to_string());
+                v.check(condition.blend >= 0.0, &field(&format!("{}.blend", name)), condition.blend, "0 or more".to_string());
+            }
+        }
+    }
+
+    v.finish()
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     pub launch_options: LaunchOptions,
-    pub generation_options: GenerationOptions
+    pub generation_options: GenerationOptions,
+    #[serde(default)]
+    pub erosion: ErosionOptions,
+    #[serde(default)]
+    pub rivers: RiverOptions,
+    #[serde(default)]
+    pub water: WaterOptions,
+    #[serde(default)]
+    pub biomes: BiomeOptions,
+    #[serde(default)]
+    pub normal_map: NormalMapOptions,
+    #[serde(default)]
+    pub terrain_analysis: TerrainAnalysisOptions,
+    #[serde(default)]
+    pub heightmap_export: HeightmapExportOptions,
+    // materiaux de la texture, vide: roche et neige
+    #[serde(default)]
+    pub materials: Vec<Material>
 }
 
 
 impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
+    pub fn validate(&self) -> Result<()> {
+        // rapporte les erreurs de toutes les sections en une fois
+        let mut violations = vec![];
+        for result in [self.generation_options.validate(), self.erosion.validate(), self.rivers.validate(), self.water.validate(), self.biomes.validate(),
+            self.terrain_analysis.validate(), self.heightmap_export.validate(), validate_materials(&self.materials)] {
+            match result {
+                Err(Error::InvalidSettings(v)) => violations.extend(v),
+                Err(e) => return Err(e),
+                Ok(()) => ()
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidSettings(violations))
+        }
+    }
+
+    pub fn new() -> Result<Self> {
+        Settings::load("Settings", &[])
+    }
+
+    // charge le fichier de parametres puis remplace les valeurs donnees sous la forme (nom du champ, valeur)
+    pub fn load<P: AsRef<Path>>(path: P, overrides: &[(String, String)]) -> Result<Self> {
+        Settings::load_config(path.as_ref(), overrides)
+            .map_err(|source| Error::Settings { path: path.as_ref().to_path_buf(), source })
+    }
+
+    fn load_config(path: &Path, overrides: &[(String, String)]) -> std::result::Result<Self, ConfigError> {
         let mut s = Config::default();
 
         // Start off by merging in the "default" configuration file
-        s.merge(File::with_name("Settings"))?;
+        s.merge(File::from(path))?;
+
+        for (key, value) in overrides {
+            let full_key = Settings::find_key(&s, key);
+            s.set(&full_key, value.as_str())?;
+        }
 
         s.try_into()
     }
+
+    // retrouve la section a laquelle appartient un champ ("seed" -> "generation_options.seed"), un champ absent
+    // du fichier est suppose appartenir a erosion, rivers, water, biomes, normal_map, terrain_analysis ou heightmap_export s'il en fait partie, a generation_options
+    // sinon (un nom invalide est alors refuse par deny_unknown_fields)
+    fn find_key(s: &Config, key: &str) -> String {
+        if key.contains('.') {
+            return key.to_string()
+        }
+
+        for section in SETTINGS_SECTIONS.iter() {
+            let full_key = format!("{}.{}", section, key);
+            if s.get::<config::Value>(&full_key).is_ok() {
+                return full_key
+            }
+        }
+
+        if EROSION_FIELDS.contains(&key) {
+            return format!("erosion.{}", key)
+        }
+        if RIVER_FIELDS.contains(&key) {
+            return format!("rivers.{}", key)
+        }
+        if WATER_FIELDS.contains(&key) {
+            return format!("water.{}", key)
+        }
+        if BIOME_FIELDS.contains(&key) {
+            return format!("biomes.{}", key)
+        }
+        if NORMAL_MAP_FIELDS.contains(&key) {
+            return format!("normal_map.{}", key)
+        }
+        if TERRAIN_ANALYSIS_FIELDS.contains(&key) {
+            return format!("terrain_analysis.{}", key)
+        }
+        if HEIGHTMAP_EXPORT_FIELDS.contains(&key) {
+            return format!("heightmap_export.{}", key)
+        }
+
+        format!("generation_options.{}", key)
+    }
 }
 
 
@@ -1,5 +1,3 @@
-use std::{fs::File, io::Write};
-
 use crate::utils::{ColorMapArray, HALF_PI, PI};
 
 
@@ -13,6 +11,12 @@ pub struct LightSpectrum {
     color_curves: Option<[[f32; 3]; LENGTH]>
 }
 
+impl Default for LightSpectrum {
+    fn default() -> Self {
+        LightSpectrum::new()
+    }
+}
+
 impl LightSpectrum {
     pub fn new() -> LightSpectrum {
 
@@ -49,38 +53,31 @@ impl LightSpectrum {
 
         let mut color = self.colors[closest].1;
 
-        for i in 0..LENGTH {
+        for (i, curve) in curves.iter_mut().enumerate() {
             value = i as f32 / LENGTH_F32;
             while closest != 0 && f32::abs(normalized_wl[closest] - value) > f32::abs(normalized_wl[closest - 1] - value) {
                 closest -= 1;
                 color = self.colors[closest].1;
             }
 
-            curves[i][0] = color.0;
-            curves[i][1] = color.1;
-            curves[i][2] = color.2;
+            *curve = [color.0, color.1, color.2];
 
         }
 
-        // make the curves continuous
+        // make the curves continuous (les trois couches sont independantes et lissees ensemble)
         let mut i2: usize;
 
-        for layer_id in 0..3 {
-
-            for j in (1..=n).rev() {
-                for i in j..(LENGTH - j) {
-                    i2 = LENGTH - 1 - i;
-    
-                    curves[i][layer_id] = (curves[i - j][layer_id] + curves[i + j][layer_id]) / 2.0;
-                    curves[i2][layer_id] = (curves[i2 - j][layer_id] + curves[i2 + j][layer_id]) / 2.0;
-                }
-                
-                for to_correct in 0..j {
-                    curves[to_correct][layer_id] = curves[j][layer_id];
-                    curves[LENGTH - to_correct - 1][layer_id] = curves[LENGTH - 1 - j][layer_id];
-                }
+        for j in (1..=n).rev() {
+            for i in j..(LENGTH - j) {
+                i2 = LENGTH - 1 - i;
 
+                curves[i] = [0, 1, 2].map(|layer_id| (curves[i - j][layer_id] + curves[i + j][layer_id]) / 2.0);
+                curves[i2] = [0, 1, 2].map(|layer_id| (curves[i2 - j][layer_id] + curves[i2 + j][layer_id]) / 2.0);
+            }
 
+            for to_correct in 0..j {
+                curves[to_correct] = curves[j];
+                curves[LENGTH - to_correct - 1] = curves[LENGTH - 1 - j];
             }
         }
 
@@ -110,12 +107,12 @@ impl LightSpectrum {
         let mut counter: usize = 0;
         let mut value: f32;
 
-        for i in 0..LENGTH {
+        for (i, point) in curve.iter_mut().enumerate() {
             value = i as f32 / LENGTH_F32;
             while value > amounts_vec[counter] {
                 counter += 1;
             }
-            curve[i] = self.colors[counter].0;
+            *point = self.colors[counter].0;
         }
 
         // make the curve continuous
@@ -142,7 +139,7 @@ impl LightSpectrum {
 
     pub fn get_wl(&self, value: f32) -> Option<f32> {
         if let Some(curve) = &self.wl_curve {
-            let value = value.max(0.0).min(1.0);
+            let value = value.clamp(0.0, 1.0);
         
 
             let i1: usize = (value * 999.0) as usize;
@@ -162,7 +159,7 @@ impl LightSpectrum {
         if let Some(curves) = &self.color_curves {
             let mut color = (0_f32, 0_f32, 0_f32);
 
-            let value = self.normalize(wavelength).max(0.0).min(1.0);
+            let value = self.normalize(wavelength).clamp(0.0, 1.0);
         
 
             let i1: usize = (value * 999.0) as usize;
@@ -247,6 +244,7 @@ pub fn compute_diffusion_rate(distance: f32, wavelength: f32) -> f32 {
 }
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn generate_sky_colormap(
     colormap: &mut ColorMapArray, w: usize,
     planet_radius: f32, atmosphere_radius: f32,
@@ -288,11 +286,6 @@ pub fn generate_sky_colormap(
     sun_col_out[2] = sun_color.2;
 
 
-    {
-        let mut file = File::create("cfg.data").expect("welp");
-        file.write_all(format!("{}\n{}\n{}", sun_color.0, sun_color.1, sun_color.2).as_bytes()).expect("welp2");
-    }
-
     let center = (w / 2) as i32;
     let squared_sphere_radius = center.pow(2);
     let mut squared_distance_from_center: i32;
@@ -316,7 +309,7 @@ pub fn generate_sky_colormap(
         for y in 0..w {
 
             squared_distance_from_center = (x as i32 - center).pow(2) + (y as i32 - center).pow(2);
-            if squared_distance_from_center <= squared_sphere_radius + squared_sphere_radius / 50 * 0 {
+            if squared_distance_from_center <= squared_sphere_radius {
                 if let Some(pixel) = colormap.get_mut_pixel(w - 1 - y, x) {
                     
 
@@ -0,0 +1,126 @@
+use crate::utils::{Arr2d, parallel_map};
+
+
+// nom, carte et valeurs du noir et du blanc a l'export, voir TerrainAnalysis::named_maps
+pub type NamedMap<'a> = (&'static str, &'a Arr2d<f32>, Option<(f32, f32)>);
+
+
+// cartes de meme taille que la heightmap analysee, en unites reelles: les distances horizontales sont ramenees en unites
+// de hauteur par la largeur d'un point (cell_size)
+#[derive(Clone)]
+pub struct TerrainAnalysis {
+    // pente en degres, de 0 (plat) a 90
+    pub slope: Arr2d<f32>,
+    // orientation de la pente (direction de la descente) en degres: 0 vers le haut de la heightmap, 90 vers la droite, -1 si plat
+    pub aspect: Arr2d<f32>,
+    // courbures en plan (des courbes de niveau) et de profil (le long de la pente), par unite de hauteur:
+    // positives sur les zones convexes (cretes, ruptures de pente), negatives dans les zones concaves (vallees, pieds de pente)
+    pub plan_curvature: Arr2d<f32>,
+    pub profile_curvature: Arr2d<f32>,
+    // hauteur moins la hauteur moyenne du carre de cote 2 * tpi_radius + 1 points centre sur le point:
+    // positive sur les sommets et les cretes, negative dans les creux
+    pub tpi: Arr2d<f32>
+}
+
+impl TerrainAnalysis {
+    // nom du fichier sans extension, None a la place des valeurs du noir et du blanc pour les cartes sans bornes
+    pub fn named_maps(&self) -> Vec<NamedMap<'_>> {
+        vec![("slopemap", &self.slope, Some((0.0, 90.0))), ("aspectmap", &self.aspect, Some((-1.0, 360.0))),
+            ("plancurvaturemap", &self.plan_curvature, None), ("profilecurvaturemap", &self.profile_curvature, None), ("tpimap", &self.tpi, None)]
+    }
+
+    pub fn extract(&self, x: usize, y: usize, width: usize, height: usize) -> TerrainAnalysis {
+        TerrainAnalysis {
+            slope: self.slope.extract(x, y, width, height),
+            aspect: self.aspect.extract(x, y, width, height),
+            plan_curvature: self.plan_curvature.extract(x, y, width, height),
+            profile_curvature: self.profile_curvature.extract(x, y, width, height),
+            tpi: self.tpi.extract(x, y, width, height)
+        }
+    }
+}
+
+
+// hauteur du point (x, y): hors de la heightmap, le point du bord le plus proche, ou celui de l'autre cote d'un terrain qui se repete
+pub fn clamped_height(heightmap: &Arr2d<f32>, x: i32, y: i32, tileable: bool) -> f32 {
+    if tileable {
+        *heightmap.get_wrapped(x, y)
+    } else {
+        *heightmap.get(x.clamp(0, heightmap.get_width() as i32 - 1) as usize, y.clamp(0, heightmap.get_height() as i32 - 1) as usize).unwrap()
+    }
+}
+
+
+// derivees de Horn (Sobel) de la hauteur au point (x, y), selon x et selon y (vers le bas de la heightmap), en unites de
+// hauteur par unite de hauteur; cell_size: largeur d'un point de heightmap en unites de hauteur
+pub fn sobel_gradient(heightmap: &Arr2d<f32>, x: i32, y: i32, cell_size: f32, tileable: bool) -> (f32, f32) {
+    let get = |x: i32, y: i32| clamped_height(heightmap, x, y, tileable);
+    let dx = (get(x + 1, y - 1) + 2.0 * get(x + 1, y) + get(x + 1, y + 1) - get(x - 1, y - 1) - 2.0 * get(x - 1, y) - get(x - 1, y + 1)) / (8.0 * cell_size);
+    let dy = (get(x - 1, y + 1) + 2.0 * get(x, y + 1) + get(x + 1, y + 1) - get(x - 1, y - 1) - 2.0 * get(x, y - 1) - get(x + 1, y - 1)) / (8.0 * cell_size);
+    (dx, dy)
+}
+
+
+// derivees sur la fenetre 3x3 de chaque point: premieres derivees de Horn (Sobel), secondes derivees d'Evans
+// cell_size: largeur d'un point de heightmap en unites de hauteur
+pub fn analyse_terrain(heightmap: &Arr2d<f32>, cell_size: f32, tpi_radius: usize, tileable: bool, threads: usize) -> TerrainAnalysis {
+    println!("analysing terrain...");
+
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
+
+    let get = |x: i32, y: i32| clamped_height(heightmap, x, y, tileable);
+
+    // moyenne de la fenetre du tpi: moyenne glissante sur les lignes puis sur les colonnes
+    let radius = tpi_radius as i32;
+    let window = (2 * radius + 1) as f32;
+    let row_means: Vec<Vec<f32>> = parallel_map(height, threads, |y| {
+        (0..width as i32).map(|x| (-radius..=radius).map(|dx| get(x + dx, y as i32)).sum::<f32>() / window).collect()
+    });
+    let row_mean = |x: i32, y: i32| {
+        let y = if tileable { y.rem_euclid(height as i32 - 1) } else { y.clamp(0, height as i32 - 1) };
+        row_means[y as usize][x as usize]
+    };
+
+    let columns: Vec<Vec<[f32; 5]>> = parallel_map(width, threads, |x| {
+        let x = x as i32;
+        (0..height as i32).map(|y| {
+            // y vers le bas de la heightmap
+            let (p, q) = sobel_gradient(heightmap, x, y, cell_size, tileable);
+            let center = get(x, y);
+            let r = (get(x - 1, y) - 2.0 * center + get(x + 1, y)) / (cell_size * cell_size);
+            let t = (get(x, y - 1) - 2.0 * center + get(x, y + 1)) / (cell_size * cell_size);
+            let s = (get(x + 1, y + 1) + get(x - 1, y - 1) - get(x + 1, y - 1) - get(x - 1, y + 1)) / (4.0 * cell_size * cell_size);
+
+            let squared_gradient = p * p + q * q;
+            let slope = squared_gradient.sqrt().atan().to_degrees();
+
+            let (aspect, plan_curvature, profile_curvature) = if squared_gradient > f32::EPSILON {
+                // la descente va dans la direction (-p, -q)
+                let aspect = (-p).atan2(q).to_degrees().rem_euclid(360.0);
+                // derivees secondes dans la direction des courbes de niveau et dans celle de la pente (Zevenbergen et Thorne)
+                let plan_curvature = -(q * q * r - 2.0 * p * q * s + p * p * t) / squared_gradient;
+                let profile_curvature = -(p * p * r + 2.0 * p * q * s + q * q * t) / squared_gradient;
+                (aspect, plan_curvature, profile_curvature)
+            } else {
+                (-1.0, 0.0, 0.0)
+            };
+
+            let tpi = center - (-radius..=radius).map(|dy| row_mean(x, y + dy)).sum::<f32>() / window;
+
+            [slope, aspect, plan_curvature, profile_curvature, tpi]
+        }).collect()
+    });
+
+    let mut maps: [Arr2d<f32>; 5] = [(); 5].map(|_| Arr2d::zeros(width, height));
+    for (x, column) in columns.into_iter().enumerate() {
+        for (y, values) in column.into_iter().enumerate() {
+            for (map, value) in maps.iter_mut().zip(values) {
+                *map.get_mut(x, y).unwrap() = value;
+            }
+        }
+    }
+
+    let [slope, aspect, plan_curvature, profile_curvature, tpi] = maps;
+    TerrainAnalysis { slope, aspect, plan_curvature, profile_curvature, tpi }
+}
@@ -1,28 +1,51 @@
 use rand_core::RngCore;
 use rand_pcg::Mcg128Xsl64;
 
-use crate::{utils::{Arr2d, ColorMapArray, HALF_PI, TWO_POW_15_F32, bilinear_interpolation}, settings::GenerationOptions};
+use crate::{utils::{Arr2d, ColorMapArray, PI, HALF_PI, TWO_POW_15_F32, bilinear_interpolation, line_rng, parallel_map}, settings::GenerationOptions, erosion::ErosionMaps,
+    water::WaterMaps, biomes::BiomeMaps, materials::Palette, terrain_analysis::TerrainAnalysis};
+
+// quantites erodees / deposees, en fraction de max_terrain_height, pour lesquelles la coloration atteint ~63% de son intensite
+const ERODED_COLORATION_SCALE: f32 = 0.05;
+const DEPOSITED_COLORATION_SCALE: f32 = 0.01;
+// profondeur d'eau, en fraction de max_terrain_height, pour laquelle l'eau a pris ~63% de la couleur de l'eau profonde
+const WATER_DEPTH_COLORATION_SCALE: f32 = 0.05;
+// part de ambient_color rendue aux points dont le ciel est cache par l'occlusion ambiante
+const AMBIENT_OCCLUSION_FILL: f32 = 0.1;
+// nombre de pentes, du bas au haut du disque solaire, dont l'ombre est suivie pour la penombre de add_azimuth_shadow
+const SUN_DISC_SAMPLES: usize = 5;
 
 pub fn generate_f32_2(h: f32, rng: &mut Mcg128Xsl64) -> f32 {
     let n = (rng.next_u32() >> 16) as f32 - TWO_POW_15_F32;
 
-    ((n as f32) / TWO_POW_15_F32) * h
+    (n / TWO_POW_15_F32) * h
 }
 
-pub fn generate_terrain_texture(output: &mut ColorMapArray, heightmap: &mut Arr2d<f32>, gradientmap: &Arr2d<f32>, scale_divisor: usize,
-     width: usize, ref_height: f32, shadow_direction: u8, sun_angle: f32, rng: &mut Mcg128Xsl64, ambient_color: &[f32;3], sun_color: &[f32;3], settings: &GenerationOptions) {
+// output est stocke transpose: le pixel (x, y) de la heightmap correspond a output(y, x)
+// les cartes de l'analyse du terrain sont a la resolution du mesh, celles de l'eau et des biomes a la resolution de la heightmap
+// sun_azimuth (radians): ombres calculees le long de n'importe quelle direction, a la place de shadow_direction
+// renvoie le masque des ombres et, si l'occlusion ambiante est activee, la part du ciel visible, de meme taille que la heightmap
+#[allow(clippy::too_many_arguments)]
+pub fn generate_terrain_texture(output: &mut ColorMapArray, heightmap: &mut Arr2d<f32>, analysis: &TerrainAnalysis, erosion_maps: &ErosionMaps,
+     water: Option<&WaterMaps>, biomes: Option<&BiomeMaps>, palette: Option<&Palette>, cell_size: f32, shadow_direction: u8, sun_azimuth: Option<f32>, sun_angle: f32, rng: &mut Mcg128Xsl64, ambient_color: &[f32;3], sun_color: &[f32;3], settings: &GenerationOptions) -> (Arr2d<f32>, Option<Arr2d<f32>>) {
 
-    let mut noise: f32;
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
 
-    add_environment_coloration(output, heightmap, gradientmap, width, scale_divisor, ref_height, rng, settings);
+    let threads = settings.get_threads();
 
-    for x in 0..width {
-        for y in 0..width {
+    add_environment_coloration(output, heightmap, analysis, erosion_maps, water, biomes, palette, rng, settings);
 
-            if let Some(pixel) = output.get_mut_pixel(y, x) {
+    // un generateur par ligne pour que le bruit ne depende pas du nombre de threads
+    let base = rng.next_u64();
+    let noises: Vec<Vec<f32>> = parallel_map(width, threads, |x| {
+        let mut rng = line_rng(base, x);
+        (0..height).map(|_| generate_f32_2(0.01, &mut rng)).collect()
+    });
 
-                noise = generate_f32_2(0.01, rng);
+    for (x, line) in noises.iter().enumerate() {
+        for (y, noise) in line.iter().enumerate() {
 
+            if let Some(pixel) = output.get_mut_pixel(y, x) {
                 *pixel.0 += noise;
                 *pixel.1 += noise;
                 *pixel.2 += noise;
@@ -32,87 +55,191 @@ pub fn generate_terrain_texture(output: &mut ColorMapArray, heightmap: &mut Arr2
         }
     }
     
-    add_shadow(output, heightmap, width, shadow_direction, sun_angle, ref_height, ambient_color, sun_color);
-
-}
-
+    // la surface de l'eau, plane, recoit et projette les ombres a la place du fond
+    let surface = water.map(|water| {
+        let mut surface = heightmap.clone();
+        for (height, depth) in surface.get_mut_vec().iter_mut().zip(water.depth.get_vec().iter()) {
+            *height += depth;
+        }
+        surface
+    });
 
-pub fn add_environment_coloration(output: &mut ColorMapArray, _heightmap: &Arr2d<f32>,
-     gradientmap: &Arr2d<f32>, width: usize, scale_divisor: usize, _ref_height: f32, _rng: &mut Mcg128Xsl64, settings: &GenerationOptions) {
+    let surface = surface.as_ref().unwrap_or(heightmap);
 
-    let color_for_environements: [[f32;3]; 3] = [
-        [87.0 / 255.0, 93.0 / 255.0, 98.0 / 255.0],  // roche sombre
-        [185.0 / 255.0, 180.0 / 255.0, 171.0 / 255.0], // roche
-        //[97.0 / 255.0, 109.0 / 255.0, 74.0 / 255.0] // vegetation
-        [1.2, 1.2, 1.2]
-    ];
-
-    let mut current_amounts: [f32; 3];
+    // meme rapport entre hauteurs et distances que pour les ombres
+    let sky_view = if settings.ambient_occlusion_rays > 0 {
+        let sky_view = generate_sky_view_map(surface, settings.ambient_occlusion_rays, settings.ambient_occlusion_radius, cell_size,
+            settings.tileable, threads);
+        add_ambient_occlusion(output, &sky_view, settings.ambient_occlusion_strength, ambient_color);
+        Some(sky_view)
+    } else {
+        None
+    };
 
-    let mut gradient: f32;
+    let shadow_mask = match sun_azimuth {
+        // rayon angulaire du disque solaire, comme dans le ciel (sky_generation::add_lighting_and_sun_effect)
+        Some(azimuth) => add_azimuth_shadow(output, surface, azimuth, sun_angle, settings.sun_size * PI, cell_size, ambient_color, sun_color,
+            settings.tileable, threads),
+        None => add_shadow(output, surface, shadow_direction, sun_angle, cell_size, ambient_color, sun_color, settings.tileable, threads)
+    };
 
-    let mut color: [f32; 3];
+    (shadow_mask, sky_view)
+}
 
-    let mut x_gradientmap: usize;
-    let mut y_gradientmap: usize;
 
-    let mut gradient_total: f32 = 0.0;
-    let mut number_of_points: f32 = 0.0;
+// valeur de map, a la resolution du mesh, au point (x_position, y_position) de ses coordonnees
+fn sample_mesh_map(map: &Arr2d<f32>, x_position: f32, y_position: f32) -> f32 {
+    let x = usize::min(x_position as usize, map.get_width() - 1);
+    let y = usize::min(y_position as usize, map.get_height() - 1);
+    let x1 = usize::min(x + 1, map.get_width() - 1);
+    let y1 = usize::min(y + 1, map.get_height() - 1);
 
-    for x in 0..width {
-        for y in 0..width {
+    bilinear_interpolation(x_position - x as f32, y_position - y as f32,
+        *map.get(x, y).unwrap(), *map.get(x, y1).unwrap(), *map.get(x1, y).unwrap(), *map.get(x1, y1).unwrap())
+}
 
-            if let Some(pixel) = output.get_mut_pixel(y, x) {
+#[allow(clippy::too_many_arguments)]
+pub fn add_environment_coloration(output: &mut ColorMapArray, heightmap: &Arr2d<f32>,
+     analysis: &TerrainAnalysis, erosion_maps: &ErosionMaps, water: Option<&WaterMaps>, biomes: Option<&BiomeMaps>,
+     palette: Option<&Palette>, _rng: &mut Mcg128Xsl64, settings: &GenerationOptions) {
 
-                x_gradientmap = x.div_euclid(scale_divisor);
-                y_gradientmap = y.div_euclid(scale_divisor);
-
-                if let Some(v11) = gradientmap.get(x_gradientmap + 1, y_gradientmap + 1) {
-                    gradient = bilinear_interpolation(
-                        (x - x_gradientmap * scale_divisor) as f32 / scale_divisor as f32,
-                        (y - y_gradientmap * scale_divisor) as f32 / scale_divisor as f32,
-                        *gradientmap.get(x_gradientmap, y_gradientmap).unwrap_or_else(|| {println!("setting gradient to default at {} {}", x_gradientmap, y_gradientmap); &0.0}),
-                        *gradientmap.get(x_gradientmap, y_gradientmap + 1).unwrap_or_else(|| {println!("setting gradient to default at {} {}", x_gradientmap, y_gradientmap + 1); &0.0}), 
-                        *gradientmap.get(x_gradientmap + 1, y_gradientmap).unwrap_or_else(|| {println!("setting gradient to default at {} {}", x_gradientmap + 1, y_gradientmap); &0.0}), 
-                        *v11)
-                } else {
-                    gradient = *gradientmap.get(x_gradientmap, y_gradientmap).unwrap_or_else(|| {println!("setting gradient to default at {} {}", x_gradientmap, y_gradientmap); &0.0});
-                }
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
 
-                gradient *= 2.0;
-          
-                gradient_total += gradient;
-                number_of_points += 1.0;
+    // les cartes de l'analyse du terrain sont a la resolution du mesh: rapport entre leurs coordonnees et celles de la heightmap
+    let x_ratio = (analysis.slope.get_width() - 1) as f32 / (width - 1) as f32;
+    let y_ratio = (analysis.slope.get_height() - 1) as f32 / (height - 1) as f32;
 
-                current_amounts = [0.0, 0.0, 0.0];
+    // couleurs sans materiaux, reprises par les materiaux par defaut de Settings.toml
+    let color_for_environements: [[f32;3]; 2] = [
+        [185.0 / 255.0, 180.0 / 255.0, 171.0 / 255.0], // roche
+        [1.0, 1.0, 1.0] // neige
+    ];
 
-                current_amounts[1] += gradient.round();
-                current_amounts[2] += (1.0 - gradient).round();
+    // sol mis a nu dans les ravines, et sediments des cones de dejection
+    let gully_color: [f32; 3] = [115.0 / 255.0, 97.0 / 255.0, 82.0 / 255.0];
+    let sediment_color: [f32; 3] = [168.0 / 255.0, 153.0 / 255.0, 122.0 / 255.0];
+
+    // eau peu profonde et profonde, sable sec et mouille des plages
+    let shallow_water_color: [f32; 3] = [64.0 / 255.0, 142.0 / 255.0, 156.0 / 255.0];
+    let deep_water_color: [f32; 3] = [16.0 / 255.0, 46.0 / 255.0, 86.0 / 255.0];
+    let sand_color: [f32; 3] = [214.0 / 255.0, 197.0 / 255.0, 152.0 / 255.0];
+    let wet_sand_color: [f32; 3] = [146.0 / 255.0, 128.0 / 255.0, 98.0 / 255.0];
+    let water_depth_scale = WATER_DEPTH_COLORATION_SCALE * settings.max_terrain_height;
+
+    let eroded_scale = ERODED_COLORATION_SCALE * settings.max_terrain_height;
+    let deposited_scale = DEPOSITED_COLORATION_SCALE * settings.max_terrain_height;
+    let eroded = erosion_maps.eroded.as_ref().filter(|_| settings.erosion_coloration > 0.0);
+    let deposited = erosion_maps.deposited.as_ref().filter(|_| settings.erosion_coloration > 0.0);
+    // les cartes de l'erosion sont a la resolution du premier niveau de l'erosion: celle du mesh, ou la pleine resolution
+    let (erosion_x_ratio, erosion_y_ratio) = eroded.or(deposited).map_or((x_ratio, y_ratio), |map| {
+        ((map.get_width() - 1) as f32 / (width - 1) as f32, (map.get_height() - 1) as f32 / (height - 1) as f32)
+    });
+
+    // chaque ligne (x constant) est calculee en parallele: couleur ajoutee a chaque pixel et somme des pentes
+    let lines: Vec<(Vec<[f32; 3]>, f32)> = {
+        let output: &ColorMapArray = output;
+        parallel_map(width, settings.get_threads(), |x| {
+            let mut x_position: f32;
+            let mut y_position: f32;
+
+            let mut slope: f32;
+
+            let mut color: [f32; 3];
+
+            let mut slope_total: f32 = 0.0;
+            let mut line: Vec<[f32; 3]> = Vec::with_capacity(height);
+
+            for y in 0..height {
+
+                if output.get_pixel(y, x).is_some() {
+
+                    x_position = x as f32 * x_ratio;
+                    y_position = y as f32 * y_ratio;
+
+                    slope = sample_mesh_map(&analysis.slope, x_position, y_position);
+                    slope_total += slope;
+
+                    // roche sur les pentes et neige (ou biome) ailleurs, sous les materiaux s'il y en a
+                    // la roche s'assombrit avec la pente, jusqu'au quart de sa couleur a la verticale
+                    color = if slope > settings.rock_threshold {
+                        let darkening = 0.75 * (slope - settings.rock_threshold) / (90.0 - settings.rock_threshold);
+                        color_for_environements[0].map(|channel| channel * (1.0 - darkening))
+                    } else if let Some(biomes) = biomes {
+                        // les zones peu pentues prennent la couleur de leur biome au lieu de la neige
+                        *biomes.colors.get(x, y).unwrap()
+                    } else {
+                        color_for_environements[1]
+                    };
+                    if let Some(palette) = palette {
+                        let biome_color = biomes.map(|biomes| *biomes.colors.get(x, y).unwrap());
+                        let sample = |map: &Arr2d<f32>| sample_mesh_map(map, x_position, y_position);
+                        color = palette.color(x, y, *heightmap.get(x, y).unwrap(), slope, sample(&analysis.plan_curvature),
+                            sample(&analysis.profile_curvature), sample(&analysis.tpi), biome_color, color);
+                    }
 
-                if current_amounts[1] >= 0.0 {
-                    current_amounts[0] += 0.0;
-                    current_amounts[1] -= current_amounts[0];
-                }
+                    // les ravines puis les depots sont melanges a la couleur, d'autant plus que l'erosion y a ete forte
+                    if let Some(eroded) = eroded {
+                        let amount = 1.0 - (-sample_mesh_map(eroded, x as f32 * erosion_x_ratio, y as f32 * erosion_y_ratio) / eroded_scale).exp();
+                        let t = (amount * settings.erosion_coloration).clamp(0.0, 1.0) * 0.5;
+                        for channel in 0..=2 {
+                            color[channel] = color[channel] * (1.0 - t) + gully_color[channel] * t;
+                        }
+                    }
+                    if let Some(deposited) = deposited {
+                        let amount = 1.0 - (-sample_mesh_map(deposited, x as f32 * erosion_x_ratio, y as f32 * erosion_y_ratio) / deposited_scale).exp();
+                        let t = (amount * settings.erosion_coloration).clamp(0.0, 1.0) * 0.7;
+                        for channel in 0..=2 {
+                            color[channel] = color[channel] * (1.0 - t) + sediment_color[channel] * t;
+                        }
+                    }
 
-                color = [0.0, 0.0, 0.0];
-                for channel in 0..=2 {
-                    for j in 0..color_for_environements.len() {
-                        color[channel] += color_for_environements[j][channel] * current_amounts[j].min(1.0).max(0.0);
+                    // l'eau recouvre le fond d'autant plus qu'elle est profonde, le sable est mouille pres de l'eau
+                    if let Some(water) = water {
+                        if *water.mask.get(x, y).unwrap() > 0.0 {
+                            let t = 1.0 - (-*water.depth.get(x, y).unwrap() / water_depth_scale).exp();
+                            let opacity = 0.6 + 0.4 * t;
+                            for channel in 0..=2 {
+                                let water_color = shallow_water_color[channel] * (1.0 - t) + deep_water_color[channel] * t;
+                                color[channel] = color[channel] * (1.0 - opacity) + water_color * opacity;
+                            }
+                        } else {
+                            let shore = *water.shore.get(x, y).unwrap();
+                            let wetness = shore * shore;
+                            let t = f32::min(shore * 2.0, 1.0);
+                            for channel in 0..=2 {
+                                let beach_color = sand_color[channel] * (1.0 - wetness) + wet_sand_color[channel] * wetness;
+                                color[channel] = color[channel] * (1.0 - t) + beach_color * t;
+                            }
+                        }
                     }
+
+                    line.push(color);
+                } else {
+                    line.push([0.0, 0.0, 0.0]);
                 }
+            }
 
-                *pixel.0 += if gradient > settings.rock_threshold { color_for_environements[1][0] * (1.5 - gradient + 1.0) / 2.0} else {color_for_environements[2][0]};
-                *pixel.1 += if gradient > settings.rock_threshold { color_for_environements[1][1] * (1.5 - gradient + 1.0) / 2.0} else {color_for_environements[2][1]};
-                *pixel.2 += if gradient > settings.rock_threshold { color_for_environements[1][2] * (1.5 - gradient + 1.0) / 2.0} else {color_for_environements[2][2]};
+            (line, slope_total)
+        })
+    };
 
+    let mut slope_total: f32 = 0.0;
+    let mut number_of_points: f32 = 0.0;
 
+    for (x, (line, line_slope_total)) in lines.iter().enumerate() {
+        for (y, color) in line.iter().enumerate() {
+            if let Some(pixel) = output.get_mut_pixel(y, x) {
+                *pixel.0 += color[0];
+                *pixel.1 += color[1];
+                *pixel.2 += color[2];
+                number_of_points += 1.0;
             }
-
-
         }
+        slope_total += line_slope_total;
     }
 
-    println!("gradient mean: {}", gradient_total / number_of_points);
+    println!("mean slope: {} degrees", slope_total / number_of_points);
 }
 
 
@@ -122,7 +249,8 @@ fn get_exposition(slope: f32, sun_angle: f32) -> f32 {
     CBF.sin().abs()
 }
 
-fn get_pos_with_direction(i: usize, j: usize, direction: u8, width: usize) -> [usize; 2] {
+// i: position le long de la direction de balayage, j: indice de la ligne balayee
+fn get_pos_with_direction(i: usize, j: usize, direction: u8, width: usize, height: usize) -> [usize; 2] {
     match direction {
         0 => {
             [i, j]
@@ -134,116 +262,391 @@ fn get_pos_with_direction(i: usize, j: usize, direction: u8, width: usize) -> [u
             [width - i - 1, j]
         }
         _ => {
-            [j, width - i - 1]
+            [j, height - i - 1]
         }
     }
 }
 
-fn checked_get_pos_with_direction(i: i32, j: i32, direction: u8, width: usize) -> Option<[usize; 2]> {
-    if i >= width as i32 || i < 0 || j >= width as i32 || j < 0{
+// longueur d'une ligne balayee et nombre de lignes
+fn get_sweep_size(direction: u8, width: usize, height: usize) -> (usize, usize) {
+    match direction {
+        0 | 2 => (width, height),
+        _ => (height, width)
+    }
+}
+
+fn checked_get_pos_with_direction(i: i32, j: i32, direction: u8, width: usize, height: usize) -> Option<[usize; 2]> {
+    let (length, number_of_lines) = get_sweep_size(direction, width, height);
+
+    if i >= length as i32 || i < 0 || j >= number_of_lines as i32 || j < 0{
         None
     } else {
-        Some(get_pos_with_direction(i as usize, j as usize, direction, width))
+        Some(get_pos_with_direction(i as usize, j as usize, direction, width, height))
     }
 
 
 }
 
 //67 104 156
-pub fn add_shadow(output: &mut ColorMapArray, heightmap: &Arr2d<f32>, width: usize, direction: u8, angle: f32, ref_height: f32, ambient_color: &[f32;3], sun_color: &[f32;3]) {
+// cell_size: largeur d'un point en unites de hauteur
+// renvoie le masque des ombres: 1 eclaire, 0 dans l'ombre
+#[allow(clippy::too_many_arguments)]
+pub fn add_shadow(output: &mut ColorMapArray, heightmap: &Arr2d<f32>, direction: u8, angle: f32, cell_size: f32, ambient_color: &[f32;3], sun_color: &[f32;3],
+    tileable: bool, threads: usize) -> Arr2d<f32> {
+
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
+    let (length, number_of_lines) = get_sweep_size(direction, width, height);
+
+    let coef = ((angle  % HALF_PI).tan()).abs() * cell_size;
+
+    // les lignes balayees sont independantes: elles sont calculees en parallele, puis recopiees dans output
+    let lines: Vec<Vec<([f32; 3], f32)>> = {
+        let output: &ColorMapArray = output;
+        parallel_map(number_of_lines, threads, |j| {
+            let mut current_max: f32 = 0.0;
+            let mut pos: [usize; 2];
+
+            let mut pixel: [f32; 3];
+            let mut exposition: f32;
+            let mut local_height: f32;
+
+            let mut exposition_sum: f32;
+            let mut n: u8;
+
+            if tileable {
+                // premier tour sans ombrer: les ombres projetees depuis l'autre cote du terrain sont prises en compte
+                for i in 0..(length - 1) {
+                    pos = get_pos_with_direction(i, j, direction, width, height);
+                    current_max = f32::max(current_max, *heightmap.get(pos[0], pos[1]).unwrap()) - coef;
+                }
+            }
 
-    let mut current_max_per_line: Vec<f32> = vec![0.0_f32;width];
-    let mut pos: [usize; 2];
+            let mut line: Vec<([f32; 3], f32)> = Vec::with_capacity(length);
 
-    let coef = ((angle  % HALF_PI).tan()).abs() * ref_height / width as f32;
+            for i in 0..length {
 
-    let mut pixel;
-    let mut exposition: f32;
-    let mut local_height: f32;
+                exposition_sum = 0.0;
+                n = 0;
 
-    let mut exposition_sum: f32;
-    let mut n: u8;
+                pos = get_pos_with_direction(i, j, direction, width, height);
 
+                let (r, g, b) = output.get_pixel(pos[1], pos[0]).unwrap();
+                pixel = [*r, *g, *b];
+                local_height = *heightmap.get(pos[0], pos[1]).unwrap(); 
 
-    for i in 0..width {
-        for j in 0..width {
+                exposition = 1.0;
+                let lit = local_height >= current_max;
 
-            exposition_sum = 0.0;
-            n = 0;
+                if lit {
+                    current_max = local_height;
 
-            pos = get_pos_with_direction(i, j, direction, width);
+                    for di in [-1, 1] {
+                        let neighbour_i = if tileable { (i as i32 + di).rem_euclid(length as i32 - 1) } else { i as i32 + di };
 
-            pixel = output.get_mut_pixel(pos[1], pos[0]).unwrap();
-            local_height = *heightmap.get(pos[0], pos[1]).unwrap(); 
+                        if let Some(pos) = checked_get_pos_with_direction(neighbour_i, j as i32, direction, width, height) {
+                            if let Some(height) = heightmap.get(pos[0], pos[1]) {
+                                exposition_sum += get_exposition((height - local_height) * di as f32, angle);
+                                n += 1;
+                            }
+                        }
+                    }
+                    exposition = 1.0 - (1.0 - exposition_sum / n as f32) * 0.6;
 
-            exposition = 1.0;
+                    for channel in 0..3 {
+                        pixel[channel] = sun_color[channel] * 0.05 + pixel[channel] * 0.95;
+                    }
 
-            if local_height >= current_max_per_line[j] {
-                current_max_per_line[j] = local_height;
 
-                for di in [-1, 1] {
-                    if let Some(pos) = checked_get_pos_with_direction(i as i32 + di, j as i32, direction, width) {
-                        if let Some(height) = heightmap.get(pos[0], pos[1]) {
-                            exposition_sum += get_exposition((height - local_height) * di as f32, angle);
-                            n += 1;
-                        }
+                } else {
+                    exposition -= 0.60;
+                    for channel in 0..3 {
+                        pixel[channel] = ambient_color[channel] * 0.1 + pixel[channel] * 0.9;
                     }
                 }
-                exposition = 1.0 - (1.0 - exposition_sum / n as f32) * 0.6;
-
-                *pixel.0 = sun_color[0] * 0.05 + *pixel.0 * 0.95;
-                *pixel.1 = sun_color[1] * 0.05 + *pixel.1 * 0.95;
-                *pixel.2 = sun_color[2] * 0.05 + *pixel.2 * 0.95;
 
+                for value in pixel.iter_mut() {
+                    *value -= 1.0 - exposition;
+                }
 
-            } else {
-                exposition -= 0.60;
-                *pixel.0 = ambient_color[0] * 0.1 + *pixel.0 * 0.9;
-                *pixel.1 = ambient_color[1] * 0.1 + *pixel.1 * 0.9;
-                *pixel.2 = ambient_color[2] * 0.1 + *pixel.2 * 0.9;
-            }
+                if i == 0 && !tileable {
+                    pixel[0] += 0.5;
+                }
 
-            *pixel.0 -= 1.0 - exposition;
-            *pixel.1 -= 1.0 - exposition;
-            *pixel.2 -= 1.0 - exposition;
+                line.push((pixel, if lit { 1.0 } else { 0.0 }));
 
-            if i == 0 {
-                *pixel.0 += 0.5;
+                current_max -= coef;
             }
 
-            current_max_per_line[j] -= coef;
-
-
-
-
-
-
-
-
+            line
+        })
+    };
+
+    let mut mask: Arr2d<f32> = Arr2d::zeros(width, height);
+    for (j, line) in lines.iter().enumerate() {
+        for (i, (color, lit)) in line.iter().enumerate() {
+            let pos = get_pos_with_direction(i, j, direction, width, height);
+            let pixel = output.get_mut_pixel(pos[1], pos[0]).unwrap();
+            *pixel.0 = color[0];
+            *pixel.1 = color[1];
+            *pixel.2 = color[2];
+            *mask.get_mut(pos[0], pos[1]).unwrap() = *lit;
         }
     }
 
-
+    mask
 }
 
 
+// hauteur interpolee au point (x, y), None hors d'un terrain qui ne se repete pas
+fn sample_height(heightmap: &Arr2d<f32>, x: f32, y: f32, tileable: bool) -> Option<f32> {
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
 
+    let (x0, y0) = if tileable {
+        (x.floor() as i32, y.floor() as i32)
+    } else if x < 0.0 || y < 0.0 || x > (width - 1) as f32 || y > (height - 1) as f32 {
+        return None
+    } else {
+        (i32::min(x as i32, width as i32 - 2), i32::min(y as i32, height as i32 - 2))
+    };
+    let corner = |dx: i32, dy: i32| if tileable {
+        *heightmap.get_wrapped(x0 + dx, y0 + dy)
+    } else {
+        *heightmap.get((x0 + dx) as usize, (y0 + dy) as usize).unwrap()
+    };
+    Some(bilinear_interpolation(x - x0 as f32, y - y0 as f32, corner(0, 0), corner(0, 1), corner(1, 0), corner(1, 1)))
+}
 
+// fraction visible d'un disque coupe par une droite horizontale a offset rayons au dessus de son centre
+fn visible_disc_fraction(offset: f32) -> f32 {
+    let offset = offset.clamp(-1.0, 1.0);
+    (offset.acos() - offset * (1.0 - offset * offset).sqrt()) / PI
+}
 
+// part visible du disque solaire d'azimut azimuth (radians, 0 vers le haut de la heightmap, dans le sens des aiguilles d'une montre)
+// et de hauteur angle, de rayon angulaire sun_radius: les lignes perpendiculaires a l'axe le plus proche de l'azimut sont balayees
+// depuis le cote du soleil, et chaque point recoit de la ligne precedente (interpolee entre ses 2 points les plus proches) la hauteur
+// de l'ombre projetee par les points deja balayes, pour SUN_DISC_SAMPLES pentes du bas au haut du disque: la hauteur angulaire
+// de l'horizon est interpolee entre la derniere pente cachee et la premiere visible, et la part du disque au dessus de l'horizon
+// donne la penombre. le cout est de SUN_DISC_SAMPLES pentes par point, quels que soient le soleil et la taille du terrain
+fn sun_visibility(heightmap: &Arr2d<f32>, azimuth: f32, angle: f32, sun_radius: f32, cell_size: f32, tileable: bool) -> Arr2d<f32> {
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
+
+    // direction du soleil, decomposee sur l'axe balaye (forward) et le long des lignes (lateral)
+    let direction = (azimuth.sin(), -azimuth.cos());
+    let along_x = direction.0.abs() >= direction.1.abs();
+    let (length, line_length, forward, lateral) = if along_x {
+        (width, height, direction.0, direction.1)
+    } else {
+        (height, width, direction.1, direction.0)
+    };
+    let position = |i: usize, j: usize| if along_x { (i, j) } else { (j, i) };
 
+    // d'une ligne a la suivante, le rayon vers le soleil se decale de shift points le long des lignes et parcourt distance unites
+    let shift = lateral / forward.abs();
+    let distance = (1.0 + shift * shift).sqrt() * cell_size;
 
+    let angles: Vec<f32> = if sun_radius > 0.0 {
+        (0..SUN_DISC_SAMPLES).map(|k| angle - sun_radius + 2.0 * sun_radius * k as f32 / (SUN_DISC_SAMPLES - 1) as f32).collect()
+    } else {
+        vec![angle]
+    };
+    // baisse de l'ombre de chaque pente d'une ligne a la suivante
+    let drops: Vec<f32> = angles.iter().map(|angle| angle.min(HALF_PI - 1e-3).tan() * distance).collect();
+
+    // un terrain qui se repete n'a pas de bord ou commencer: il est balaye deux fois, et les ombres projetees
+    // depuis l'autre cote du terrain sont prises en compte au second tour
+    let (period, line_period) = if tileable { (length - 1, line_length - 1) } else { (length, line_length) };
+    let sweeps = if tileable { 2 * period } else { length };
+
+    // hauteur de l'ombre de chaque pente sur la ligne precedente et la ligne en cours, ou du terrain s'il est plus haut
+    let mut previous: Vec<Vec<f32>> = vec![vec![0.0; line_length]; angles.len()];
+    let mut current = previous.clone();
+    let mut margins = vec![0.0; angles.len()];
+    let mut visibility: Arr2d<f32> = Arr2d::zeros(width, height);
+
+    for sweep in 0..sweeps {
+        let i = if forward > 0.0 { period - 1 - sweep % period } else { sweep % period };
+
+        for j in 0..line_period {
+            let (x, y) = position(i, j);
+            let local_height = *heightmap.get(x, y).unwrap();
+
+            // points de la ligne precedente encadrant le rayon, aucun sur la premiere ligne ou si le rayon sort du terrain
+            let ray = j as f32 + shift;
+            let neighbours = if sweep == 0 {
+                None
+            } else if tileable {
+                let j0 = ray.floor();
+                Some(((j0 as i32).rem_euclid(line_period as i32) as usize, (j0 as i32 + 1).rem_euclid(line_period as i32) as usize, ray - j0))
+            } else if ray < 0.0 || ray > (line_length - 1) as f32 {
+                None
+            } else {
+                let j0 = usize::min(ray as usize, line_length - 2);
+                Some((j0, j0 + 1, ray - j0 as f32))
+            };
+
+            // ombre de chaque pente au dessus du point: positive si la pente est cachee
+            for (((drop, previous_line), current_line), margin) in drops.iter().zip(&previous).zip(&mut current).zip(&mut margins) {
+                let shadow = match neighbours {
+                    Some((j0, j1, t)) => previous_line[j0] * (1.0 - t) + previous_line[j1] * t - drop,
+                    None => f32::NEG_INFINITY
+                };
+                current_line[j] = f32::max(local_height, shadow);
+                *margin = shadow - local_height;
+            }
 
+            *visibility.get_mut(x, y).unwrap() = match margins.iter().position(|margin| *margin < 0.0) {
+                None => 0.0,
+                Some(0) => 1.0,
+                Some(k) => {
+                    // horizon entre les pentes k - 1 (cachee) et k (visible)
+                    let horizon = angles[k - 1] + (angles[k] - angles[k - 1]) * margins[k - 1] / (margins[k - 1] - margins[k]);
+                    visible_disc_fraction((horizon - angle) / sun_radius)
+                }
+            };
+        }
 
+        std::mem::swap(&mut previous, &mut current);
+    }
 
+    // la derniere ligne et la derniere colonne d'un terrain qui se repete sont les premieres
+    if tileable {
+        for y in 0..height {
+            for x in 0..width {
+                if x == width - 1 || y == height - 1 {
+                    *visibility.get_mut(x, y).unwrap() = *visibility.get(x % (width - 1), y % (height - 1)).unwrap();
+                }
+            }
+        }
+    }
 
+    visibility
+}
 
+// ombres d'un soleil d'azimut azimuth et de hauteur angle, avec la penombre de son disque de rayon angulaire sun_radius (voir sun_visibility)
+// les hauteurs sont comparees aux distances comme dans add_shadow: un point mesure cell_size unites de hauteur
+// renvoie le masque des ombres, de 1 (soleil entierement visible) a 0
+#[allow(clippy::too_many_arguments)]
+pub fn add_azimuth_shadow(output: &mut ColorMapArray, heightmap: &Arr2d<f32>, azimuth: f32, angle: f32, sun_radius: f32, cell_size: f32,
+    ambient_color: &[f32;3], sun_color: &[f32;3], tileable: bool, threads: usize) -> Arr2d<f32> {
+
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
+
+    let direction = (azimuth.sin(), -azimuth.cos());
+    let sun_visibility = sun_visibility(heightmap, azimuth, angle, sun_radius, cell_size, tileable);
+
+    let sample = |x: f32, y: f32| sample_height(heightmap, x, y, tileable);
+
+    // les colonnes sont independantes: elles sont calculees en parallele, puis recopiees dans output
+    let columns: Vec<Vec<([f32; 3], f32)>> = {
+        let output: &ColorMapArray = output;
+        parallel_map(width, threads, |x| {
+            (0..height).map(|y| {
+                let local_height = *heightmap.get(x, y).unwrap();
+                let visibility = *sun_visibility.get(x, y).unwrap();
+
+                // exposition du point eclaire: pente vers les deux voisins le long du rayon, comme dans add_shadow
+                let mut exposition_sum = 0.0;
+                let mut n = 0;
+                for di in [-1.0, 1.0] {
+                    if let Some(neighbour_height) = sample(x as f32 - direction.0 * di, y as f32 - direction.1 * di) {
+                        exposition_sum += get_exposition((neighbour_height - local_height) * di, angle);
+                        n += 1;
+                    }
+                }
+                // dans un coin de la heightmap, les deux voisins peuvent etre dehors: le point est eclaire comme un point plat
+                let mean_exposition = if n > 0 { exposition_sum / n as f32 } else { get_exposition(0.0, angle) };
+                let exposition = 1.0 - (1.0 - mean_exposition) * 0.6;
+
+                let (r, g, b) = output.get_pixel(y, x).unwrap();
+                let mut pixel = [*r, *g, *b];
+                for channel in 0..3 {
+                    let lit = sun_color[channel] * 0.05 + pixel[channel] * 0.95 - (1.0 - exposition);
+                    let shadowed = ambient_color[channel] * 0.1 + pixel[channel] * 0.9 - 0.6;
+                    pixel[channel] = lit * visibility + shadowed * (1.0 - visibility);
+                }
 
+                (pixel, visibility)
+            }).collect()
+        })
+    };
+
+    let mut mask: Arr2d<f32> = Arr2d::zeros(width, height);
+    for (x, column) in columns.iter().enumerate() {
+        for (y, (color, visibility)) in column.iter().enumerate() {
+            let pixel = output.get_mut_pixel(y, x).unwrap();
+            *pixel.0 = color[0];
+            *pixel.1 = color[1];
+            *pixel.2 = color[2];
+            *mask.get_mut(x, y).unwrap() = *visibility;
+        }
+    }
 
+    mask
+}
 
 
+// part de la lumiere du ciel recue en chaque point (sky-view factor), entre 0 et 1: dans chacune des rays directions, un rayon
+// de radius points cherche la hauteur angulaire de l'horizon, et la lumiere venant d'au dessus de l'horizon est moyennee sur les directions
+// les hauteurs sont comparees aux distances comme dans add_shadow: un point mesure cell_size unites de hauteur
+pub fn generate_sky_view_map(heightmap: &Arr2d<f32>, rays: u32, radius: f32, cell_size: f32, tileable: bool, threads: usize) -> Arr2d<f32> {
+    println!("computing ambient occlusion...");
+
+    let width = heightmap.get_width();
+    let height = heightmap.get_height();
+
+    let directions: Vec<(f32, f32)> = (0..rays).map(|i| {
+        let angle = 2.0 * PI * (i as f32 + 0.5) / rays as f32;
+        (angle.cos(), angle.sin())
+    }).collect();
+    let steps = radius.ceil() as usize;
+
+    let columns: Vec<Vec<f32>> = parallel_map(width, threads, |x| {
+        (0..height).map(|y| {
+            let local_height = *heightmap.get(x, y).unwrap();
+
+            let sky: f32 = directions.iter().map(|direction| {
+                let mut horizon_tan: f32 = 0.0;
+                for step in 1..=steps {
+                    let distance = f32::min(step as f32, radius);
+                    match sample_height(heightmap, x as f32 + direction.0 * distance, y as f32 + direction.1 * distance, tileable) {
+                        Some(ray_height) => horizon_tan = f32::max(horizon_tan, (ray_height - local_height) / (distance * cell_size)),
+                        None => break
+                    }
+                }
+                // lumiere du ciel au dessus de l'horizon recue par un sol horizontal: cos^2 de la hauteur angulaire de l'horizon
+                1.0 / (1.0 + horizon_tan * horizon_tan)
+            }).sum();
+
+            sky / rays as f32
+        }).collect()
+    });
+
+    let mut sky_view: Arr2d<f32> = Arr2d::zeros(width, height);
+    for (x, column) in columns.into_iter().enumerate() {
+        for (y, value) in column.into_iter().enumerate() {
+            *sky_view.get_mut(x, y).unwrap() = value;
+        }
+    }
 
+    sky_view
+}
 
-
-
-
-
+// assombrit output la ou le ciel est cache: comme dans les ombres (add_shadow), la lumiere perdue est remplacee par une
+// faible lumiere de couleur ambient_color
+pub fn add_ambient_occlusion(output: &mut ColorMapArray, sky_view: &Arr2d<f32>, strength: f32, ambient_color: &[f32;3]) {
+    for x in 0..sky_view.get_width() {
+        for y in 0..sky_view.get_height() {
+            let occlusion = strength * (1.0 - *sky_view.get(x, y).unwrap());
+            if let Some(pixel) = output.get_mut_pixel(y, x) {
+                *pixel.0 = *pixel.0 * (1.0 - occlusion) + ambient_color[0] * AMBIENT_OCCLUSION_FILL * occlusion;
+                *pixel.1 = *pixel.1 * (1.0 - occlusion) + ambient_color[1] * AMBIENT_OCCLUSION_FILL * occlusion;
+                *pixel.2 = *pixel.2 * (1.0 - occlusion) + ambient_color[2] * AMBIENT_OCCLUSION_FILL * occlusion;
+            }
+        }
+    }
+}
@@ -7,13 +7,11 @@ use image::{DynamicImage, GenericImageView};
 use rand_core::RngCore;
 use rand_pcg::Mcg128Xsl64;
 
-use crate::settings::GenerationOptions;
-
 pub const TWO_POW_32_MINUS_1: u32 = 4294967295;
 
 pub const TWO_POW_15_F32: f32 = 32768.0;
 
-pub const PI: f32 = 3.141592653589793238462643383279502884197169399375105820974944592307816406286208998628034825342117067982148086513282306647;
+pub const PI: f32 = std::f32::consts::PI;
 pub const HALF_PI: f32 = PI / 2.0;
 
 #[derive(Clone, Copy)]
@@ -23,6 +21,7 @@ pub struct Vec2<T> {
 }
 
 impl Vec2<f64> {
+    #[allow(clippy::result_unit_err)]
     pub fn normalize_ip(&mut self) -> Result<(), ()> {
         let length = self.get_length();
 
@@ -115,6 +114,14 @@ impl ColorMapArray {
         ColorMapArray {r: Arr2d::zeros(width, height), g: Arr2d::zeros(width, height), b: Arr2d::zeros(width, height)}
     }
 
+    pub fn extract(&self, x: usize, y: usize, width: usize, height: usize) -> ColorMapArray {
+        ColorMapArray {
+            r: self.r.extract(x, y, width, height),
+            g: self.g.extract(x, y, width, height),
+            b: self.b.extract(x, y, width, height)
+        }
+    }
+
     pub fn get_width(&self) -> usize {
         self.r.get_width()
     }
@@ -143,13 +150,18 @@ impl<T> Arr2d<T> {
     }
 
     pub fn geti(&self, x: i32, y: i32) -> Option<&T> {
-        let x2: usize;
-        let y2: usize;
-        x2 = x.try_into().ok()?;
-        y2 = y.try_into().ok()?;
+        let x2: usize = x.try_into().ok()?;
+        let y2: usize = y.try_into().ok()?;
         self.get(x2, y2)
     }
 
+    // acces sur un tore: la derniere ligne et la derniere colonne sont confondues avec les premieres
+    pub fn get_wrapped(&self, x: i32, y: i32) -> &T {
+        let x2 = x.rem_euclid(self.width as i32 - 1) as usize;
+        let y2 = y.rem_euclid(self.height as i32 - 1) as usize;
+        &self.vector[y2 * self.width + x2]
+    }
+
     pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
         if x >= self.width || y >= self.height{
             None
@@ -175,6 +187,39 @@ impl<T> Arr2d<T> {
 }
 
 
+impl<T: Clone> Arr2d<T> {
+    // recopie la premiere colonne et la premiere ligne sur les dernieres, pour un tableau qui se repete
+    pub fn wrap_edges(&mut self) {
+        let last_x = self.width - 1;
+        let last_y = self.height - 1;
+
+        for y in 0..self.height {
+            self.vector[y * self.width + last_x] = self.vector[y * self.width].clone();
+        }
+        for x in 0..self.width {
+            self.vector[last_y * self.width + x] = self.vector[x].clone();
+        }
+    }
+
+    // garde le coin superieur gauche de width x height
+    pub fn crop(&self, width: usize, height: usize) -> Arr2d<T> {
+        self.extract(0, 0, width, height)
+    }
+
+    // copie le rectangle de width x height dont le coin superieur gauche est en (x, y)
+    pub fn extract(&self, x: usize, y: usize, width: usize, height: usize) -> Arr2d<T> {
+        assert!(x + width <= self.width && y + height <= self.height);
+
+        let mut v: Vec<T> = Vec::with_capacity(width * height);
+        for j in y..(y + height) {
+            v.extend_from_slice(&self.vector[j * self.width + x..j * self.width + x + width]);
+        }
+
+        Arr2d { vector: v, width, height }
+    }
+}
+
+
 impl Arr2d<f32> {
     pub fn zeros(width: usize, height: usize) -> Arr2d<f32> {
         
@@ -189,23 +234,54 @@ impl Arr2d<f32> {
         Arr2d { vector: v, width, height }
     }
 
-    pub fn from_dynamic_image(img: DynamicImage, max_height: f32, generation_settings: &GenerationOptions) -> Arr2d<f32> {
-
-        let w = 2_usize.pow(generation_settings.template_power_of_two) + 1;
+    // lit le coin superieur gauche de width x height de l'image, qui doit etre au moins aussi grande
+    pub fn from_dynamic_image(img: DynamicImage, max_height: f32, width: usize, height: usize) -> Arr2d<f32> {
 
-        let mut arr = Arr2d::zeros(w, w);
+        let mut arr = Arr2d::zeros(width, height);
         let mut v: &mut f32;
 
 
-        for x in 0..w {
-            for y in 0..w {
-                v = arr.get_mut(x as usize, y as usize).unwrap();
+        for x in 0..width {
+            for y in 0..height {
+                v = arr.get_mut(x, y).unwrap();
                 *v = img.get_pixel(x as u32, y as u32).0[0] as f32 / 255.0 * max_height;
             }
         };
         arr
     }
 
+    // reechantillonne le tableau en width x height par interpolation bilineaire, les bords des deux tableaux coincidant
+    pub fn resample(&self, width: usize, height: usize) -> Arr2d<f32> {
+        let mut arr = Arr2d::zeros(width, height);
+
+        let x_ratio = (self.width - 1) as f64 / usize::max(width - 1, 1) as f64;
+        let y_ratio = (self.height - 1) as f64 / usize::max(height - 1, 1) as f64;
+
+        let mut sx: f64;
+        let mut sy: f64;
+        let mut x0: usize;
+        let mut y0: usize;
+
+        for x in 0..width {
+            for y in 0..height {
+                sx = x as f64 * x_ratio;
+                sy = y as f64 * y_ratio;
+
+                x0 = usize::min(sx as usize, self.width - 1);
+                y0 = usize::min(sy as usize, self.height - 1);
+
+                let x1 = usize::min(x0 + 1, self.width - 1);
+                let y1 = usize::min(y0 + 1, self.height - 1);
+
+                *arr.get_mut(x, y).unwrap() = bilinear_interpolation(
+                    (sx - x0 as f64).min(1.0) as f32, (sy - y0 as f64).min(1.0) as f32,
+                    *self.get(x0, y0).unwrap(), *self.get(x0, y1).unwrap(), *self.get(x1, y0).unwrap(), *self.get(x1, y1).unwrap());
+            }
+        }
+
+        arr
+    }
+
 }
 
 impl Arr2d<f64> {
@@ -218,67 +294,106 @@ impl Arr2d<f64> {
 }
 
 
+// vue sur un point sur `scaling` d'un tableau, dans les deux directions
 pub struct ReducedArrayWrapper<'a, T> {
     array: &'a mut Arr2d<T>,
-    n_array: u32, 
-    reduced_n: u32
+    scaling: usize,
+    tileable: bool  // les positions hors du tableau reviennent de l'autre cote
 }
 
 impl<T> ReducedArrayWrapper<'_, T> {
-    pub fn new(array: &mut Arr2d<T>, n_array: u32, reduced_n: u32) -> ReducedArrayWrapper<T> {
+    pub fn new(array: &mut Arr2d<T>, scaling: usize) -> ReducedArrayWrapper<'_, T> {
+
+        assert!(scaling >= 1);
+        assert!(array.get_width() > scaling && array.get_height() > scaling);
 
-        assert!(n_array >= reduced_n);
-        assert_eq!(array.get_width(), array.get_height());
-        assert_eq!(array.get_width(), 2_usize.pow(n_array) + 1);
+        ReducedArrayWrapper { array, scaling, tileable: false }
+    }
+
+    // la derniere ligne et la derniere colonne reduites sont confondues avec les premieres
+    pub fn new_tileable(array: &mut Arr2d<T>, scaling: usize) -> ReducedArrayWrapper<'_, T> {
+        let mut wrapper = ReducedArrayWrapper::new(array, scaling);
+        wrapper.tileable = true;
+        wrapper
+    }
 
-        ReducedArrayWrapper { array, n_array, reduced_n }
+    fn wrap(&self, x: i32, y: i32) -> (i32, i32) {
+        if self.tileable {
+            (x.rem_euclid(self.get_reduced_width() as i32 - 1), y.rem_euclid(self.get_reduced_height() as i32 - 1))
+        } else {
+            (x, y)
+        }
     }
 
     pub fn geti(&self, x: i32, y: i32) -> Option<&T> {
+        let (x, y) = self.wrap(x, y);
         self.array.geti(
-            x * 2_i32.pow(self.n_array - self.reduced_n),
-            y * 2_i32.pow(self.n_array - self.reduced_n))
+            x * self.scaling as i32,
+            y * self.scaling as i32)
     }
 
     pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+        let (x, y) = self.wrap(x as i32, y as i32);
         self.array.get(
-            x * 2_usize.pow(self.n_array - self.reduced_n),
-            y * 2_usize.pow(self.n_array - self.reduced_n))
+            x as usize * self.scaling,
+            y as usize * self.scaling)
     }
 
     pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        let (x, y) = self.wrap(x as i32, y as i32);
         self.array.get_mut(
-            x * 2_usize.pow(self.n_array - self.reduced_n),
-            y * 2_usize.pow(self.n_array - self.reduced_n))
+            x as usize * self.scaling,
+            y as usize * self.scaling)
     }
 
-    pub fn get_reduced_n(&self) -> u32 {
-        self.reduced_n
+    pub fn is_tileable(&self) -> bool {
+        self.tileable
     }
 
     pub fn get_scaling(&self) -> usize {
-        2_usize.pow(self.n_array - self.reduced_n)
+        self.scaling
     }
 
     pub fn convert(&self, x: usize) -> usize {
-        x * 2_usize.pow(self.n_array - self.reduced_n)
+        x * self.scaling
     }
 
     pub fn get_reduced_width(&self) -> usize {
-        2_usize.pow(self.reduced_n) + 1
+        (self.array.get_width() - 1) / self.scaling + 1
+    }
+
+    pub fn get_reduced_height(&self) -> usize {
+        (self.array.get_height() - 1) / self.scaling + 1
     }
 
     pub fn is_position_valid(&self, x: i32, y: i32) -> bool {
-        let width = self.get_reduced_width() as i32;
-        0 <= x && x < width && 0 <= y && y < width
+        0 <= x && x < self.get_reduced_width() as i32 && 0 <= y && y < self.get_reduced_height() as i32
     }
 
 }
 
+impl<T: Clone> ReducedArrayWrapper<'_, T> {
+    // en mode tileable, les ecritures se font sur la premiere ligne / colonne: les recopie sur les dernieres
+    pub fn wrap_edges(&mut self) {
+        let last_x = self.get_reduced_width() - 1;
+        let last_y = self.get_reduced_height() - 1;
+        let s = self.scaling;
+
+        for y in 0..=last_y {
+            let v = self.array.get(0, y * s).unwrap().clone();
+            *self.array.get_mut(last_x * s, y * s).unwrap() = v;
+        }
+        for x in 0..=last_x {
+            let v = self.array.get(x * s, 0).unwrap().clone();
+            *self.array.get_mut(x * s, last_y * s).unwrap() = v;
+        }
+    }
+}
+
 
 
 pub fn linear_interpolation(t: f32, v0: f32, v1: f32) -> f32 {
-    assert!(0.0 <= t && t <= 1.0);
+    assert!((0.0..=1.0).contains(&t));
     (v1 - v0) * t + v0
 }
 
@@ -305,3 +420,59 @@ pub fn rand(rng: &mut Mcg128Xsl64) -> f32 {
 }
 
 
+// calcule f(0), ..., f(n - 1) sur `threads` threads, chaque thread traitant un bloc d'indices consecutifs
+// le resultat est dans l'ordre des indices: il ne depend pas du nombre de threads tant que f(i) n'en depend pas
+pub fn parallel_map<T: Send, F: Fn(usize) -> T + Sync>(n: usize, threads: usize, f: F) -> Vec<T> {
+    if threads <= 1 || n <= 1 {
+        return (0..n).map(f).collect()
+    }
+
+    let block = n.div_ceil(threads);
+    let f = &f;
+
+    std::thread::scope(|s| {
+        let handles: Vec<_> = (0..n).step_by(block)
+            .map(|start| s.spawn(move || (start..usize::min(start + block, n)).map(f).collect::<Vec<T>>()))
+            .collect();
+
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    })
+}
+
+
+// identifiants des generateurs derives de la seed, passes en premiere cle a derive_rng
+pub const CHUNK_CORNER_STREAM: i64 = 1;
+pub const CHUNK_HORIZONTAL_EDGE_STREAM: i64 = 2;
+pub const CHUNK_VERTICAL_EDGE_STREAM: i64 = 3;
+pub const CHUNK_HEIGHTMAP_STREAM: i64 = 4;
+pub const CHUNK_TEXTURE_STREAM: i64 = 5;
+pub const LINE_STREAM: i64 = 6;  // une ligne d'une etape parallelisee, voir line_rng
+pub const EROSION_BATCH_STREAM: i64 = 7;  // un lot de gouttes de l'erosion parallele
+pub const HARDNESS_STREAM: i64 = 8;  // bruit de durete de la roche
+pub const MATERIAL_STREAM: i64 = 9;  // bruit d'un materiau de la texture
+pub const CHUNK_EROSION_STREAM: i64 = 10;  // gouttes d'un chunk a un niveau de l'erosion
+
+// generateur independant pour chaque combinaison (seed, keys): le resultat ne depend pas de l'ordre dans lequel
+// les generateurs sont crees, contrairement a des tirages successifs sur un meme generateur
+pub fn derive_rng(seed: u64, keys: &[i64]) -> Mcg128Xsl64 {
+    let mut state = splitmix64(seed);
+    for key in keys {
+        state = splitmix64(state ^ *key as u64);
+    }
+
+    let low = splitmix64(state);
+    let high = splitmix64(low);
+    Mcg128Xsl64::new(((high as u128) << 64) | low as u128)
+}
+
+// generateur de la ligne `line` d'une etape parallelisee, base etant tire une fois par etape sur le generateur principal
+pub fn line_rng(base: u64, line: usize) -> Mcg128Xsl64 {
+    derive_rng(base, &[LINE_STREAM, line as i64])
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
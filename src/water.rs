@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+
+use crate::{utils::Arr2d, settings::WaterOptions, rivers::{Grid, NEIGHBOURS, fill_depressions}};
+
+
+// mer et lacs, cartes de meme taille que la heightmap
+pub struct WaterMaps {
+    // 1 sous l'eau, 0 ailleurs
+    pub mask: Arr2d<f32>,
+    // hauteur d'eau au dessus du sol
+    pub depth: Arr2d<f32>,
+    // plage: 1 sur la rive, decroit avec la distance et la hauteur au dessus de l'eau, 0 au dela et sous l'eau
+    pub shore: Arr2d<f32>
+}
+
+impl WaterMaps {
+    pub fn named_maps(&self) -> Vec<(&'static str, &Arr2d<f32>)> {
+        vec![("watermask.png", &self.mask), ("waterdepthmap.png", &self.depth), ("shoremap.png", &self.shore)]
+    }
+
+    pub fn extract(&self, x: usize, y: usize, width: usize, height: usize) -> WaterMaps {
+        WaterMaps {
+            mask: self.mask.extract(x, y, width, height),
+            depth: self.depth.extract(x, y, width, height),
+            shore: self.shore.extract(x, y, width, height)
+        }
+    }
+}
+
+
+// remplit de mer les points sous sea_level, et de lacs les cuvettes (priority-flood sans pente) d'au moins min_lake_depth
+// de profondeur si generate_lakes est active; lakes: false pour ne calculer que la mer, qui ne depend que de la hauteur locale
+pub fn generate_water(heightmap: &Arr2d<f32>, tileable: bool, lakes: bool, water_settings: &WaterOptions) -> WaterMaps {
+    println!("filling seas and lakes...");
+
+    let grid = Grid::new(heightmap, tileable);
+    let n = grid.len();
+    let heights = grid.heights(heightmap);
+
+    // hauteur de la surface de l'eau, NEG_INFINITY hors de l'eau
+    let mut surface = vec![f64::NEG_INFINITY; n];
+
+    if lakes && water_settings.generate_lakes {
+        let filled = fill_depressions(&heights, &grid, 0.0);
+
+        // chaque lac est un ensemble connexe de points remplis, garde s'il est assez profond en un point
+        let mut lake_id = vec![usize::MAX; n];
+        let mut number_of_lakes = 0;
+
+        for start in 0..n {
+            if filled[start] <= heights[start] || lake_id[start] != usize::MAX {
+                continue
+            }
+
+            let mut lake = vec![start];
+            let mut queue = VecDeque::from([start]);
+            lake_id[start] = start;
+            let mut max_depth: f64 = 0.0;
+
+            while let Some(index) = queue.pop_front() {
+                max_depth = f64::max(max_depth, filled[index] - heights[index]);
+                for direction in NEIGHBOURS {
+                    if let Some(neighbour) = grid.neighbour(index, direction) {
+                        if filled[neighbour] > heights[neighbour] && lake_id[neighbour] == usize::MAX {
+                            lake_id[neighbour] = start;
+                            lake.push(neighbour);
+                            queue.push_back(neighbour);
+                        }
+                    }
+                }
+            }
+
+            if max_depth >= water_settings.min_lake_depth as f64 {
+                number_of_lakes += 1;
+                for index in lake {
+                    surface[index] = filled[index];
+                }
+            }
+        }
+
+        println!("{} lakes found.", number_of_lakes);
+    }
+
+    if let Some(sea_level) = water_settings.sea_level {
+        for index in 0..n {
+            if heights[index] < sea_level as f64 {
+                surface[index] = f64::max(surface[index], sea_level as f64);
+            }
+        }
+    }
+
+    // plage: parcours en largeur depuis l'eau jusqu'a beach_width points, en gardant la surface de l'eau la plus proche
+    let beach_width = water_settings.beach_width;
+    let mut distance = vec![u32::MAX; n];
+    let mut shore_surface = surface.clone();
+    let mut queue: VecDeque<usize> = (0..n).filter(|index| surface[*index] > heights[*index]).collect();
+    for index in queue.iter() {
+        distance[*index] = 0;
+    }
+
+    while let Some(index) = queue.pop_front() {
+        if distance[index] >= beach_width {
+            continue
+        }
+        for direction in NEIGHBOURS {
+            if let Some(neighbour) = grid.neighbour(index, direction) {
+                if distance[neighbour] == u32::MAX {
+                    distance[neighbour] = distance[index] + 1;
+                    shore_surface[neighbour] = shore_surface[index];
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    let (width, height) = (heightmap.get_width(), heightmap.get_height());
+    let mut mask: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut depth: Arr2d<f32> = Arr2d::zeros(width, height);
+    let mut shore: Arr2d<f32> = Arr2d::zeros(width, height);
+
+    for index in 0..n {
+        let (x, y) = (index % grid.width, index / grid.width);
+
+        if distance[index] == 0 {
+            *mask.get_mut(x, y).unwrap() = 1.0;
+            *depth.get_mut(x, y).unwrap() = (surface[index] - heights[index]) as f32;
+        } else if distance[index] != u32::MAX {
+            let above = ((heights[index] - shore_surface[index]) as f32 / water_settings.beach_height).max(0.0);
+            let t = (1.0 - (distance[index] - 1) as f32 / beach_width as f32) * (1.0 - above).max(0.0);
+            *shore.get_mut(x, y).unwrap() = t;
+        }
+    }
+
+    if tileable {
+        mask.wrap_edges();
+        depth.wrap_edges();
+        shore.wrap_edges();
+    }
+
+    WaterMaps { mask, depth, shore }
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{small_settings, heightmap_pipeline};
+use mapgeneration::Settings;
+
+
+// les chunks (0, 0) et (1, 0) partagent leur bord commun, et chaque chunk est le meme quel que soit l'ordre de generation
+fn check_chunk_borders(settings: &Settings) {
+    let pipeline = heightmap_pipeline(settings);
+    let left = pipeline.chunk_heightmap(0, 0).unwrap();
+    let right = pipeline.chunk_heightmap(1, 0).unwrap();
+
+    let (w, h) = (left.get_width(), left.get_height());
+    for y in 0..h {
+        assert_eq!(left.get(w - 1, y), right.get(0, y), "border point {} differs", y);
+    }
+
+    let pipeline = heightmap_pipeline(settings);
+    assert_eq!(pipeline.chunk_heightmap(1, 0).unwrap().get_vec(), right.get_vec());
+    assert_eq!(pipeline.chunk_heightmap(0, 0).unwrap().get_vec(), left.get_vec());
+}
+
+#[test]
+fn diamond_square_chunks_match() {
+    check_chunk_borders(&small_settings(&[]));
+}
+
+#[test]
+fn noise_chunks_match() {
+    check_chunk_borders(&small_settings(&[("heightmap_algorithm", "fbm")]));
+}
+
+#[test]
+fn chunks_match_with_erosion_levels() {
+    let mut settings = small_settings(&[]);
+    settings.erosion.erosion_level_iterations = vec![1000, 1000];
+    check_chunk_borders(&settings);
+}
+
+#[test]
+fn chunks_match_with_scaled_erosion_radius() {
+    // rayons des gouttes de 3, 6 puis 12 points du niveau
+    let mut settings = small_settings(&[("erosion_radius_scaling", "2.0")]);
+    settings.erosion.erosion_level_iterations = vec![1000, 1000, 1000];
+    check_chunk_borders(&settings);
+}
+
+#[test]
+fn pipe_eroded_chunks_match() {
+    check_chunk_borders(&small_settings(&[("hydraulic_erosion_model", "pipes")]));
+}
+
+#[test]
+fn chunks_are_recomputed_identically_after_eviction() {
+    let settings = small_settings(&[]);
+    let expected = heightmap_pipeline(&settings).chunk_heightmap(2, -1).unwrap();
+
+    let pipeline = heightmap_pipeline(&settings).with_chunk_cache_capacity(1);
+    for (cx, cy) in [(0, 0), (3, 3), (2, -1)] {
+        pipeline.chunk_heightmap(cx, cy).unwrap();
+    }
+    assert_eq!(pipeline.chunk_heightmap(2, -1).unwrap().get_vec(), expected.get_vec());
+}
@@ -0,0 +1,26 @@
+#![allow(dead_code)]
+
+use mapgeneration::{Arr2d, Settings, TerrainPipeline};
+
+
+// parametres du depot (Settings.toml) sur un petit terrain, avec peu d'erosion, puis les valeurs de overrides
+pub fn small_settings(overrides: &[(&str, &str)]) -> Settings {
+    let mut all: Vec<(String, String)> = [("terrain_power_of_two", "6"), ("mesh_power_of_two", "4"), ("template_power_of_two", "2"),
+        ("number_of_erosion_iterations", "2000"), ("thermal_erosion_iterations", "5"), ("pipe_iterations", "20")]
+        .iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+    all.extend(overrides.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+
+    Settings::load("Settings.toml", &all).unwrap()
+}
+
+
+// pipeline sans ciel ni texture, avec une template plate
+pub fn heightmap_pipeline(settings: &Settings) -> TerrainPipeline {
+    let w = 2_usize.pow(settings.generation_options.template_power_of_two) + 1;
+    TerrainPipeline::new(settings.generation_options.clone())
+        .with_erosion_options(&settings.erosion)
+        .with_template(Arr2d::init_with_value(w, w, 50.0))
+        .sky_heightmap(false)
+        .sky_texture(false)
+        .terrain_texture(false)
+}
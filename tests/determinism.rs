@@ -0,0 +1,61 @@
+mod common;
+
+use common::{small_settings, heightmap_pipeline};
+use mapgeneration::TerrainOutput;
+
+
+// terrain complet (rivieres, lacs, biomes et texture compris) genere avec threads threads
+fn terrain(overrides: &[(&str, &str)], threads: usize) -> TerrainOutput {
+    let threads = threads.to_string();
+    let mut all = vec![("threads", threads.as_str()), ("generate_rivers", "true"), ("generate_lakes", "true"), ("generate_biomes", "true"),
+        ("ambient_occlusion_rays", "8")];
+    all.extend_from_slice(overrides);
+
+    let settings = small_settings(&all);
+    heightmap_pipeline(&settings)
+        .with_river_options(&settings.rivers)
+        .with_water_options(&settings.water)
+        .with_biome_options(&settings.biomes)
+        .with_materials(&settings.materials)
+        .terrain_texture(true)
+        .run_terrain(&[0.4, 0.5, 0.6], &[1.0, 0.9, 0.8])
+        .unwrap()
+}
+
+// le resultat ne depend pas du nombre de threads
+fn check_threads(overrides: &[(&str, &str)]) {
+    let single = terrain(overrides, 1);
+    let multi = terrain(overrides, 4);
+
+    assert_eq!(single.heightmap.get_vec(), multi.heightmap.get_vec(), "heightmap");
+    for ((name, single_map), (_, multi_map)) in single.erosion_maps.named_maps().into_iter().zip(multi.erosion_maps.named_maps()) {
+        assert_eq!(single_map.get_vec(), multi_map.get_vec(), "{}", name);
+    }
+    assert_eq!(single.rivers.unwrap().mask.get_vec(), multi.rivers.unwrap().mask.get_vec(), "rivers");
+    assert_eq!(single.water.unwrap().depth.get_vec(), multi.water.unwrap().depth.get_vec(), "water");
+    assert_eq!(single.shadowmask.unwrap().get_vec(), multi.shadowmask.unwrap().get_vec(), "shadows");
+    assert_eq!(single.skyviewmap.unwrap().get_vec(), multi.skyviewmap.unwrap().get_vec(), "ambient occlusion");
+
+    // la colormap est stockee transposee
+    let (single_colormap, multi_colormap) = (single.colormap.unwrap(), multi.colormap.unwrap());
+    for x in 0..single.heightmap.get_width() {
+        for y in 0..single.heightmap.get_height() {
+            assert_eq!(single_colormap.get_pixel(y, x), multi_colormap.get_pixel(y, x), "colormap at ({}, {})", x, y);
+        }
+    }
+}
+
+#[test]
+fn sequential_droplets_do_not_depend_on_threads() {
+    check_threads(&[]);
+}
+
+#[test]
+fn parallel_droplets_do_not_depend_on_threads() {
+    check_threads(&[("parallel_erosion", "true"), ("heightmap_algorithm", "fbm")]);
+}
+
+#[test]
+fn pipes_do_not_depend_on_threads() {
+    check_threads(&[("hydraulic_erosion_model", "pipes"), ("sun_azimuth", "135.0")]);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::small_settings;
+use mapgeneration::{Error, Settings};
+
+
+// champs ("section.champ") refuses par la validation
+fn rejected_fields(settings: &Settings) -> Vec<String> {
+    match settings.validate() {
+        Err(Error::InvalidSettings(violations)) => violations.into_iter().map(|violation| violation.field).collect(),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(()) => vec![]
+    }
+}
+
+#[test]
+fn default_settings_are_valid() {
+    assert_eq!(rejected_fields(&small_settings(&[])), Vec::<String>::new());
+}
+
+#[test]
+fn out_of_range_fields_are_rejected() {
+    for (field, value, rejected) in [
+        ("irregularity", "1.5", "generation_options.irregularity"),
+        ("sun_angle", "90.0", "generation_options.sun_angle"),
+        ("rock_threshold", "-1.0", "generation_options.rock_threshold"),
+        ("cell_size", "0.0", "generation_options.cell_size"),
+        ("mesh_power_of_two", "7", "generation_options.mesh_power_of_two"),
+        ("inertia", "1.0", "erosion.inertia"),
+        ("radius", "0", "erosion.radius"),
+        ("radius", "33", "erosion.radius"),
+        ("talus_angle", "89.5", "erosion.talus_angle"),
+        ("pipe_time_step", "0.0", "erosion.pipe_time_step"),
+        ("river_threshold", "1.5", "rivers.river_threshold"),
+        ("min_lake_depth", "-1.0", "water.min_lake_depth"),
+        ("tpi_radius", "0", "terrain_analysis.tpi_radius")
+    ] {
+        assert_eq!(rejected_fields(&small_settings(&[(field, value)])), vec![rejected.to_string()], "{} = {}", field, value);
+    }
+}
+
+#[test]
+fn all_violations_are_reported() {
+    let fields = rejected_fields(&small_settings(&[("irregularity", "-0.5"), ("gravity", "-1.0"), ("beach_height", "0.0")]));
+    assert_eq!(fields, vec!["generation_options.irregularity", "erosion.gravity", "water.beach_height"]);
+}
+
+#[test]
+fn scaled_erosion_radius_is_bounded() {
+    let mut settings = small_settings(&[("erosion_radius_scaling", "2.0")]);
+    settings.erosion.erosion_level_iterations = vec![1000, 1000, 1000, 1000];
+    // rayons 3, 6, 12 et 24
+    assert_eq!(rejected_fields(&settings), Vec::<String>::new());
+
+    // rayons 3, 6, 12, 24 et 48
+    settings.erosion.erosion_level_iterations.push(1000);
+    assert_eq!(rejected_fields(&settings), vec!["erosion.erosion_radius_scaling"]);
+}
+
+#[test]
+fn material_conditions_are_checked() {
+    let mut settings = small_settings(&[]);
+    let mut material = settings.materials[0].clone();
+    material.opacity = 2.0;
+    material.color = None;
+    settings.materials.push(material);
+
+    let name = &settings.materials[0].name;
+    assert_eq!(rejected_fields(&settings), vec![format!("materials.{}.color", name), format!("materials.{}.opacity", name)]);
+}
+
+#[test]
+fn chunk_settings_are_checked() {
+    for (field, value, rejected) in [
+        ("tileable", "true", "generation_options.tileable"),
+        ("terrain_width", "40", "generation_options.terrain_width")
+    ] {
+        let settings = small_settings(&[(field, value)]);
+        assert_eq!(rejected_fields(&settings), Vec::<String>::new(), "{} = {}", field, value);
+        match settings.generation_options.validate_chunks() {
+            Err(Error::InvalidSettings(violations)) => {
+                let fields: Vec<String> = violations.into_iter().map(|violation| violation.field).collect();
+                assert_eq!(fields, vec![rejected.to_string()], "{} = {}", field, value);
+            }
+            other => panic!("{} = {} accepted for chunks: {:?}", field, value, other.err())
+        }
+    }
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::collections::VecDeque;
+
+use common::{small_settings, heightmap_pipeline};
+use mapgeneration::{Arr2d, WaterOptions, water::generate_water};
+
+
+// ecart de hauteur tolere entre la surface de l'eau, calculee en f64, et la hauteur plus la profondeur en f32
+const SURFACE_TOLERANCE: f32 = 1e-3;
+
+
+fn water_options(sea_level: Option<f32>, generate_lakes: bool) -> WaterOptions {
+    WaterOptions { sea_level, generate_lakes, min_lake_depth: 0.0, ..WaterOptions::default() }
+}
+
+// nombre de points d'ou l'eau ne peut pas rejoindre le bord sans remonter (cuvettes)
+fn undrained_points(surface: &Arr2d<f32>) -> usize {
+    let (width, height) = (surface.get_width(), surface.get_height());
+    let mut drained = vec![false; width * height];
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+    for y in 0..height {
+        for x in 0..width {
+            if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                drained[y * width + x] = true;
+                queue.push_back((x, y));
+            }
+        }
+    }
+
+    // en remontant l'ecoulement: l'eau d'un voisin au moins aussi haut s'ecoule vers le point
+    while let Some((x, y)) = queue.pop_front() {
+        let level = *surface.get(x, y).unwrap();
+        for (dx, dy) in [(1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+            if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                continue
+            }
+            let (nx, ny) = (nx as usize, ny as usize);
+            if !drained[ny * width + nx] && *surface.get(nx, ny).unwrap() >= level - SURFACE_TOLERANCE {
+                drained[ny * width + nx] = true;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    drained.iter().filter(|drained| !**drained).count()
+}
+
+#[test]
+fn filled_lakes_leave_no_pits() {
+    let settings = small_settings(&[]);
+    let heightmap = heightmap_pipeline(&settings).run_terrain(&[0.5; 3], &[1.0; 3]).unwrap().heightmap;
+    assert!(undrained_points(&heightmap) > 0, "the eroded terrain has no depression to fill");
+
+    let water = generate_water(&heightmap, false, true, &water_options(None, true));
+    let mut surface = heightmap.clone();
+    for (height, depth) in surface.get_mut_vec().iter_mut().zip(water.depth.get_vec()) {
+        assert!(*depth >= 0.0);
+        *height += depth;
+    }
+
+    assert_eq!(undrained_points(&surface), 0);
+}
+
+// cuvette de size x size: bord a 10, interieur a 5 et centre a 2
+fn bowl(size: usize) -> Arr2d<f32> {
+    let mut heightmap: Arr2d<f32> = Arr2d::init_with_value(size, size, 5.0);
+    for i in 0..size {
+        for (x, y) in [(i, 0), (i, size - 1), (0, i), (size - 1, i)] {
+            *heightmap.get_mut(x, y).unwrap() = 10.0;
+        }
+    }
+    *heightmap.get_mut(size / 2, size / 2).unwrap() = 2.0;
+    heightmap
+}
+
+#[test]
+fn lakes_fill_depressions_up_to_their_rim() {
+    let heightmap = bowl(9);
+    let water = generate_water(&heightmap, false, true, &water_options(None, true));
+
+    for y in 0..9 {
+        for x in 0..9 {
+            let border = x == 0 || y == 0 || x == 8 || y == 8;
+            assert_eq!(*water.mask.get(x, y).unwrap(), if border { 0.0 } else { 1.0 }, "mask at ({}, {})", x, y);
+            let depth = if border { 0.0 } else { 10.0 - heightmap.get(x, y).unwrap() };
+            assert_eq!(*water.depth.get(x, y).unwrap(), depth, "depth at ({}, {})", x, y);
+        }
+    }
+}
+
+#[test]
+fn shallow_or_disabled_lakes_are_not_filled() {
+    let heightmap = bowl(9);
+    let shallow = WaterOptions { min_lake_depth: 9.0, ..water_options(None, true) };
+    let disabled = water_options(None, false);
+
+    // les chunks ne remplissent pas les lacs (lakes = false), meme avec generate_lakes
+    for water in [generate_water(&heightmap, false, true, &shallow), generate_water(&heightmap, false, true, &disabled),
+                  generate_water(&heightmap, false, false, &water_options(None, true))] {
+        assert!(water.mask.get_vec().iter().all(|mask| *mask == 0.0));
+        assert!(water.depth.get_vec().iter().all(|depth| *depth == 0.0));
+    }
+}
+
+#[test]
+fn sea_covers_points_below_sea_level() {
+    // pente de 0 (x = 0) a 8 (x = 8)
+    let mut heightmap: Arr2d<f32> = Arr2d::zeros(9, 5);
+    for y in 0..5 {
+        for x in 0..9 {
+            *heightmap.get_mut(x, y).unwrap() = x as f32;
+        }
+    }
+
+    let options = water_options(Some(3.5), false);
+    let water = generate_water(&heightmap, false, true, &options);
+
+    for y in 0..5 {
+        for x in 0..9 {
+            let under_sea = x <= 3;
+            assert_eq!(*water.mask.get(x, y).unwrap(), if under_sea { 1.0 } else { 0.0 }, "mask at ({}, {})", x, y);
+            assert_eq!(*water.depth.get(x, y).unwrap(), if under_sea { 3.5 - x as f32 } else { 0.0 }, "depth at ({}, {})", x, y);
+
+            // la plage va de la rive a beach_width points, et s'efface en montant
+            let shore = *water.shore.get(x, y).unwrap();
+            if under_sea || x > 3 + options.beach_width as usize {
+                assert_eq!(shore, 0.0, "shore at ({}, {})", x, y);
+            } else if x > 4 {
+                assert!(shore <= *water.shore.get(x - 1, y).unwrap(), "shore at ({}, {})", x, y);
+            }
+        }
+    }
+    assert!(*water.shore.get(4, 2).unwrap() > 0.0);
+}